@@ -0,0 +1,52 @@
+/*
+Extracts a small bundled egraph with two extractors, through the library's prelude,
+and prints their costs, the extracted terms, and the classes where they differ.
+
+    cargo run --example compare_extractors
+
+The egraph's root is either (f (g s) (h s)) or big. As a tree, s is counted twice,
+so bottom-up chooses big, but as a dag s is shared, so faster-greedy-dag chooses f.
+*/
+
+use extraction_gym::prelude::*;
+
+const EGRAPH: &str = include_str!("shared_subterm.json");
+const EXTRACTORS: [&str; 2] = ["bottom-up", "faster-greedy-dag"];
+
+fn main() {
+    let egraph = extraction_gym::schema::parse_egraph(EGRAPH, false).unwrap();
+    let roots = &egraph.root_eclasses;
+
+    let results: Vec<ExtractionResult> = EXTRACTORS
+        .iter()
+        .map(|name| {
+            let mut result = extract_with(name, &egraph, roots).unwrap();
+            result.check(&egraph);
+            result.retain_reachable(&egraph, roots);
+            println!(
+                "{name}: tree cost {}, dag cost {}",
+                result.tree_cost(&egraph, roots),
+                result.dag_cost(&egraph, roots)
+            );
+            for root in roots {
+                println!("    {}", result.to_sexp(&egraph, root));
+            }
+            result
+        })
+        .collect();
+
+    let [a, b] = [&results[0], &results[1]];
+    println!("{} vs {}:", EXTRACTORS[0], EXTRACTORS[1]);
+    let mut classes: Vec<&ClassId> = a.choices.keys().chain(b.choices.keys()).collect();
+    classes.sort();
+    classes.dedup();
+    for cid in classes {
+        let (x, y) = (a.choices.get(cid), b.choices.get(cid));
+        if x != y {
+            let show = |n: Option<&NodeId>| n.map_or("-".to_string(), |n| n.to_string());
+            println!("    class {cid}: {} vs {}", show(x), show(y));
+        }
+    }
+
+    assert!(b.dag_cost(&egraph, roots) < a.dag_cost(&egraph, roots));
+}
@@ -34,6 +34,25 @@ def process(js, extractors):
 
     summaries = {}
 
+    # Costs from different cost models (--cost-model) aren't comparable.
+    mismatched = [
+        name
+        for name, d in by_name.items()
+        if e1 in d and e2 in d and d[e1].get("cost_model") != d[e2].get("cost_model")
+    ]
+    if mismatched:
+        print(f"WARNING: {len(mismatched)} egraphs were extracted under different cost models, e.g. {mismatched[0]}")
+
+    # A timed out or fallen back run's costs are another approach's (see RunStatus).
+    # Results written before status was recorded are ok.
+    not_ok = [
+        name
+        for name, d in by_name.items()
+        if any(d[e].get("status", "ok") != "ok" for e in extractors if e in d)
+    ]
+    if not_ok:
+        print(f"WARNING: {len(not_ok)} egraphs have results that aren't the extractor's own, e.g. {not_ok[0]}")
+
     for name, d in by_name.items():
         try:
             if d[e1]["tree"] !=  d[e2]["tree"]:
@@ -104,6 +123,10 @@ if __name__ == "__main__":
 
     extractors = sorted(set(j["extractor"] for j in js))
 
+    cost_models = set(json.dumps(j.get("cost_model"), sort_keys=True) for j in js)
+    if len(cost_models) > 1:
+        print(f"WARNING: the results were extracted under {len(cost_models)} different cost models")
+
     for i in range(len(extractors)):
         for j in range(i + 1, len(extractors)):
             ex1, ex2 = extractors[i], extractors[j]
@@ -0,0 +1,261 @@
+/*
+Summarizes a directory of results, as written by single extractions with `--out`, so
+that `extraction-gym aggregate output/` is all a benchmark needs, without plot.py.
+
+For each extractor it prints how many egraphs it ran on, its total time, and its mean
+tree and dag costs and time. Then for each pair of extractors, over the egraphs both
+ran on, it prints how often the first's dag cost is lower, the same, or higher, and
+the geometric mean and quantiles of the ratios of their tree costs, dag costs and
+times, first over second. Egraphs where either cost is zero are left out of that
+cost's ratios. Quantiles are the minimum, quartiles and maximum, interpolated
+linearly.
+
+Run with `extraction-gym aggregate [--compare a,b] <dir>...`. `--compare` prints
+only that pair. Every .json file under the directories is read, and those that
+aren't results, such as batch reports, are skipped with a warning. Results that
+aren't ok, see `schema::RunStatus`, and pairs extracted under different cost models
+are warned about, as plot.py does.
+*/
+
+use crate::*;
+use serde::Deserialize;
+use std::path::Path;
+
+// The fields of a `schema::ResultRecord` this needs.
+#[derive(Deserialize, Debug, Clone)]
+pub struct RunSummary {
+    pub name: String,
+    pub extractor: String,
+    pub tree: f64,
+    pub dag: f64,
+    pub micros: u64,
+    // Results from before it was recorded are ok.
+    #[serde(default)]
+    pub status: Option<String>,
+    #[serde(default)]
+    pub cost_model: Option<serde_json::Value>,
+}
+
+pub struct HeadToHead {
+    // By dag cost, for the first extractor.
+    pub wins: usize,
+    pub ties: usize,
+    pub losses: usize,
+    // None if every egraph had a zero cost.
+    pub tree: Option<RatioStats>,
+    pub dag: Option<RatioStats>,
+    pub micros: Option<RatioStats>,
+}
+
+pub struct RatioStats {
+    pub count: usize,
+    pub geomean: f64,
+    // The minimum, quartiles and maximum.
+    pub quantiles: [f64; 5],
+}
+
+impl RatioStats {
+    fn of(mut ratios: Vec<f64>) -> Option<Self> {
+        if ratios.is_empty() {
+            return None;
+        }
+        ratios.sort_by(f64::total_cmp);
+        let n = ratios.len();
+        let quantile = |q: f64| {
+            let pos = q * (n - 1) as f64;
+            let (low, high) = (pos.floor() as usize, pos.ceil() as usize);
+            ratios[low] + (ratios[high] - ratios[low]) * (pos - low as f64)
+        };
+        Some(RatioStats {
+            count: n,
+            geomean: (ratios.iter().map(|r| r.ln()).sum::<f64>() / n as f64).exp(),
+            quantiles: [0.0, 0.25, 0.5, 0.75, 1.0].map(quantile),
+        })
+    }
+}
+
+// The results under `dirs`, in order of their paths. A later result for the same
+// egraph and extractor replaces an earlier one.
+pub fn load_results(dirs: &[impl AsRef<Path>]) -> IndexMap<(String, String), RunSummary> {
+    let mut paths = vec![];
+    for dir in dirs {
+        paths.extend(
+            walkdir::WalkDir::new(dir)
+                .into_iter()
+                .filter_map(Result::ok)
+                .filter(|e| {
+                    e.file_type().is_file()
+                        && e.path().extension().and_then(std::ffi::OsStr::to_str) == Some("json")
+                })
+                .map(|e| e.into_path()),
+        );
+    }
+    paths.sort();
+
+    let mut results = IndexMap::new();
+    for path in paths {
+        let summary: RunSummary = match std::fs::read(&path)
+            .map_err(anyhow::Error::from)
+            .and_then(|bytes| Ok(serde_json::from_slice(&bytes)?))
+        {
+            Ok(summary) => summary,
+            Err(e) => {
+                eprintln!(
+                    "Warning: skipping {}, it isn't a result: {e}",
+                    path.display()
+                );
+                continue;
+            }
+        };
+        let key = (summary.name.clone(), summary.extractor.clone());
+        if results.insert(key, summary).is_some() {
+            eprintln!("Warning: {} repeats an earlier result", path.display());
+        }
+    }
+    results
+}
+
+// None if no egraph has results from both.
+pub fn head_to_head(
+    results: &IndexMap<(String, String), RunSummary>,
+    a: &str,
+    b: &str,
+) -> Option<HeadToHead> {
+    let pairs: Vec<(&RunSummary, &RunSummary)> = results
+        .iter()
+        .filter(|((_, extractor), _)| extractor == a)
+        .filter_map(|((name, _), run_a)| {
+            let run_b = results.get(&(name.clone(), b.to_string()))?;
+            Some((run_a, run_b))
+        })
+        .collect();
+    if pairs.is_empty() {
+        return None;
+    }
+
+    let mismatched = pairs
+        .iter()
+        .filter(|(run_a, run_b)| run_a.cost_model != run_b.cost_model)
+        .count();
+    if mismatched > 0 {
+        eprintln!(
+            "Warning: {mismatched} egraphs were extracted under different cost models by {a} and {b}"
+        );
+    }
+
+    let (mut wins, mut ties, mut losses) = (0, 0, 0);
+    for (run_a, run_b) in &pairs {
+        if (run_a.dag - run_b.dag).abs() < EPSILON_ALLOWANCE {
+            ties += 1;
+        } else if run_a.dag < run_b.dag {
+            wins += 1;
+        } else {
+            losses += 1;
+        }
+    }
+    let ratios = |cost: fn(&RunSummary) -> f64| {
+        RatioStats::of(
+            pairs
+                .iter()
+                .filter(|(run_a, run_b)| cost(run_a) > 0.0 && cost(run_b) > 0.0)
+                .map(|(run_a, run_b)| cost(run_a) / cost(run_b))
+                .collect(),
+        )
+    };
+    Some(HeadToHead {
+        wins,
+        ties,
+        losses,
+        tree: ratios(|run| run.tree),
+        dag: ratios(|run| run.dag),
+        // As in plot.py, a run under a microsecond counts as one.
+        micros: ratios(|run| run.micros.max(1) as f64),
+    })
+}
+
+pub fn aggregate_command(mut args: pico_args::Arguments) {
+    let compare: Option<String> = args.opt_value_from_str("--compare").unwrap();
+    let dirs: Vec<std::path::PathBuf> = args.finish().into_iter().map(Into::into).collect();
+    if dirs.is_empty() {
+        panic!("No result directories given");
+    }
+
+    let results = load_results(&dirs);
+    let not_ok = results
+        .values()
+        .filter(|run| run.status.as_deref().unwrap_or("ok") != "ok")
+        .count();
+    if not_ok > 0 {
+        eprintln!("Warning: {not_ok} results aren't the extractor's own, see their status");
+    }
+
+    let mut extractors: Vec<&str> = results.values().map(|run| run.extractor.as_str()).collect();
+    extractors.sort();
+    extractors.dedup();
+
+    println!("extractor\tegraphs\ttotal ms\tmean tree\tmean dag\tmean micros");
+    for extractor in &extractors {
+        let runs: Vec<&RunSummary> = results
+            .values()
+            .filter(|run| run.extractor == *extractor)
+            .collect();
+        let n = runs.len() as f64;
+        let total_micros: u64 = runs.iter().map(|run| run.micros).sum();
+        println!(
+            "{extractor}\t{}\t{}\t{:.4}\t{:.4}\t{:.0}",
+            runs.len(),
+            total_micros / 1000,
+            runs.iter().map(|run| run.tree).sum::<f64>() / n,
+            runs.iter().map(|run| run.dag).sum::<f64>() / n,
+            total_micros as f64 / n,
+        );
+    }
+
+    let pairs: Vec<(String, String)> = match compare {
+        Some(pair) => {
+            let (a, b) = pair
+                .split_once(',')
+                .unwrap_or_else(|| panic!("--compare {pair} isn't two extractors, a,b"));
+            vec![(a.to_string(), b.to_string())]
+        }
+        None => extractors
+            .iter()
+            .enumerate()
+            .flat_map(|(i, a)| {
+                extractors[i + 1..]
+                    .iter()
+                    .map(|b| (a.to_string(), b.to_string()))
+            })
+            .collect(),
+    };
+    for (a, b) in pairs {
+        println!();
+        let Some(outcome) = head_to_head(&results, &a, &b) else {
+            println!("{a} vs {b}: no egraphs have results from both");
+            continue;
+        };
+        println!(
+            "{a} vs {b}: {} wins, {} ties, {} losses by dag cost",
+            outcome.wins, outcome.ties, outcome.losses
+        );
+        println!("{a} / {b}\tegraphs\tgeomean\tmin\tq1\tmedian\tq3\tmax");
+        for (name, stats) in [
+            ("tree", &outcome.tree),
+            ("dag", &outcome.dag),
+            ("micros", &outcome.micros),
+        ] {
+            let Some(stats) = stats else {
+                println!("{name}\t0");
+                continue;
+            };
+            let quantiles: Vec<String> =
+                stats.quantiles.iter().map(|q| format!("{q:.4}")).collect();
+            println!(
+                "{name}\t{}\t{:.4}\t{}",
+                stats.count,
+                stats.geomean,
+                quantiles.join("\t")
+            );
+        }
+    }
+}
@@ -0,0 +1,143 @@
+/*
+Runs one extractor on every egraph in a directory, on up to `threads` threads, and
+writes a single report for them all. This saves starting the binary and loading
+the registry once per file, as running it from the Makefile does.
+
+Each egraph is extracted as a plain run of the binary would, without any of the
+other options: the result is checked and pruned to what the roots need. A file
+that fails to parse or whose extraction panics is reported with its error, and
+the rest of the batch still runs.
+
+Which egraphs are in the directory, and their suites, is up to `corpus`. Egraphs
+with more classes than `--max-classes` are left out of the report.
+
+An egraph with the same `corpus::egraph_hash` as one earlier in the batch isn't
+extracted again. Its record copies the earlier one's results, and names it in
+`duplicate_of`, so aggregates can leave it out rather than count it twice. Hashing
+reads each egraph an extra time, which is quick next to extracting it.
+
+Run with `extraction-gym --batch <dir> [--extractor <name>] [--threads <n>]
+[--suite <suite>]... [--max-classes <n>]`.
+*/
+
+use crate::corpus::{Corpus, CorpusEntry};
+use crate::*;
+use rustc_hash::FxHashMap;
+
+pub fn run_batch(
+    ed: &ExtractorDetail,
+    extractor_name: &str,
+    corpus: &Corpus,
+    lenient: bool,
+    threads: usize,
+) -> schema::BatchReport {
+    // None if the egraph can't be read, or is too big.
+    let hashes = extract::parallel::run_jobs(corpus.entries.len(), threads, |i| {
+        let egraph = corpus.read(&corpus.entries[i], lenient).ok().flatten()?;
+        Some(crate::corpus::egraph_hash(&egraph))
+    });
+    let mut first = FxHashMap::<&str, usize>::default();
+    let duplicate_of: Vec<Option<usize>> = hashes
+        .iter()
+        .enumerate()
+        .map(|(i, hash)| {
+            let original = *first.entry(hash.as_deref()?).or_insert(i);
+            (original != i).then_some(original)
+        })
+        .collect();
+
+    let mut results =
+        extract::parallel::run_jobs(corpus.entries.len(), threads, |i| match duplicate_of[i] {
+            Some(_) => None,
+            None => extract_file(ed, corpus, &corpus.entries[i], lenient),
+        });
+    let mut duplicates = 0;
+    for (i, original) in duplicate_of.iter().enumerate() {
+        if let Some(original) = original.and_then(|j| results[j].clone()) {
+            let entry = &corpus.entries[i];
+            results[i] = Some(schema::BatchRecord {
+                name: entry.path.display().to_string(),
+                suite: entry.suite.clone(),
+                duplicate_of: Some(original.name.clone()),
+                ..original
+            });
+            duplicates += 1;
+        }
+    }
+    for (record, hash) in results.iter_mut().zip(hashes) {
+        if let Some(record) = record {
+            record.hash = hash;
+        }
+    }
+    if duplicates > 0 {
+        log::info!("Skipped {duplicates} duplicate egraphs");
+    }
+
+    schema::BatchReport {
+        version: schema::SCHEMA_VERSION,
+        extractor: extractor_name.to_string(),
+        threads,
+        duplicates,
+        results: results.into_iter().flatten().collect(),
+    }
+}
+
+// None if the egraph is too big for the corpus.
+fn extract_file(
+    ed: &ExtractorDetail,
+    corpus: &Corpus,
+    entry: &CorpusEntry,
+    lenient: bool,
+) -> Option<schema::BatchRecord> {
+    let mut record = schema::BatchRecord {
+        name: entry.path.display().to_string(),
+        suite: entry.suite.clone(),
+        tree: None,
+        dag: None,
+        micros: None,
+        memory_limit_exceeded: false,
+        error: None,
+        hash: None,
+        duplicate_of: None,
+    };
+
+    let egraph = match corpus.read(entry, lenient) {
+        Ok(Some(egraph)) => egraph,
+        Ok(None) => return None,
+        Err(e) => {
+            record.error = Some(format!("Failed to parse: {e:#}"));
+            return Some(record);
+        }
+    };
+    if !ed.supports_cycles && extract::canonicalize::has_cycles(&egraph) {
+        record.error = Some("The extractor doesn't support egraphs with cycles".into());
+        return Some(record);
+    }
+
+    let roots = &egraph.root_eclasses;
+    let extracted = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        let start_time = std::time::Instant::now();
+        let mut result = ed.extractor.extract(&egraph, roots);
+        let us = start_time.elapsed().as_micros();
+        result.retain_reachable(&egraph, roots);
+        result.check(&egraph);
+        (result, us)
+    }));
+    match extracted {
+        Ok((result, us)) => {
+            record.tree = Some(result.tree_cost(&egraph, roots).into_inner());
+            record.dag = Some(result.dag_cost(&egraph, roots).into_inner());
+            record.micros = Some(us as u64);
+            record.memory_limit_exceeded = result.memory_limit_exceeded;
+        }
+        Err(panic) => {
+            let message = panic
+                .downcast_ref::<String>()
+                .cloned()
+                .or_else(|| panic.downcast_ref::<&str>().map(|s| s.to_string()))
+                .unwrap_or_default();
+            record.error = Some(format!("Extraction failed: {message}"));
+        }
+    }
+    Some(record)
+}
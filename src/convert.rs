@@ -0,0 +1,141 @@
+/*
+A binary form of the egraph format, so benchmarks of fast extractors aren't
+dominated by parsing JSON.
+
+`extraction-gym convert <egraph.json>...` reads each egraph as `--batch` would, in
+strict mode unless given `--lenient`, and writes it next to the input with a .bin
+extension. Anything that reads an egraph with `schema::read_egraph`, like a plain
+extraction, reads a .bin file as the egraph it was converted from.
+
+The file is bincode. Ids are strings once each, and children and roots are indices,
+so there's little to parse. A file of another format version is an error, so a
+stale conversion is caught rather than misread: convert the corpus again.
+*/
+
+use crate::*;
+use anyhow::Context;
+use egraph_serialize::ClassData;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+pub const BINARY_VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize)]
+struct BinaryEGraph {
+    version: u32,
+    node_ids: Vec<String>,
+    // Indexed like `node_ids`.
+    ops: Vec<String>,
+    costs: Vec<f64>,
+    node_class: Vec<u32>,
+    // Node i's children are children[child_start[i]..child_start[i + 1]], as indices
+    // into `node_ids`.
+    child_start: Vec<u32>,
+    children: Vec<u32>,
+    class_ids: Vec<String>,
+    // Indexed like `class_ids`.
+    class_types: Vec<Option<String>>,
+    roots: Vec<u32>,
+}
+
+pub fn write_binary(egraph: &EGraph, path: &Path) -> anyhow::Result<()> {
+    let classes = egraph.classes();
+    let mut binary = BinaryEGraph {
+        version: BINARY_VERSION,
+        node_ids: Vec::with_capacity(egraph.nodes.len()),
+        ops: Vec::with_capacity(egraph.nodes.len()),
+        costs: Vec::with_capacity(egraph.nodes.len()),
+        node_class: Vec::with_capacity(egraph.nodes.len()),
+        child_start: vec![0],
+        children: vec![],
+        class_ids: classes.keys().map(|cid| cid.to_string()).collect(),
+        class_types: classes
+            .keys()
+            .map(|cid| egraph.class_data.get(cid).and_then(|data| data.typ.clone()))
+            .collect(),
+        roots: egraph
+            .root_eclasses
+            .iter()
+            .map(|root| classes.get_index_of(root).unwrap() as u32)
+            .collect(),
+    };
+    for (nid, node) in &egraph.nodes {
+        binary.node_ids.push(nid.to_string());
+        binary.ops.push(node.op.clone());
+        binary.costs.push(node.cost.into_inner());
+        binary
+            .node_class
+            .push(classes.get_index_of(&node.eclass).unwrap() as u32);
+        for child in &node.children {
+            binary
+                .children
+                .push(egraph.nodes.get_index_of(child).unwrap() as u32);
+        }
+        binary.child_start.push(binary.children.len() as u32);
+    }
+
+    let file = std::fs::File::create(path)?;
+    bincode::serialize_into(std::io::BufWriter::new(file), &binary)?;
+    Ok(())
+}
+
+pub fn read_binary(path: &Path) -> anyhow::Result<EGraph> {
+    let file = std::fs::File::open(path)?;
+    let binary: BinaryEGraph =
+        bincode::deserialize_from(std::io::BufReader::new(file)).context("Not a binary egraph")?;
+    anyhow::ensure!(
+        binary.version == BINARY_VERSION,
+        "Binary egraph version {} isn't the supported version {BINARY_VERSION}, convert it again",
+        binary.version
+    );
+
+    let node_ids: Vec<NodeId> = binary.node_ids.into_iter().map(NodeId::from).collect();
+    let class_ids: Vec<ClassId> = binary.class_ids.into_iter().map(ClassId::from).collect();
+    let mut egraph = EGraph::default();
+    for (i, op) in binary.ops.into_iter().enumerate() {
+        let children =
+            &binary.children[binary.child_start[i] as usize..binary.child_start[i + 1] as usize];
+        egraph.add_node(
+            node_ids[i].clone(),
+            Node {
+                op,
+                children: children
+                    .iter()
+                    .map(|&c| node_ids[c as usize].clone())
+                    .collect(),
+                eclass: class_ids[binary.node_class[i] as usize].clone(),
+                cost: Cost::new(binary.costs[i])?,
+            },
+        );
+    }
+    for (cid, typ) in class_ids.iter().zip(binary.class_types) {
+        if typ.is_some() {
+            egraph.class_data.insert(cid.clone(), ClassData { typ });
+        }
+    }
+    egraph.root_eclasses = binary
+        .roots
+        .iter()
+        .map(|&root| class_ids[root as usize].clone())
+        .collect();
+    Ok(egraph)
+}
+
+pub fn convert_command(mut args: pico_args::Arguments) {
+    let lenient = args.contains("--lenient");
+    let files: Vec<PathBuf> = args.finish().into_iter().map(PathBuf::from).collect();
+    if files.is_empty() {
+        panic!("No egraphs given");
+    }
+
+    for file in &files {
+        let egraph = schema::read_egraph(&file.to_string_lossy(), lenient)
+            .with_context(|| format!("Failed to parse {}", file.display()))
+            .unwrap();
+        let out = file.with_extension("bin");
+        write_binary(&egraph, &out)
+            .with_context(|| format!("Failed to write {}", out.display()))
+            .unwrap();
+        log::info!("Converted {} to {}", file.display(), out.display());
+    }
+}
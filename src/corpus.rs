@@ -0,0 +1,125 @@
+/*
+The egraphs that tests and benchmarks run on, found by walking directories such as
+./data and ./test_data, so they all agree on which files are egraphs and where they
+came from.
+
+Every .json file under a walked directory is an egraph. Each is tagged with its
+suite: the directory it's in directly under the walked one, e.g. babble, egg, flexc
+or tensat under ./data, or crafted and fuzz under ./test_data. Files directly in the
+walked directory are in the suite ".".
+
+A corpus can be restricted to some suites, and to egraphs with at most so many
+classes, as `--suite` and `--max-classes` do for `--batch`. The size is only known
+once an egraph is read, so that filter applies in `read`.
+
+Corpora have the same egraph serialized more than once, so `egraph_hash` hashes what
+an egraph is rather than how it's written: its nodes in order of their ids, with
+their ops, classes, costs and children, then its roots. Files with the same hash
+extract the same way.
+*/
+
+use crate::*;
+use std::path::{Path, PathBuf};
+
+pub struct CorpusEntry {
+    pub path: PathBuf,
+    pub suite: String,
+}
+
+#[derive(Default)]
+pub struct Corpus {
+    // Sorted by path.
+    pub entries: Vec<CorpusEntry>,
+    max_classes: Option<usize>,
+}
+
+impl Corpus {
+    pub fn discover(dirs: impl IntoIterator<Item = impl AsRef<Path>>) -> Self {
+        let mut entries = vec![];
+        for dir in dirs {
+            let dir = dir.as_ref();
+            for entry in walkdir::WalkDir::new(dir)
+                .into_iter()
+                .filter_map(Result::ok)
+                .filter(|e| {
+                    e.file_type().is_file()
+                        && e.path().extension().and_then(std::ffi::OsStr::to_str) == Some("json")
+                })
+            {
+                let path = entry.into_path();
+                let suite = match path.strip_prefix(dir).ok().and_then(|p| p.parent()) {
+                    Some(parent) if parent != Path::new("") => parent
+                        .components()
+                        .next()
+                        .unwrap()
+                        .as_os_str()
+                        .to_string_lossy()
+                        .into_owned(),
+                    _ => ".".into(),
+                };
+                entries.push(CorpusEntry { path, suite });
+            }
+        }
+        entries.sort_by(|a, b| a.path.cmp(&b.path));
+        Corpus {
+            entries,
+            max_classes: None,
+        }
+    }
+
+    // Keeps only the egraphs in these suites, or all of them if there are none.
+    pub fn in_suites(mut self, suites: &[String]) -> Self {
+        if !suites.is_empty() {
+            self.entries.retain(|e| suites.contains(&e.suite));
+        }
+        self
+    }
+
+    pub fn with_max_classes(mut self, max_classes: usize) -> Self {
+        self.max_classes = Some(max_classes);
+        self
+    }
+
+    // None if the egraph has more classes than allowed.
+    pub fn read(&self, entry: &CorpusEntry, lenient: bool) -> anyhow::Result<Option<EGraph>> {
+        let egraph = schema::read_egraph(&entry.path.to_string_lossy(), lenient)?;
+        if self
+            .max_classes
+            .is_some_and(|max| egraph.classes().len() > max)
+        {
+            return Ok(None);
+        }
+        Ok(Some(egraph))
+    }
+
+    // Every egraph in the corpus that isn't too big. Panics if one can't be read.
+    pub fn egraphs(&self, lenient: bool) -> impl Iterator<Item = EGraph> + '_ {
+        self.entries.iter().filter_map(move |entry| {
+            self.read(entry, lenient)
+                .with_context(|| format!("Failed to parse {}", entry.path.display()))
+                .unwrap()
+        })
+    }
+}
+
+// The same for the same nodes and roots, however the file orders or formats them.
+pub fn egraph_hash(egraph: &EGraph) -> String {
+    let mut nodes: Vec<(&NodeId, &Node)> = egraph.nodes.iter().collect();
+    nodes.sort_by_key(|(nid, _)| *nid);
+    let mut canonical = vec![];
+    for (nid, node) in nodes {
+        let children: Vec<String> = node.children.iter().map(|c| c.to_string()).collect();
+        let line = (
+            nid.to_string(),
+            &node.op,
+            node.eclass.to_string(),
+            node.cost.into_inner(),
+            children,
+        );
+        serde_json::to_writer(&mut canonical, &line).unwrap();
+        canonical.push(b'\n');
+    }
+    let roots: Vec<String> = egraph.root_eclasses.iter().map(|r| r.to_string()).collect();
+    serde_json::to_writer(&mut canonical, &roots).unwrap();
+    extract::cost_model::content_hash(&canonical)
+}
@@ -0,0 +1,148 @@
+/*
+Compares the extractions of one egraph under two cost models, to see which choices
+changing the costs flipped, and what each flip is worth under either model.
+
+The two cost models are given as two copies of the egraph that differ only in their
+node costs. Both are extracted with the same extractor, and for each class whose
+chosen node differs, the report gives both nodes' costs under both models. The dag
+cost of each extraction under each model is printed too, so it's clear how much
+each extraction gains under its own model and loses under the other.
+
+Run with `extraction-gym compare-cost-models [--extractor <name>] <a.json> <b.json>`.
+*/
+
+use crate::*;
+use anyhow::Context;
+
+pub struct ChoiceDelta {
+    pub class: ClassId,
+    // The node chosen under each model, if the class was chosen at all.
+    pub a: Option<NodeId>,
+    pub b: Option<NodeId>,
+    // How much more the node chosen under b costs than the one chosen under a,
+    // with each model's costs. Unchosen nodes cost nothing.
+    pub delta_under_a: f64,
+    pub delta_under_b: f64,
+}
+
+// Errors unless the egraphs have the same nodes, classes and roots.
+pub fn check_same_structure(a: &EGraph, b: &EGraph) -> anyhow::Result<()> {
+    anyhow::ensure!(
+        a.root_eclasses == b.root_eclasses,
+        "The egraphs have different roots"
+    );
+    anyhow::ensure!(
+        a.nodes.len() == b.nodes.len(),
+        "The egraphs have different numbers of nodes"
+    );
+    for (nid, node) in &a.nodes {
+        let other = b
+            .nodes
+            .get(nid)
+            .ok_or_else(|| anyhow::anyhow!("Node {nid} is only in the first egraph"))?;
+        anyhow::ensure!(
+            node.op == other.op && node.children == other.children && node.eclass == other.eclass,
+            "Node {nid} differs in more than its cost"
+        );
+    }
+    Ok(())
+}
+
+pub fn choice_deltas(
+    a: &EGraph,
+    b: &EGraph,
+    result_a: &ExtractionResult,
+    result_b: &ExtractionResult,
+) -> Vec<ChoiceDelta> {
+    let cost = |egraph: &EGraph, nid: Option<&NodeId>| {
+        nid.map_or(0.0, |nid| egraph[nid].cost.into_inner())
+    };
+
+    let mut classes: Vec<&ClassId> = result_a.choices.keys().collect();
+    classes.extend(
+        result_b
+            .choices
+            .keys()
+            .filter(|cid| !result_a.choices.contains_key(*cid)),
+    );
+
+    classes
+        .into_iter()
+        .filter_map(|cid| {
+            let node_a = result_a.choices.get(cid);
+            let node_b = result_b.choices.get(cid);
+            if node_a == node_b {
+                return None;
+            }
+            Some(ChoiceDelta {
+                class: cid.clone(),
+                a: node_a.cloned(),
+                b: node_b.cloned(),
+                delta_under_a: cost(a, node_b) - cost(a, node_a),
+                delta_under_b: cost(b, node_b) - cost(b, node_a),
+            })
+        })
+        .collect()
+}
+
+pub fn compare_cost_models_command(mut args: pico_args::Arguments) {
+    let extractor_name: String = args
+        .opt_value_from_str("--extractor")
+        .unwrap()
+        .unwrap_or_else(|| "bottom-up".into());
+    let lenient = args.contains("--lenient");
+    let filename_a: String = args.free_from_str().unwrap();
+    let filename_b: String = args.free_from_str().unwrap();
+
+    let rest = args.finish();
+    if !rest.is_empty() {
+        panic!("Unknown arguments: {:?}", rest);
+    }
+
+    let read = |filename: &str| {
+        schema::read_egraph(filename, lenient)
+            .with_context(|| format!("Failed to parse {filename}"))
+            .unwrap()
+    };
+    let a = read(&filename_a);
+    let b = read(&filename_b);
+    check_same_structure(&a, &b)
+        .with_context(|| format!("{filename_a} and {filename_b} aren't the same egraph"))
+        .unwrap();
+
+    let extractors = extractors();
+    let ed = extractors
+        .get(extractor_name.as_str())
+        .with_context(|| format!("Unknown extractor: {extractor_name}"))
+        .unwrap();
+    let roots = &a.root_eclasses;
+    let mut result_a = ed.extractor.extract(&a, roots);
+    let mut result_b = ed.extractor.extract(&b, roots);
+    for (result, egraph) in [(&mut result_a, &a), (&mut result_b, &b)] {
+        result.retain_reachable(egraph, roots);
+        result.check(egraph);
+    }
+
+    println!("extraction\tdag under a\tdag under b");
+    for (name, result) in [("a", &result_a), ("b", &result_b)] {
+        println!(
+            "{name}\t{}\t{}",
+            result.dag_cost(&a, roots),
+            result.dag_cost(&b, roots)
+        );
+    }
+
+    println!();
+    println!("class\tnode under a\tnode under b\tdelta under a\tdelta under b");
+    let none = || "-".to_string();
+    for delta in choice_deltas(&a, &b, &result_a, &result_b) {
+        println!(
+            "{}\t{}\t{}\t{}\t{}",
+            delta.class,
+            delta.a.map_or_else(none, |nid| nid.to_string()),
+            delta.b.map_or_else(none, |nid| nid.to_string()),
+            delta.delta_under_a,
+            delta.delta_under_b
+        );
+    }
+}
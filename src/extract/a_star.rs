@@ -0,0 +1,165 @@
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+
+use im_rc::{OrdMap, OrdSet};
+
+use super::*;
+
+/// A* search for the extraction with the cheapest dag cost.
+///
+/// A state is a partial extraction: the choices made so far, and the classes that
+/// the roots or the chosen nodes need but that have no choice yet. A state is
+/// expanded by choosing each node of its smallest pending class, skipping nodes
+/// that would close a cycle. The cost so far is the sum of the chosen nodes' costs,
+/// and the estimate of the cost still to come is the sum of the cheapest node cost
+/// of each pending class. Pending classes are a set, so a class needed in several
+/// places is only counted once. That estimate never overestimates, and never
+/// decreases by more than the cost of a step, so the first complete state taken
+/// from the queue is optimal. States are only expanded once, however they were
+/// reached.
+///
+/// The search space is exponential, so after `max_expansions` states it gives up
+/// and returns the faster-greedy-dag extraction. That extraction's cost is also
+/// used to prune states that can't beat it.
+pub struct AStarExtractor {
+    pub max_expansions: usize,
+}
+
+#[derive(Clone)]
+struct State {
+    choices: OrdMap<ClassId, NodeId>,
+    pending: OrdSet<ClassId>,
+    cost: Cost,
+    estimate: Cost,
+}
+
+// Ordered by the lowest total, then by the order states were made, for determinism.
+struct Entry(Reverse<(Cost, usize)>, State);
+
+impl PartialEq for Entry {
+    fn eq(&self, other: &Self) -> bool {
+        self.0 == other.0
+    }
+}
+
+impl Eq for Entry {}
+
+impl PartialOrd for Entry {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Entry {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.0.cmp(&other.0)
+    }
+}
+
+impl Extractor for AStarExtractor {
+    fn extract(&self, egraph: &EGraph, roots: &[ClassId]) -> ExtractionResult {
+        self.extract_with_cache(egraph, roots, &CostCache::new(egraph))
+    }
+
+    fn extract_with_cache(
+        &self,
+        egraph: &EGraph,
+        roots: &[ClassId],
+        cache: &CostCache,
+    ) -> ExtractionResult {
+        debug_assert!(std::ptr::eq(egraph, cache.egraph()));
+        let greedy = super::faster_greedy_dag::FasterGreedyDagExtractor
+            .extract_with_cache(egraph, roots, cache);
+        let greedy_cost = greedy.dag_cost(egraph, roots);
+        let lower_bounds = cache.class_min_costs();
+
+        let pending: OrdSet<ClassId> = roots.iter().cloned().collect();
+        let estimate = pending.iter().map(|c| lower_bounds[c]).sum();
+        let mut queue = BinaryHeap::new();
+        let mut made = 0;
+        queue.push(Entry(
+            Reverse((estimate, made)),
+            State {
+                choices: OrdMap::new(),
+                pending,
+                cost: Cost::default(),
+                estimate,
+            },
+        ));
+
+        let mut expanded = FxHashSet::<OrdMap<ClassId, NodeId>>::default();
+        while let Some(Entry(_, state)) = queue.pop() {
+            let class_id = match state.pending.get_min() {
+                Some(class_id) => class_id.clone(),
+                None => {
+                    let mut result = ExtractionResult::default();
+                    for (cid, nid) in state.choices {
+                        result.choose(cid, nid);
+                    }
+                    return result;
+                }
+            };
+
+            if !expanded.insert(state.choices.clone()) {
+                continue;
+            }
+            if expanded.len() > self.max_expansions {
+                log::info!("A* gave up after {} states", self.max_expansions);
+                return greedy;
+            }
+
+            for node_id in &egraph[&class_id].nodes {
+                let node = &egraph[node_id];
+                if closes_cycle(egraph, &state.choices, &class_id, node) {
+                    continue;
+                }
+
+                let mut next = state.clone();
+                next.choices.insert(class_id.clone(), node_id.clone());
+                next.pending.remove(&class_id);
+                next.cost += node.cost;
+                next.estimate -= lower_bounds[&class_id];
+                for child in &node.children {
+                    let child_cid = egraph.nid_to_cid(child);
+                    if !next.choices.contains_key(child_cid)
+                        && next.pending.insert(child_cid.clone()).is_none()
+                    {
+                        next.estimate += lower_bounds[child_cid];
+                    }
+                }
+
+                let total = next.cost + next.estimate;
+                // Only strictly cheaper extractions are worth finding.
+                if total >= greedy_cost {
+                    continue;
+                }
+                made += 1;
+                queue.push(Entry(Reverse((total, made)), next));
+            }
+        }
+
+        // Nothing is cheaper than the greedy extraction.
+        greedy
+    }
+}
+
+// Whether choosing `node` for `class_id` would make a cycle with `choices`.
+fn closes_cycle(
+    egraph: &EGraph,
+    choices: &OrdMap<ClassId, NodeId>,
+    class_id: &ClassId,
+    node: &Node,
+) -> bool {
+    cycles::reaches(
+        node.children.iter().map(|c| egraph.nid_to_cid(c)),
+        class_id,
+        |cid| {
+            choices.get(cid).into_iter().flat_map(move |nid| {
+                egraph[nid]
+                    .children
+                    .iter()
+                    .map(move |c| egraph.nid_to_cid(c))
+            })
+        },
+    )
+}
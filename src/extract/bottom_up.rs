@@ -1,7 +1,76 @@
 use super::*;
+use fast_egraph::{FastEgraph, FastExtractor, NodeIndex};
 
 pub struct BottomUpExtractor;
 impl Extractor for BottomUpExtractor {
+    fn extract(&self, egraph: &EGraph, _roots: &[ClassId]) -> ExtractionResult {
+        let fast = FastEgraph::new(egraph);
+        let result = fast.to_result(&self.extract_fast(&fast));
+        soundness::check_tree_optimal(egraph, &result);
+        result
+    }
+}
+
+impl FastExtractor for BottomUpExtractor {
+    fn extract_fast(&self, fast: &FastEgraph) -> Vec<Option<NodeIndex>> {
+        let mut costs = vec![INFINITY; fast.num_classes()];
+        let mut choices = vec![None; fast.num_classes()];
+        let mut did_something = false;
+
+        loop {
+            for class in fast.class_indices() {
+                for node in fast.nodes(class) {
+                    let cost = fast.node_cost[node.index()]
+                        + fast
+                            .children(node)
+                            .iter()
+                            .map(|&c| costs[c.index()])
+                            .sum::<Cost>();
+                    if cost < costs[class.index()] {
+                        choices[class.index()] = Some(node);
+                        costs[class.index()] = cost;
+                        did_something = true;
+                    }
+                }
+            }
+
+            if did_something {
+                did_something = false;
+            } else {
+                break;
+            }
+        }
+        choices
+    }
+}
+
+/// Bottom-up extraction for a tree cost where each level of depth is discounted
+/// by `gamma`, see `ExtractionResult::discounted_tree_cost`. Each class's best
+/// discounted cost only depends on its children's, so the same fixed point
+/// iteration as `BottomUpExtractor` applies. With gamma = 1 they agree.
+///
+/// Discounting can make a cycle look cheaper than any finite term, so a node is
+/// only selected if that doesn't close a cycle in the current choices. On egraphs
+/// with cycles the result is then valid, but not necessarily optimal.
+pub struct DiscountedBottomUpExtractor {
+    gamma: f64,
+}
+
+impl DiscountedBottomUpExtractor {
+    pub fn new(gamma: f64) -> anyhow::Result<Self> {
+        anyhow::ensure!(
+            gamma > 0.0 && gamma <= 1.0,
+            "gamma must be above 0 and at most 1, not {gamma}"
+        );
+        Ok(Self { gamma })
+    }
+
+    pub fn gamma(&self) -> f64 {
+        self.gamma
+    }
+}
+
+impl Extractor for DiscountedBottomUpExtractor {
     fn extract(&self, egraph: &EGraph, _roots: &[ClassId]) -> ExtractionResult {
         let mut result = ExtractionResult::default();
         let mut costs = FxHashMap::<ClassId, Cost>::with_capacity_and_hasher(
@@ -13,8 +82,11 @@ impl Extractor for BottomUpExtractor {
         loop {
             for class in egraph.classes().values() {
                 for node in &class.nodes {
-                    let cost = result.node_sum_cost(egraph, &egraph[node], &costs);
-                    if &cost < costs.get(&class.id).unwrap_or(&INFINITY) {
+                    let cost =
+                        result.node_discounted_cost(egraph, &egraph[node], &costs, self.gamma);
+                    if &cost < costs.get(&class.id).unwrap_or(&INFINITY)
+                        && !reaches_class(&result, egraph, &egraph[node], &class.id)
+                    {
                         result.choose(class.id.clone(), node.clone());
                         costs.insert(class.id.clone(), cost);
                         did_something = true;
@@ -32,3 +104,73 @@ impl Extractor for BottomUpExtractor {
         result
     }
 }
+
+/// Bottom-up extraction minimising the depth, see `ExtractionResult::depth_cost`,
+/// rather than the tree cost. A class's least depth only depends on its children's,
+/// so the same fixed point iteration as `BottomUpExtractor` applies, with the sum
+/// over the children replaced by the max. Costs are never negative, so a node can't
+/// make its class shallower by way of itself, and no cycles are chosen.
+///
+/// The depth is optimal, but which of the shallowest extractions is returned is
+/// arbitrary, see `ilp_cbc::CbcExtractorMinDepth` for the cheapest of them.
+pub struct DepthBottomUpExtractor;
+
+impl Extractor for DepthBottomUpExtractor {
+    fn extract(&self, egraph: &EGraph, _roots: &[ClassId]) -> ExtractionResult {
+        let mut result = ExtractionResult::default();
+        let mut depths = FxHashMap::<ClassId, Cost>::with_capacity_and_hasher(
+            egraph.classes().len(),
+            Default::default(),
+        );
+        let mut did_something = false;
+
+        loop {
+            for class in egraph.classes().values() {
+                for nid in &class.nodes {
+                    let node = &egraph[nid];
+                    let depth = node.cost
+                        + node
+                            .children
+                            .iter()
+                            .map(|c| *depths.get(egraph.nid_to_cid(c)).unwrap_or(&INFINITY))
+                            .max()
+                            .unwrap_or_default();
+                    if &depth < depths.get(&class.id).unwrap_or(&INFINITY) {
+                        result.choose(class.id.clone(), nid.clone());
+                        depths.insert(class.id.clone(), depth);
+                        did_something = true;
+                    }
+                }
+            }
+
+            if did_something {
+                did_something = false;
+            } else {
+                break;
+            }
+        }
+
+        result
+    }
+}
+
+// Whether `target` can be reached from the children of `node` by following the current choices.
+fn reaches_class(
+    result: &ExtractionResult,
+    egraph: &EGraph,
+    node: &Node,
+    target: &ClassId,
+) -> bool {
+    cycles::reaches(
+        node.children.iter().map(|c| egraph.nid_to_cid(c)),
+        target,
+        |cid| {
+            result.choices.get(cid).into_iter().flat_map(move |nid| {
+                egraph[nid]
+                    .children
+                    .iter()
+                    .map(move |c| egraph.nid_to_cid(c))
+            })
+        },
+    )
+}
@@ -0,0 +1,140 @@
+/*
+Puts a parsed egraph into a canonical form that doesn't change the optimal tree or dag cost:
+
+- Nodes with a child in their own class are removed, they can never be part of
+  a loop-free extraction.
+- Nodes in the same class with the same op and the same children classes are
+  duplicates, only the cheapest is kept.
+- Nodes that depend on a class with no nodes left are removed.
+- Children refer to their class through the class's first remaining node, so two
+  nodes with the same children classes have identical children lists.
+
+Node and class ids are otherwise unchanged, so an extraction of the canonical
+egraph is also an extraction of the original.
+*/
+
+use super::*;
+
+#[derive(Default, Debug)]
+pub struct CanonicalizeStats {
+    pub self_loops: usize,
+    pub duplicates: usize,
+    pub dead: usize,
+}
+
+pub fn canonicalize(egraph: &EGraph) -> (EGraph, CanonicalizeStats) {
+    let mut stats = CanonicalizeStats::default();
+    let n2c = |nid: &NodeId| egraph.nid_to_cid(nid);
+
+    let mut kept: FxHashSet<NodeId> = Default::default();
+    for class in egraph.classes().values() {
+        // (op, children classes) -> cheapest node with them.
+        let mut seen: FxHashMap<(&str, Vec<&ClassId>), &NodeId> = Default::default();
+        for nid in &class.nodes {
+            let node = &egraph[nid];
+            let children: Vec<&ClassId> = node.children.iter().map(n2c).collect();
+            if children.contains(&&class.id) {
+                stats.self_loops += 1;
+                continue;
+            }
+            match seen.entry((node.op.as_str(), children)) {
+                std::collections::hash_map::Entry::Occupied(mut e) => {
+                    stats.duplicates += 1;
+                    if node.cost < egraph[*e.get()].cost {
+                        e.insert(nid);
+                    }
+                }
+                std::collections::hash_map::Entry::Vacant(e) => {
+                    e.insert(nid);
+                }
+            }
+        }
+        kept.extend(seen.into_values().cloned());
+    }
+
+    let before = kept.len();
+    let canonical = rebuild_with(egraph, kept);
+    stats.dead = before - canonical.nodes.len();
+
+    log::info!("Canonicalization removed {stats:?}");
+    (canonical, stats)
+}
+
+// Makes a new egraph from the `kept` nodes, and the roots, of `egraph`. Nodes whose
+// children have no kept nodes are also removed, and children are pointed at the
+// first kept node of their class.
+pub fn rebuild_with(egraph: &EGraph, mut kept: FxHashSet<NodeId>) -> EGraph {
+    let n2c = |nid: &NodeId| egraph.nid_to_cid(nid);
+
+    // Remove nodes with a child class that has no nodes left, until nothing changes.
+    loop {
+        let live: FxHashSet<&ClassId> = kept.iter().map(n2c).collect();
+        let before = kept.len();
+        kept.retain(|nid| egraph[nid].children.iter().all(|c| live.contains(n2c(c))));
+        if kept.len() == before {
+            break;
+        }
+    }
+
+    // Children refer to a class via one of its nodes, so point them at a kept node.
+    let mut representative: FxHashMap<ClassId, NodeId> = Default::default();
+    for (nid, _) in &egraph.nodes {
+        if kept.contains(nid) {
+            representative
+                .entry(n2c(nid).clone())
+                .or_insert_with(|| nid.clone());
+        }
+    }
+
+    let mut rebuilt = EGraph::default();
+    for (nid, node) in &egraph.nodes {
+        if !kept.contains(nid) {
+            continue;
+        }
+        let mut node = node.clone();
+        for child in &mut node.children {
+            *child = representative[n2c(child)].clone();
+        }
+        rebuilt.add_node(nid.clone(), node);
+    }
+    rebuilt.root_eclasses = egraph.root_eclasses.clone();
+    rebuilt
+}
+
+// The classes that have a finite (loop-free) term.
+pub fn extractable_classes(egraph: &EGraph) -> FxHashSet<ClassId> {
+    let n2c = |nid: &NodeId| egraph.nid_to_cid(nid);
+    let mut extractable: FxHashSet<ClassId> = Default::default();
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for (nid, node) in &egraph.nodes {
+            let cid = n2c(nid);
+            if !extractable.contains(cid)
+                && node.children.iter().all(|c| extractable.contains(n2c(c)))
+            {
+                extractable.insert(cid.clone());
+                changed = true;
+            }
+        }
+    }
+    extractable
+}
+
+// Whether some class can reach itself through the children of its nodes.
+pub fn has_cycles(egraph: &EGraph) -> bool {
+    let classes = egraph.classes();
+    !cycles::find_cycles(
+        classes.keys(),
+        |cid| {
+            classes[cid].nodes.iter().flat_map(move |nid| {
+                egraph[nid]
+                    .children
+                    .iter()
+                    .map(move |c| egraph.nid_to_cid(c))
+            })
+        },
+        1,
+    )
+    .is_empty()
+}
@@ -0,0 +1,114 @@
+/*
+Per-egraph analysis that several extractors compute as part of their setup:
+the cheapest node in each class, the parents of each class, and a
+children-before-parents ordering of the classes.
+
+When several extractors are run on the same egraph (e.g. in the tests, which
+compare every extractor against the optimal ones), the cache is built once and
+passed to each of them via `Extractor::extract_with_cache`, so the setup work
+is only done once. Each part is computed lazily on first use.
+*/
+
+use super::*;
+use std::sync::OnceLock;
+
+pub struct CostCache<'a> {
+    egraph: &'a EGraph,
+    class_min_costs: OnceLock<IndexMap<ClassId, Cost>>,
+    parents: OnceLock<IndexMap<ClassId, Vec<NodeId>>>,
+    topo_order: OnceLock<Vec<ClassId>>,
+}
+
+impl<'a> CostCache<'a> {
+    pub fn new(egraph: &'a EGraph) -> Self {
+        Self {
+            egraph,
+            class_min_costs: Default::default(),
+            parents: Default::default(),
+            topo_order: Default::default(),
+        }
+    }
+
+    pub fn egraph(&self) -> &'a EGraph {
+        self.egraph
+    }
+
+    // The cost of the cheapest node in each class, ignoring the cost of its children.
+    pub fn class_min_costs(&self) -> &IndexMap<ClassId, Cost> {
+        let egraph = self.egraph;
+        self.class_min_costs.get_or_init(|| {
+            egraph
+                .classes()
+                .values()
+                .filter_map(|class| {
+                    let min = class.nodes.iter().map(|nid| egraph[nid].cost).min()?;
+                    Some((class.id.clone(), min))
+                })
+                .collect()
+        })
+    }
+
+    // For each class, the nodes that have that class as a child.
+    pub fn parents(&self) -> &IndexMap<ClassId, Vec<NodeId>> {
+        let egraph = self.egraph;
+        self.parents.get_or_init(|| {
+            let mut parents =
+                IndexMap::<ClassId, Vec<NodeId>>::with_capacity(egraph.classes().len());
+
+            for class in egraph.classes().values() {
+                parents.insert(class.id.clone(), Vec::new());
+            }
+
+            for class in egraph.classes().values() {
+                for node in &class.nodes {
+                    for c in &egraph[node].children {
+                        parents[egraph.nid_to_cid(c)].push(node.clone());
+                    }
+                }
+            }
+            parents
+        })
+    }
+
+    // Classes ordered so that children come before their parents.
+    // Where there are cycles, the edge that closes the cycle is ignored.
+    pub fn topo_order(&self) -> &Vec<ClassId> {
+        let egraph = self.egraph;
+        self.topo_order.get_or_init(|| {
+            let mut visited = FxHashSet::<&ClassId>::default();
+            let mut order = Vec::with_capacity(egraph.classes().len());
+            for class in egraph.classes().values() {
+                Self::topo_dfs(egraph, &class.id, &mut visited, &mut order);
+            }
+            order
+        })
+    }
+
+    // Depth first with an explicit stack, as egraphs can be deeper than the thread's
+    // stack. A class is pushed again, marked done, before its children, and added to
+    // the order when that second entry is popped.
+    fn topo_dfs<'b>(
+        egraph: &'b EGraph,
+        start: &'b ClassId,
+        visited: &mut FxHashSet<&'b ClassId>,
+        order: &mut Vec<ClassId>,
+    ) {
+        let mut stack = vec![(start, false)];
+        while let Some((class_id, done)) = stack.pop() {
+            if done {
+                order.push(class_id.clone());
+                continue;
+            }
+            if !visited.insert(class_id) {
+                continue;
+            }
+            stack.push((class_id, true));
+            // Reversed, so the children are visited in the same order as by recursion.
+            for node in egraph[class_id].nodes.iter().rev() {
+                for child in egraph[node].children.iter().rev() {
+                    stack.push((egraph.nid_to_cid(child), false));
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,168 @@
+/*
+Overrides the costs in a serialized egraph, so the same egraphs can be benchmarked
+under different cost assignments without regenerating the JSON.
+
+A cost model is a list of rules, each matching nodes by node id, class id, op, or a
+regex over the op, and either replacing the matched nodes' cost or scaling it. The
+first rule that matches a node decides its cost; nodes that no rule matches keep
+the cost they were serialized with. It's read from a JSON file given with
+`--cost-model`:
+
+    {"rules": [
+        {"class": "12", "cost": 0},
+        {"op": "*", "cost": 4},
+        {"op_regex": "^(const|var)", "scale": 0.5}
+    ]}
+
+The model is applied to the egraph before extraction, so it works with every extractor.
+
+So results from different models aren't compared by mistake, each result records
+the model it was extracted under: the file's optional `version`, a free-form string
+such as "2024-03 latencies", and a hash of the file's contents. Any edit to the file,
+even to whitespace, changes the hash.
+
+With `--also-eval a.json,b.json`, the extraction is also costed under each of those
+models, with `evaluate`, so one expensive extraction shows how sensitive its cost is
+to the model without extracting again. Each model is applied to the costs the egraph
+was serialized with, not to those of `--cost-model`.
+*/
+
+use super::*;
+use serde::Deserialize;
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct CostModelFile {
+    version: Option<String>,
+    rules: Vec<CostRuleFile>,
+}
+
+// One of the matchers, and one of `cost` or `scale`.
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct CostRuleFile {
+    node: Option<String>,
+    class: Option<String>,
+    op: Option<String>,
+    op_regex: Option<String>,
+    cost: Option<f64>,
+    scale: Option<f64>,
+}
+
+#[derive(Debug)]
+enum Matcher {
+    Node(NodeId),
+    Class(ClassId),
+    Op(String),
+    OpRegex(regex::Regex),
+}
+
+#[derive(Debug)]
+enum Adjustment {
+    Cost(Cost),
+    Scale(f64),
+}
+
+#[derive(Debug, Default)]
+pub struct CostModel {
+    rules: Vec<(Matcher, Adjustment)>,
+    pub version: Option<String>,
+    // See `content_hash`.
+    pub hash: String,
+}
+
+// 64-bit FNV-1a, in hex. Unlike std's hashers it's the same across Rust versions and
+// platforms, so hashes in old results stay comparable.
+pub(crate) fn content_hash(bytes: &[u8]) -> String {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for &byte in bytes {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    format!("{hash:016x}")
+}
+
+impl CostModel {
+    pub fn from_json_file(path: &std::path::Path) -> anyhow::Result<Self> {
+        let bytes = std::fs::read(path)?;
+        let file: CostModelFile = serde_json::from_slice(&bytes)?;
+        let mut model = CostModel {
+            version: file.version,
+            hash: content_hash(&bytes),
+            ..Default::default()
+        };
+        for (i, rule) in file.rules.into_iter().enumerate() {
+            let matcher = match (rule.node, rule.class, rule.op, rule.op_regex) {
+                (Some(node), None, None, None) => Matcher::Node(node.into()),
+                (None, Some(class), None, None) => Matcher::Class(class.into()),
+                (None, None, Some(op), None) => Matcher::Op(op),
+                (None, None, None, Some(re)) => Matcher::OpRegex(
+                    regex::Regex::new(&re).with_context(|| format!("Rule {i} has a bad regex"))?,
+                ),
+                _ => anyhow::bail!("Rule {i} needs exactly one of node, class, op or op_regex"),
+            };
+            let adjustment = match (rule.cost, rule.scale) {
+                (Some(cost), None) => Adjustment::Cost(
+                    NotNan::new(cost)
+                        .ok()
+                        .filter(|c| c.into_inner() >= 0.0)
+                        .ok_or_else(|| anyhow::anyhow!("Rule {i} has a negative or NaN cost"))?,
+                ),
+                (None, Some(scale)) if scale >= 0.0 && scale.is_finite() => {
+                    Adjustment::Scale(scale)
+                }
+                (None, Some(_)) => anyhow::bail!("Rule {i} has a negative or non-finite scale"),
+                _ => anyhow::bail!("Rule {i} needs exactly one of cost or scale"),
+            };
+            model.rules.push((matcher, adjustment));
+        }
+        Ok(model)
+    }
+
+    // The cost of the node under this model.
+    pub fn cost(&self, nid: &NodeId, node: &Node) -> Cost {
+        let matched = self.rules.iter().find(|(matcher, _)| match matcher {
+            Matcher::Node(id) => id == nid,
+            Matcher::Class(id) => *id == node.eclass,
+            Matcher::Op(op) => *op == node.op,
+            Matcher::OpRegex(re) => re.is_match(&node.op),
+        });
+        match matched {
+            None => node.cost,
+            Some((_, Adjustment::Cost(cost))) => *cost,
+            Some((_, Adjustment::Scale(scale))) => node.cost * *scale,
+        }
+    }
+
+    // A copy of the egraph with the costs of this model.
+    pub fn apply(&self, egraph: &EGraph) -> EGraph {
+        let mut adjusted = egraph.clone();
+        let mut changed = 0;
+        for (nid, node) in &mut adjusted.nodes {
+            let cost = self.cost(nid, node);
+            if cost != node.cost {
+                node.cost = cost;
+                changed += 1;
+            }
+        }
+        log::info!(
+            "Cost model changed {changed} of {} node costs",
+            egraph.nodes.len()
+        );
+        adjusted
+    }
+
+    // The tree and dag costs of the extraction of `egraph` under this model.
+    pub fn evaluate(
+        &self,
+        egraph: &EGraph,
+        result: &ExtractionResult,
+        roots: &[ClassId],
+    ) -> (Cost, Cost) {
+        let adjusted = self.apply(egraph);
+        (
+            result.tree_cost(&adjusted, roots),
+            result.dag_cost(&adjusted, roots),
+        )
+    }
+}
@@ -0,0 +1,119 @@
+// Like faster-greedy-dag, but for the cost where identical subterms are counted
+// once even if they're in different classes, see `ExtractionResult::cse_dag_cost`.
+// For example, if (f x) is in two classes, choosing it in both costs one f.
+//
+// Subterms are hash-consed as they're built, so each class's cost set records
+// the distinct subterms its term uses, and its cost is the sum over those.
+
+use super::*;
+use rustc_hash::{FxHashMap, FxHashSet};
+
+type TermId = usize;
+
+struct CostSet {
+    // The cost of each distinct subterm.
+    terms: FxHashMap<TermId, Cost>,
+    // The classes used, to avoid cycles.
+    classes: FxHashSet<ClassId>,
+    total: Cost,
+    term: TermId,
+    choice: NodeId,
+}
+
+pub struct CseGreedyDagExtractor;
+
+impl CseGreedyDagExtractor {
+    fn calculate_cost_set(
+        egraph: &EGraph,
+        node_id: &NodeId,
+        costs: &FxHashMap<ClassId, CostSet>,
+        hash_cons: &mut FxHashMap<(String, Vec<TermId>), TermId>,
+    ) -> Option<CostSet> {
+        let node = &egraph[node_id];
+        let cid = egraph.nid_to_cid(node_id);
+
+        let child_sets: Vec<&CostSet> = node
+            .children
+            .iter()
+            .map(|c| &costs[egraph.nid_to_cid(c)])
+            .collect();
+        if child_sets.iter().any(|set| set.classes.contains(cid)) {
+            return None;
+        }
+
+        let key = (
+            node.op.clone(),
+            child_sets.iter().map(|set| set.term).collect(),
+        );
+        let next = hash_cons.len();
+        let term = *hash_cons.entry(key).or_insert(next);
+
+        let mut terms = FxHashMap::default();
+        let mut classes = FxHashSet::default();
+        for set in &child_sets {
+            terms.extend(set.terms.iter().map(|(t, c)| (*t, *c)));
+            classes.extend(set.classes.iter().cloned());
+        }
+        terms.entry(term).or_insert(node.cost);
+        classes.insert(cid.clone());
+
+        Some(CostSet {
+            total: terms.values().sum(),
+            terms,
+            classes,
+            term,
+            choice: node_id.clone(),
+        })
+    }
+}
+
+impl Extractor for CseGreedyDagExtractor {
+    fn extract(&self, egraph: &EGraph, roots: &[ClassId]) -> ExtractionResult {
+        self.extract_with_cache(egraph, roots, &CostCache::new(egraph))
+    }
+
+    fn extract_with_cache(
+        &self,
+        egraph: &EGraph,
+        _roots: &[ClassId],
+        cache: &CostCache,
+    ) -> ExtractionResult {
+        debug_assert!(std::ptr::eq(egraph, cache.egraph()));
+        let parents = cache.parents();
+        let n2c = |nid: &NodeId| egraph.nid_to_cid(nid);
+
+        let mut analysis_pending = super::faster_greedy_dag::UniqueQueue::default();
+        for (node_id, node) in &egraph.nodes {
+            if node.is_leaf() {
+                analysis_pending.insert(node_id.clone());
+            }
+        }
+
+        let mut costs = FxHashMap::<ClassId, CostSet>::default();
+        let mut hash_cons = FxHashMap::<(String, Vec<TermId>), TermId>::default();
+
+        while let Some(node_id) = analysis_pending.pop() {
+            let class_id = n2c(&node_id);
+            let node = &egraph[&node_id];
+            if !node.children.iter().all(|c| costs.contains_key(n2c(c))) {
+                continue;
+            }
+
+            let prev_cost = costs.get(class_id).map_or(INFINITY, |set| set.total);
+            if let Some(cost_set) =
+                Self::calculate_cost_set(egraph, &node_id, &costs, &mut hash_cons)
+            {
+                if cost_set.total < prev_cost {
+                    costs.insert(class_id.clone(), cost_set);
+                    analysis_pending.extend(parents[class_id].iter().cloned());
+                }
+            }
+        }
+
+        let mut result = ExtractionResult::default();
+        for (cid, cost_set) in costs {
+            result.choose(cid, cost_set.choice);
+        }
+        result
+    }
+}
@@ -0,0 +1,360 @@
+/*
+The cycle handling shared by the extractors, the linter and `ExtractionResult`, so
+they agree on what a cycle is, and a fix in one place fixes them all.
+
+Each function works on a graph of classes, given as a function from a class to the
+classes it points to. For an extraction that's the children of the class's chosen
+node, and for an egraph the children of all its nodes, or of some of them.
+
+- `find_cycles` searches depth first, and reports a cycle for each edge back to a
+  class on the search's stack. That's not every simple cycle, of which there can be
+  exponentially many, but there's a cycle if and only if it reports one. It stops
+  after `limit` cycles.
+- `simple_cycles` is Johnson's algorithm, and does find every simple cycle, each
+  once, up to `limit` of them, in time linear in the size of the graph per cycle.
+- `reaches` is whether a class can be reached, e.g. to check that a choice wouldn't
+  close a cycle before making it.
+- `strongly_connected_components` is Tarjan's algorithm. A component with more than
+  one class, or a class that points to itself, has cycles; otherwise none do.
+- `feedback_classes` finds classes without which there are no cycles, by repeatedly
+  taking the class with the most edges within a component that has cycles. It's
+  not the fewest such classes, which is NP-hard, but it's small in practice.
+*/
+
+use super::*;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Status {
+    Doing,
+    Done,
+}
+
+// Each cycle starts at the class an edge points back to, and ends at the class the
+// edge is from.
+pub fn find_cycles<'a, S, F, I>(starts: S, mut successors: F, limit: usize) -> Vec<Vec<ClassId>>
+where
+    S: IntoIterator<Item = &'a ClassId>,
+    F: FnMut(&'a ClassId) -> I,
+    I: IntoIterator<Item = &'a ClassId>,
+{
+    let mut status = FxHashMap::default();
+    let mut cycles = vec![];
+    let mut stack = vec![];
+    for start in starts {
+        cycle_dfs(
+            start,
+            &mut successors,
+            &mut status,
+            &mut cycles,
+            &mut stack,
+            limit,
+        );
+    }
+    cycles
+}
+
+fn cycle_dfs<'a, F, I>(
+    class_id: &'a ClassId,
+    successors: &mut F,
+    status: &mut FxHashMap<&'a ClassId, Status>,
+    cycles: &mut Vec<Vec<ClassId>>,
+    stack: &mut Vec<&'a ClassId>,
+    limit: usize,
+) where
+    F: FnMut(&'a ClassId) -> I,
+    I: IntoIterator<Item = &'a ClassId>,
+{
+    match status.get(class_id) {
+        Some(Status::Done) => (),
+        Some(Status::Doing) => {
+            let pos = stack.iter().position(|id| *id == class_id).unwrap();
+            cycles.push(stack[pos..].iter().map(|id| (*id).clone()).collect());
+        }
+        None => {
+            if cycles.len() >= limit {
+                return;
+            }
+            status.insert(class_id, Status::Doing);
+            stack.push(class_id);
+            let children: Vec<&'a ClassId> = successors(class_id).into_iter().collect();
+            for child in children {
+                cycle_dfs(child, successors, status, cycles, stack, limit);
+            }
+            stack.pop();
+            status.insert(class_id, Status::Done);
+        }
+    }
+}
+
+// Each cycle starts at its first class in `classes`. Only edges between `classes`
+// count.
+pub fn simple_cycles<'a, F, I>(
+    classes: &[&'a ClassId],
+    mut successors: F,
+    limit: usize,
+) -> Vec<Vec<ClassId>>
+where
+    F: FnMut(&'a ClassId) -> I,
+    I: IntoIterator<Item = &'a ClassId>,
+{
+    struct State<'a, 'g> {
+        graph: &'g FxHashMap<&'a ClassId, Vec<&'a ClassId>>,
+        start: &'a ClassId,
+        within: FxHashSet<&'a ClassId>,
+        blocked: FxHashSet<&'a ClassId>,
+        // The classes to unblock when a class is unblocked.
+        unblocks: FxHashMap<&'a ClassId, Vec<&'a ClassId>>,
+        stack: Vec<&'a ClassId>,
+        cycles: Vec<Vec<ClassId>>,
+        limit: usize,
+    }
+
+    fn unblock<'a>(cid: &'a ClassId, state: &mut State<'a, '_>) {
+        state.blocked.remove(cid);
+        for other in state.unblocks.remove(cid).unwrap_or_default() {
+            if state.blocked.contains(other) {
+                unblock(other, state);
+            }
+        }
+    }
+
+    // Whether a cycle through `cid` was found.
+    fn circuit<'a>(cid: &'a ClassId, state: &mut State<'a, '_>) -> bool {
+        let graph = state.graph;
+        let mut found = false;
+        state.stack.push(cid);
+        state.blocked.insert(cid);
+        for &child in &graph[cid] {
+            if state.cycles.len() >= state.limit {
+                break;
+            }
+            if !state.within.contains(child) {
+                continue;
+            }
+            if child == state.start {
+                state
+                    .cycles
+                    .push(state.stack.iter().map(|c| (*c).clone()).collect());
+                found = true;
+            } else if !state.blocked.contains(child) && circuit(child, state) {
+                found = true;
+            }
+        }
+        if found {
+            unblock(cid, state);
+        } else {
+            for &child in &graph[cid] {
+                if state.within.contains(child) {
+                    let unblocks = state.unblocks.entry(child).or_default();
+                    if !unblocks.contains(&cid) {
+                        unblocks.push(cid);
+                    }
+                }
+            }
+        }
+        state.stack.pop();
+        found
+    }
+
+    let graph = restrict(classes, &mut successors);
+    let mut cycles = vec![];
+    for (i, &start) in classes.iter().enumerate() {
+        if cycles.len() >= limit {
+            break;
+        }
+        // The cycles through `start` and later classes, which are all in its
+        // component of the graph of those classes.
+        let Some(component) = tarjan(&classes[i..], &graph)
+            .into_iter()
+            .find(|component| component.contains(&start))
+        else {
+            continue;
+        };
+        let mut state = State {
+            graph: &graph,
+            start,
+            within: component.into_iter().collect(),
+            blocked: Default::default(),
+            unblocks: Default::default(),
+            stack: vec![],
+            cycles,
+            limit,
+        };
+        circuit(start, &mut state);
+        cycles = state.cycles;
+    }
+    cycles
+}
+
+// Whether `target` can be reached from `starts`, which count as reached.
+pub fn reaches<'a, S, F, I>(starts: S, target: &ClassId, mut successors: F) -> bool
+where
+    S: IntoIterator<Item = &'a ClassId>,
+    F: FnMut(&'a ClassId) -> I,
+    I: IntoIterator<Item = &'a ClassId>,
+{
+    let mut todo: Vec<&ClassId> = starts.into_iter().collect();
+    let mut visited = FxHashSet::<&ClassId>::default();
+    while let Some(cid) = todo.pop() {
+        if cid == target {
+            return true;
+        }
+        if visited.insert(cid) {
+            todo.extend(successors(cid));
+        }
+    }
+    false
+}
+
+// Children before parents, so in reverse topological order of the components. Only
+// edges between `classes` count.
+pub fn strongly_connected_components<'a, F, I>(
+    classes: &[&'a ClassId],
+    mut successors: F,
+) -> Vec<Vec<ClassId>>
+where
+    F: FnMut(&'a ClassId) -> I,
+    I: IntoIterator<Item = &'a ClassId>,
+{
+    let graph = restrict(classes, &mut successors);
+    tarjan(classes, &graph)
+        .into_iter()
+        .map(|component| component.into_iter().cloned().collect())
+        .collect()
+}
+
+// Removing these classes from `classes` leaves no cycles between the rest.
+pub fn feedback_classes<'a, F, I>(classes: &[&'a ClassId], mut successors: F) -> Vec<ClassId>
+where
+    F: FnMut(&'a ClassId) -> I,
+    I: IntoIterator<Item = &'a ClassId>,
+{
+    let mut graph = restrict(classes, &mut successors);
+    let mut remaining: Vec<&ClassId> = classes.to_vec();
+    let mut feedback = vec![];
+    loop {
+        let cyclic: Vec<Vec<&ClassId>> = tarjan(&remaining, &graph)
+            .into_iter()
+            .filter(|component| component.len() > 1 || graph[component[0]].contains(&component[0]))
+            .collect();
+        if cyclic.is_empty() {
+            return feedback;
+        }
+        for component in cyclic {
+            let within: FxHashSet<&ClassId> = component.iter().copied().collect();
+            let mut in_degree = FxHashMap::<&ClassId, usize>::default();
+            for cid in &component {
+                for child in graph[cid].iter().filter(|c| within.contains(*c)) {
+                    *in_degree.entry(*child).or_default() += 1;
+                }
+            }
+            let out_degree =
+                |cid: &ClassId| graph[cid].iter().filter(|c| within.contains(*c)).count();
+            let worst = *component
+                .iter()
+                .max_by_key(|cid| in_degree.get(*cid).copied().unwrap_or(0) * out_degree(cid))
+                .unwrap();
+            feedback.push(worst.clone());
+            remaining.retain(|cid| *cid != worst);
+            graph.remove(worst);
+            for children in graph.values_mut() {
+                children.retain(|c| *c != worst);
+            }
+        }
+    }
+}
+
+// The graph with only edges between `classes`, each once.
+fn restrict<'a, F, I>(
+    classes: &[&'a ClassId],
+    successors: &mut F,
+) -> FxHashMap<&'a ClassId, Vec<&'a ClassId>>
+where
+    F: FnMut(&'a ClassId) -> I,
+    I: IntoIterator<Item = &'a ClassId>,
+{
+    let included: FxHashSet<&ClassId> = classes.iter().copied().collect();
+    classes
+        .iter()
+        .map(|&cid| {
+            let mut children: Vec<&ClassId> = successors(cid)
+                .into_iter()
+                .filter(|c| included.contains(c))
+                .collect();
+            children.sort();
+            children.dedup();
+            (cid, children)
+        })
+        .collect()
+}
+
+// The components of the graph between `classes`.
+fn tarjan<'a>(
+    classes: &[&'a ClassId],
+    graph: &FxHashMap<&'a ClassId, Vec<&'a ClassId>>,
+) -> Vec<Vec<&'a ClassId>> {
+    struct State<'a> {
+        next_index: usize,
+        index: FxHashMap<&'a ClassId, usize>,
+        low_link: FxHashMap<&'a ClassId, usize>,
+        stack: Vec<&'a ClassId>,
+        on_stack: FxHashSet<&'a ClassId>,
+        components: Vec<Vec<&'a ClassId>>,
+        included: FxHashSet<&'a ClassId>,
+    }
+
+    fn connect<'a>(
+        cid: &'a ClassId,
+        graph: &FxHashMap<&'a ClassId, Vec<&'a ClassId>>,
+        state: &mut State<'a>,
+    ) {
+        state.index.insert(cid, state.next_index);
+        state.low_link.insert(cid, state.next_index);
+        state.next_index += 1;
+        state.stack.push(cid);
+        state.on_stack.insert(cid);
+
+        for &child in &graph[cid] {
+            if !state.included.contains(child) {
+                continue;
+            }
+            if !state.index.contains_key(child) {
+                connect(child, graph, state);
+                let low = state.low_link[cid].min(state.low_link[child]);
+                state.low_link.insert(cid, low);
+            } else if state.on_stack.contains(child) {
+                let low = state.low_link[cid].min(state.index[child]);
+                state.low_link.insert(cid, low);
+            }
+        }
+
+        if state.low_link[cid] == state.index[cid] {
+            let mut component = vec![];
+            loop {
+                let member = state.stack.pop().unwrap();
+                state.on_stack.remove(member);
+                component.push(member);
+                if member == cid {
+                    break;
+                }
+            }
+            state.components.push(component);
+        }
+    }
+
+    let mut state = State {
+        next_index: 0,
+        index: Default::default(),
+        low_link: Default::default(),
+        stack: vec![],
+        on_stack: Default::default(),
+        components: vec![],
+        included: classes.iter().copied().collect(),
+    };
+    for &cid in classes {
+        if graph.contains_key(cid) && !state.index.contains_key(cid) {
+            connect(cid, graph, &mut state);
+        }
+    }
+    state.components
+}
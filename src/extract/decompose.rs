@@ -0,0 +1,309 @@
+/*
+Splits an extraction into independent subproblems, for extractors like the ILPs whose
+time grows much faster than the egraph, with `--decompose`.
+
+In the graph with an edge from each class to the children classes of each of its
+nodes, a class d splits off if every class reachable from d is dominated by d, that
+is, only reachable from the roots through d. Then no class of d's region is shared
+with the rest of the egraph, nor in a cycle with it, so the cheapest dag for d doesn't
+depend on the rest of the extraction. Starting from the innermost, each region is
+extracted on its own with d as its root, then replaced in the enclosing problem by a
+leaf in d's class that costs as much as the region's dag. The results are stitched
+together, so with a dag-optimal extractor the result is dag optimal too.
+
+Regions with fewer than `min_region_classes` classes aren't split off, as they
+aren't worth an extraction of their own, nor is a region of every reachable class.
+Dominators are found with the iterative algorithm of Cooper, Harvey and Kennedy.
+
+Like the reductions in `preprocess`, this doesn't change the optimal dag cost, but
+unlike them it needs to run the extractor once per region, so it wraps the
+extractor rather than the egraph.
+*/
+
+use super::*;
+
+pub const MIN_REGION_CLASSES: usize = 8;
+
+pub struct DecomposedExtractor<'a> {
+    pub extractor: &'a dyn Extractor,
+    pub min_region_classes: usize,
+}
+
+// The classes reachable from the roots, and the subproblem each belongs to.
+struct Regions<'a> {
+    classes: Vec<&'a ClassId>,
+    index: FxHashMap<&'a ClassId, usize>,
+    // The class whose region's subproblem each class is in, with its own nodes. None
+    // for the problem of the roots.
+    problem: Vec<Option<usize>>,
+    // For each split class, the problem it's a leaf in.
+    enclosing: FxHashMap<usize, Option<usize>>,
+    // Innermost first.
+    splits: Vec<usize>,
+}
+
+impl Extractor for DecomposedExtractor<'_> {
+    fn extract(&self, egraph: &EGraph, roots: &[ClassId]) -> ExtractionResult {
+        let regions = regions(egraph, roots, self.min_region_classes);
+        if regions.splits.is_empty() {
+            return self.extractor.extract(egraph, roots);
+        }
+        log::info!(
+            "Decomposed {} classes into {} subproblems",
+            regions.classes.len(),
+            regions.splits.len() + 1
+        );
+
+        let mut nodes = FxHashMap::<Option<usize>, Vec<&NodeId>>::default();
+        for nid in egraph.nodes.keys() {
+            if let Some(&i) = regions.index.get(egraph.nid_to_cid(nid)) {
+                nodes.entry(regions.problem[i]).or_default().push(nid);
+            }
+        }
+        let mut leaves = FxHashMap::<Option<usize>, Vec<usize>>::default();
+        for &w in &regions.splits {
+            leaves.entry(regions.enclosing[&w]).or_default().push(w);
+        }
+
+        let mut result = ExtractionResult::default();
+        let mut leaf_costs = FxHashMap::<usize, Cost>::default();
+        for problem in regions.splits.iter().map(|&d| Some(d)).chain([None]) {
+            let problem_leaves = leaves.remove(&problem).unwrap_or_default();
+            let sub = subproblem(
+                egraph,
+                &regions,
+                &nodes.remove(&problem).unwrap_or_default(),
+                &problem_leaves,
+                &leaf_costs,
+            );
+            let sub_roots = match problem {
+                Some(d) => vec![regions.classes[d].clone()],
+                None => roots.to_vec(),
+            };
+            let sub_result = self.extractor.extract(&sub, &sub_roots);
+            result.memory_limit_exceeded |= sub_result.memory_limit_exceeded;
+
+            // The leaves' choices are made by their own problems.
+            let leaf_classes: FxHashSet<&ClassId> =
+                problem_leaves.iter().map(|&w| regions.classes[w]).collect();
+            for (cid, nid) in &sub_result.choices {
+                if !leaf_classes.contains(cid) {
+                    result.choose(cid.clone(), nid.clone());
+                }
+            }
+            if let Some(d) = problem {
+                let cost = if sub_result.choices.contains_key(&sub_roots[0]) {
+                    sub_result.dag_cost(&sub, &sub_roots)
+                } else {
+                    INFINITY
+                };
+                leaf_costs.insert(d, cost);
+            }
+        }
+        result
+    }
+}
+
+// The problem's own nodes, and a leaf for each region split off from it, which has
+// the id of the first node of the region's class.
+fn subproblem(
+    egraph: &EGraph,
+    regions: &Regions,
+    nodes: &[&NodeId],
+    leaves: &[usize],
+    leaf_costs: &FxHashMap<usize, Cost>,
+) -> EGraph {
+    let leaf_ids: FxHashMap<usize, NodeId> = leaves
+        .iter()
+        .map(|&w| (w, egraph[regions.classes[w]].nodes[0].clone()))
+        .collect();
+
+    let mut sub = EGraph::default();
+    for &nid in nodes {
+        let mut node = egraph[nid].clone();
+        for child in &mut node.children {
+            if let Some(leaf) = leaf_ids.get(&regions.index[egraph.nid_to_cid(child)]) {
+                *child = leaf.clone();
+            }
+        }
+        sub.add_node(nid.clone(), node);
+    }
+    for &w in leaves {
+        sub.add_node(
+            leaf_ids[&w].clone(),
+            Node {
+                op: format!("region {}", regions.classes[w]),
+                children: vec![],
+                eclass: regions.classes[w].clone(),
+                cost: leaf_costs[&w],
+            },
+        );
+    }
+    sub
+}
+
+fn regions<'a>(egraph: &'a EGraph, roots: &'a [ClassId], min_region_classes: usize) -> Regions<'a> {
+    let egraph_classes = egraph.classes();
+
+    // The reachable classes, with the virtual root, which points to the roots, last.
+    let mut index = FxHashMap::<&ClassId, usize>::default();
+    let mut classes: Vec<&ClassId> = vec![];
+    let mut todo: Vec<&ClassId> = roots.iter().collect();
+    while let Some(cid) = todo.pop() {
+        if !index.contains_key(cid) {
+            index.insert(cid, classes.len());
+            classes.push(cid);
+            for nid in &egraph_classes[cid].nodes {
+                todo.extend(egraph[nid].children.iter().map(|c| egraph.nid_to_cid(c)));
+            }
+        }
+    }
+    let n = classes.len();
+    let virtual_root = n;
+    let mut succ: Vec<Vec<usize>> = classes
+        .iter()
+        .map(|&cid| {
+            let mut children: Vec<usize> = egraph_classes[cid]
+                .nodes
+                .iter()
+                .flat_map(|nid| egraph[nid].children.iter())
+                .map(|c| index[egraph.nid_to_cid(c)])
+                .collect();
+            children.sort();
+            children.dedup();
+            children
+        })
+        .collect();
+    let mut root_indices: Vec<usize> = roots.iter().map(|r| index[r]).collect();
+    root_indices.sort();
+    root_indices.dedup();
+    succ.push(root_indices);
+
+    // Postorder from the virtual root.
+    let mut postorder = vec![];
+    let mut visited = vec![false; n + 1];
+    let mut stack = vec![(virtual_root, 0)];
+    visited[virtual_root] = true;
+    while let Some((u, next)) = stack.last_mut() {
+        let u = *u;
+        if *next < succ[u].len() {
+            let w = succ[u][*next];
+            *next += 1;
+            if !visited[w] {
+                visited[w] = true;
+                stack.push((w, 0));
+            }
+        } else {
+            postorder.push(u);
+            stack.pop();
+        }
+    }
+    let mut post_number = vec![0; n + 1];
+    for (i, &u) in postorder.iter().enumerate() {
+        post_number[u] = i;
+    }
+    let mut preds: Vec<Vec<usize>> = vec![vec![]; n + 1];
+    for (u, children) in succ.iter().enumerate() {
+        for &w in children {
+            preds[w].push(u);
+        }
+    }
+
+    let mut idom = vec![usize::MAX; n + 1];
+    idom[virtual_root] = virtual_root;
+    let intersect = |idom: &[usize], mut a: usize, mut b: usize| {
+        while a != b {
+            while post_number[a] < post_number[b] {
+                a = idom[a];
+            }
+            while post_number[b] < post_number[a] {
+                b = idom[b];
+            }
+        }
+        a
+    };
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for &u in postorder.iter().rev().skip(1) {
+            let mut new_idom = usize::MAX;
+            for &p in &preds[u] {
+                if idom[p] != usize::MAX {
+                    new_idom = if new_idom == usize::MAX {
+                        p
+                    } else {
+                        intersect(&idom, p, new_idom)
+                    };
+                }
+            }
+            if idom[u] != new_idom {
+                idom[u] = new_idom;
+                changed = true;
+            }
+        }
+    }
+
+    // Number the dominator tree in preorder, so each class's region is the classes
+    // numbered from it up to its region's size.
+    let mut dominated: Vec<Vec<usize>> = vec![vec![]; n + 1];
+    for (u, &d) in idom.iter().enumerate().take(n) {
+        dominated[d].push(u);
+    }
+    let mut preorder = vec![];
+    let mut todo = vec![virtual_root];
+    while let Some(u) = todo.pop() {
+        preorder.push(u);
+        todo.extend(dominated[u].iter().rev());
+    }
+    let mut pre_number = vec![0; n + 1];
+    for (i, &u) in preorder.iter().enumerate() {
+        pre_number[u] = i;
+    }
+    let mut size = vec![1; n + 1];
+    // The lowest and highest numbers of the classes pointed to from each region.
+    let mut lowest = pre_number.clone();
+    let mut highest = pre_number.clone();
+    for &u in preorder.iter().rev() {
+        for &w in &succ[u] {
+            lowest[u] = lowest[u].min(pre_number[w]);
+            highest[u] = highest[u].max(pre_number[w]);
+        }
+        if u != virtual_root {
+            let p = idom[u];
+            size[p] += size[u];
+            lowest[p] = lowest[p].min(lowest[u]);
+            highest[p] = highest[p].max(highest[u]);
+        }
+    }
+    let split = |u: usize| {
+        u != virtual_root
+            && size[u] >= min_region_classes
+            && size[u] < n
+            && lowest[u] >= pre_number[u]
+            && highest[u] < pre_number[u] + size[u]
+    };
+
+    let mut problem = vec![None; n + 1];
+    let mut enclosing = FxHashMap::default();
+    let mut splits = vec![];
+    for &u in preorder.iter().skip(1) {
+        let parent_problem = problem[idom[u]];
+        if split(u) {
+            problem[u] = Some(u);
+            enclosing.insert(u, parent_problem);
+            splits.push(u);
+        } else {
+            problem[u] = parent_problem;
+        }
+    }
+    splits.reverse();
+    problem.truncate(n);
+
+    Regions {
+        classes,
+        index,
+        problem,
+        enclosing,
+        splits,
+    }
+}
@@ -0,0 +1,64 @@
+/*
+Converts an extraction into an `egg::RecExpr`, so it can be handed back to an
+egg-based pipeline. The caller picks the `Language`, and each node's op and
+children are parsed with its `FromOp` implementation, just as egg parses terms.
+
+A class used several times is only added once, so the `RecExpr` shares
+subterms the same way the extraction does.
+
+Only built with the `egg-interop` feature.
+*/
+
+use egg::{FromOp, Id, Language, RecExpr};
+
+use super::*;
+
+impl ExtractionResult {
+    // The term chosen for `root`, which must be complete and acyclic, as `check` ensures.
+    pub fn to_rec_expr<L: Language + FromOp>(
+        &self,
+        egraph: &EGraph,
+        root: &ClassId,
+    ) -> anyhow::Result<RecExpr<L>> {
+        let mut expr = RecExpr::default();
+        let mut ids = FxHashMap::<ClassId, Id>::default();
+        let mut path = FxHashSet::<ClassId>::default();
+        self.add_to_rec_expr(egraph, root, &mut expr, &mut ids, &mut path)?;
+        Ok(expr)
+    }
+
+    fn add_to_rec_expr<L: Language + FromOp>(
+        &self,
+        egraph: &EGraph,
+        class_id: &ClassId,
+        expr: &mut RecExpr<L>,
+        ids: &mut FxHashMap<ClassId, Id>,
+        path: &mut FxHashSet<ClassId>,
+    ) -> anyhow::Result<Id> {
+        if let Some(id) = ids.get(class_id) {
+            return Ok(*id);
+        }
+        anyhow::ensure!(
+            path.insert(class_id.clone()),
+            "The term chosen for class {class_id} is cyclic"
+        );
+
+        let node_id = self
+            .choices
+            .get(class_id)
+            .ok_or_else(|| anyhow::anyhow!("No node chosen for class {class_id}"))?;
+        let node = &egraph[node_id];
+        let children = node
+            .children
+            .iter()
+            .map(|c| self.add_to_rec_expr(egraph, egraph.nid_to_cid(c), expr, ids, path))
+            .collect::<anyhow::Result<Vec<Id>>>()?;
+        let enode = L::from_op(&node.op, children)
+            .map_err(|e| anyhow::anyhow!("Failed to parse node {node_id}: {e:?}"))?;
+
+        path.remove(class_id);
+        let id = expr.add(enode);
+        ids.insert(class_id.clone(), id);
+        Ok(id)
+    }
+}
@@ -0,0 +1,49 @@
+/*
+Costs computed with exact rational arithmetic, to check that the float costs,
+which depend on the order they're summed in, and the epsilon comparisons made
+on them, give the same answers. Every finite f64 is a rational, so the node
+costs convert exactly, and only the sums differ.
+
+Only built with the `exact-costs` feature.
+*/
+
+use num_rational::BigRational;
+use num_traits::Zero;
+
+use super::*;
+
+pub fn exact(cost: Cost) -> BigRational {
+    BigRational::from_float(cost.into_inner()).expect("costs must be finite")
+}
+
+impl ExtractionResult {
+    pub fn exact_dag_cost(&self, egraph: &EGraph, roots: &[ClassId]) -> BigRational {
+        self.dag_cost_breakdown(egraph, roots)
+            .into_values()
+            .fold(BigRational::zero(), |sum, cost| sum + exact(cost))
+    }
+
+    pub fn exact_tree_cost(&self, egraph: &EGraph, roots: &[ClassId]) -> BigRational {
+        let mut memo = FxHashMap::<ClassId, BigRational>::default();
+        roots.iter().fold(BigRational::zero(), |sum, root| {
+            sum + self.exact_tree_cost_rec(egraph, root, &mut memo)
+        })
+    }
+
+    fn exact_tree_cost_rec(
+        &self,
+        egraph: &EGraph,
+        class_id: &ClassId,
+        memo: &mut FxHashMap<ClassId, BigRational>,
+    ) -> BigRational {
+        if let Some(cost) = memo.get(class_id) {
+            return cost.clone();
+        }
+        let node = &egraph[&self.choices[class_id]];
+        let cost = node.children.iter().fold(exact(node.cost), |sum, c| {
+            sum + self.exact_tree_cost_rec(egraph, egraph.nid_to_cid(c), memo)
+        });
+        memo.insert(class_id.clone(), cost.clone());
+        cost
+    }
+}
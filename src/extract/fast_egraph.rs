@@ -0,0 +1,540 @@
+/*
+An index-based copy of an egraph for extractors whose inner loops would otherwise
+hash class and node ids.
+
+Classes and nodes are numbered densely, classes in the order of `egraph.classes()`
+and nodes in the order of their class's `nodes`, so a class's nodes have consecutive
+indices. Each node's child classes, each class's nodes and each class's parent nodes
+are flat arrays with offsets, as in a compressed sparse row matrix. An extractor
+keeps its per-class state in vectors indexed the same way, and converts its choices
+back to the egraph's ids at the end with `to_result`.
+
+Building one costs about as much as one pass over the egraph, so it pays off for
+extractors that visit each node more than once.
+
+Indices are newtypes, so a class index can't be used as a node index by mistake. A
+root class without nodes, as a view can leave, can't be extracted, so it's left out
+of `roots`, and the result has no choice for it.
+
+`read_json` builds one straight from a file, one node at a time, without the file's
+text or the egraph in memory, for egraphs too big to load the usual way, see
+`--low-memory`. It removes dead nodes and repeated roots as `schema::parse_egraph`
+does, so it numbers classes and nodes as `new` would for the egraph that reads.
+Strict mode only checks the top-level fields and the version. Extractors that
+implement `FastExtractor` can run on the result, and `costs` scores their choices.
+*/
+
+use super::*;
+use indexmap::IndexSet;
+use serde::de::{DeserializeSeed, Error as _, IgnoredAny, MapAccess, Visitor};
+use serde::Deserialize;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ClassIndex(pub u32);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct NodeIndex(pub u32);
+
+impl ClassIndex {
+    pub fn index(self) -> usize {
+        self.0 as usize
+    }
+}
+
+impl NodeIndex {
+    pub fn index(self) -> usize {
+        self.0 as usize
+    }
+}
+
+pub struct FastEgraph {
+    pub class_ids: Vec<ClassId>,
+    pub node_ids: Vec<NodeId>,
+    pub node_class: Vec<ClassIndex>,
+    pub node_cost: Vec<Cost>,
+    // The cheapest node cost in each class.
+    pub class_min_cost: Vec<Cost>,
+    // Node i's children are children[child_start[i]..child_start[i + 1]], as they are
+    // in the egraph, so repeated children are repeated.
+    child_start: Vec<u32>,
+    children: Vec<ClassIndex>,
+    // Class i's nodes are class_node_start[i]..class_node_start[i + 1].
+    class_node_start: Vec<u32>,
+    // Class i's parents are parents[parent_start[i]..parent_start[i + 1]], each once.
+    parent_start: Vec<u32>,
+    parents: Vec<NodeIndex>,
+    pub roots: Vec<ClassIndex>,
+}
+
+// Extractors that can run on a `FastEgraph` alone.
+pub trait FastExtractor {
+    // The chosen node of each class, if it has one.
+    fn extract_fast(&self, egraph: &FastEgraph) -> Vec<Option<NodeIndex>>;
+}
+
+// See `ExtractionResult::tree_cost`, `dag_cost` and `depth_cost`.
+#[derive(Debug, PartialEq)]
+pub struct FastCosts {
+    pub tree: Cost,
+    pub dag: Cost,
+    pub depth: Cost,
+}
+
+impl FastEgraph {
+    pub fn new(egraph: &EGraph) -> Self {
+        let classes = egraph.classes();
+        let class_index = |cid: &ClassId| ClassIndex(classes.get_index_of(cid).unwrap() as u32);
+
+        let mut fast = FastEgraph {
+            class_ids: Vec::with_capacity(classes.len()),
+            node_ids: Vec::with_capacity(egraph.nodes.len()),
+            node_class: Vec::with_capacity(egraph.nodes.len()),
+            node_cost: Vec::with_capacity(egraph.nodes.len()),
+            class_min_cost: Vec::with_capacity(classes.len()),
+            child_start: vec![0],
+            children: vec![],
+            class_node_start: vec![0],
+            parent_start: vec![],
+            parents: vec![],
+            roots: vec![],
+        };
+        for root in &egraph.root_eclasses {
+            match classes.get_index_of(root) {
+                Some(i) => fast.roots.push(ClassIndex(i as u32)),
+                None => log::warn!("Root class {root} has no nodes, so it isn't extracted"),
+            }
+        }
+        for (i, class) in classes.values().enumerate() {
+            fast.class_ids.push(class.id.clone());
+            let mut min_cost = INFINITY;
+            for nid in &class.nodes {
+                let node = &egraph[nid];
+                fast.node_ids.push(nid.clone());
+                fast.node_class.push(ClassIndex(i as u32));
+                fast.node_cost.push(node.cost);
+                min_cost = min_cost.min(node.cost);
+                fast.children.extend(
+                    node.children
+                        .iter()
+                        .map(|c| class_index(egraph.nid_to_cid(c))),
+                );
+                fast.child_start.push(fast.children.len() as u32);
+            }
+            fast.class_min_cost.push(min_cost);
+            fast.class_node_start.push(fast.node_ids.len() as u32);
+        }
+        fast.index_parents();
+        fast
+    }
+
+    pub fn read_json(path: &str, lenient: bool) -> anyhow::Result<Self> {
+        let file = std::fs::File::open(path)?;
+        let mut de = serde_json::Deserializer::from_reader(std::io::BufReader::new(file));
+        let mut loader = Loader::default();
+        EGraphSeed {
+            loader: &mut loader,
+            lenient,
+        }
+        .deserialize(&mut de)?;
+        de.end()?;
+        if !lenient {
+            if let Some(version) = loader.version {
+                anyhow::ensure!(
+                    version <= crate::schema::SCHEMA_VERSION,
+                    "Schema version {version} is newer than the supported version {}",
+                    crate::schema::SCHEMA_VERSION
+                );
+            }
+        }
+        loader.finish()
+    }
+
+    // Counts each class's parents, then fills them in.
+    fn index_parents(&mut self) {
+        let num_classes = self.num_classes();
+        let mut parent_counts = vec![0u32; num_classes + 1];
+        let mut seen = vec![NodeIndex(u32::MAX); num_classes];
+        for node in self.node_indices() {
+            for &child in self.children(node) {
+                if std::mem::replace(&mut seen[child.index()], node) != node {
+                    parent_counts[child.index() + 1] += 1;
+                }
+            }
+        }
+        for i in 0..num_classes {
+            parent_counts[i + 1] += parent_counts[i];
+        }
+        self.parent_start = parent_counts.clone();
+        self.parents = vec![NodeIndex(0); *parent_counts.last().unwrap() as usize];
+        seen.fill(NodeIndex(u32::MAX));
+        for node in (0..self.num_nodes() as u32).map(NodeIndex) {
+            let (start, end) = (
+                self.child_start[node.index()] as usize,
+                self.child_start[node.index() + 1] as usize,
+            );
+            for k in start..end {
+                let child = self.children[k].index();
+                if std::mem::replace(&mut seen[child], node) != node {
+                    self.parents[parent_counts[child] as usize] = node;
+                    parent_counts[child] += 1;
+                }
+            }
+        }
+    }
+
+    pub fn num_classes(&self) -> usize {
+        self.class_ids.len()
+    }
+
+    pub fn num_nodes(&self) -> usize {
+        self.node_ids.len()
+    }
+
+    pub fn class_indices(&self) -> impl Iterator<Item = ClassIndex> {
+        (0..self.num_classes() as u32).map(ClassIndex)
+    }
+
+    pub fn node_indices(&self) -> impl Iterator<Item = NodeIndex> {
+        (0..self.num_nodes() as u32).map(NodeIndex)
+    }
+
+    pub fn children(&self, node: NodeIndex) -> &[ClassIndex] {
+        let node = node.index();
+        &self.children[self.child_start[node] as usize..self.child_start[node + 1] as usize]
+    }
+
+    pub fn nodes(&self, class: ClassIndex) -> impl Iterator<Item = NodeIndex> {
+        let class = class.index();
+        (self.class_node_start[class]..self.class_node_start[class + 1]).map(NodeIndex)
+    }
+
+    pub fn parents(&self, class: ClassIndex) -> &[NodeIndex] {
+        let class = class.index();
+        &self.parents[self.parent_start[class] as usize..self.parent_start[class + 1] as usize]
+    }
+
+    // The extraction with each class's chosen node, if it has one, in terms of the
+    // egraph's ids.
+    pub fn to_result(&self, choices: &[Option<NodeIndex>]) -> ExtractionResult {
+        debug_assert_eq!(choices.len(), self.num_classes());
+        let mut result = ExtractionResult::default();
+        for (class, choice) in choices.iter().enumerate() {
+            if let Some(node) = choice {
+                result.choose(
+                    self.class_ids[class].clone(),
+                    self.node_ids[node.index()].clone(),
+                );
+            }
+        }
+        result
+    }
+
+    // Panics, as `ExtractionResult::check` would, if a class the roots need has no
+    // choice, or the choices have a cycle.
+    pub fn costs(&self, choices: &[Option<NodeIndex>]) -> FastCosts {
+        #[derive(Clone, Copy, PartialEq)]
+        enum Visit {
+            Todo,
+            Doing,
+            Done,
+        }
+
+        // Depth first, with an explicit stack, as big egraphs can be very deep.
+        let mut visit = vec![Visit::Todo; self.num_classes()];
+        let mut tree = vec![Cost::default(); self.num_classes()];
+        let mut depth = vec![Cost::default(); self.num_classes()];
+        let mut dag = Cost::default();
+        let mut stack: Vec<(ClassIndex, bool)> = self.roots.iter().map(|&r| (r, false)).collect();
+        while let Some((class, children_done)) = stack.pop() {
+            let c = class.index();
+            let node = choices[c].unwrap_or_else(|| panic!("No choice for {}", self.class_ids[c]));
+            if children_done {
+                let children = self.children(node);
+                tree[c] = self.node_cost[node.index()]
+                    + children.iter().map(|&k| tree[k.index()]).sum::<Cost>();
+                depth[c] = self.node_cost[node.index()]
+                    + children
+                        .iter()
+                        .map(|&k| depth[k.index()])
+                        .max()
+                        .unwrap_or_default();
+                visit[c] = Visit::Done;
+                continue;
+            }
+            match visit[c] {
+                Visit::Done => continue,
+                Visit::Doing => panic!("Cycle through {}", self.class_ids[c]),
+                Visit::Todo => (),
+            }
+            visit[c] = Visit::Doing;
+            dag += self.node_cost[node.index()];
+            stack.push((class, true));
+            for &child in self.children(node) {
+                match visit[child.index()] {
+                    Visit::Doing => panic!("Cycle through {}", self.class_ids[child.index()]),
+                    Visit::Todo => stack.push((child, false)),
+                    Visit::Done => (),
+                }
+            }
+        }
+        FastCosts {
+            tree: self.roots.iter().map(|&r| tree[r.index()]).sum(),
+            dag,
+            depth: self
+                .roots
+                .iter()
+                .map(|&r| depth[r.index()])
+                .max()
+                .unwrap_or_default(),
+        }
+    }
+}
+
+// What's kept of the egraph while it's read: the ids, numbered in the order they're
+// first seen, and each node's class, cost and children. A node can be seen as a child
+// before it's read.
+#[derive(Default)]
+struct Loader {
+    node_ids: IndexSet<NodeId>,
+    class_ids: IndexSet<ClassId>,
+    // Indexed like `node_ids`, None until the node is read.
+    nodes: Vec<Option<LoadedNode>>,
+    // The node indices of the nodes' children.
+    children: Vec<u32>,
+    // The nodes that have been read, in the order they were.
+    read: Vec<u32>,
+    roots: Vec<String>,
+    version: Option<u32>,
+}
+
+struct LoadedNode {
+    class: u32,
+    cost: Cost,
+    children: std::ops::Range<u32>,
+}
+
+// The fields of a node that extraction needs.
+#[derive(Deserialize)]
+struct StreamedNode {
+    #[allow(dead_code)]
+    op: IgnoredAny,
+    children: Vec<String>,
+    eclass: String,
+    cost: f64,
+}
+
+impl Loader {
+    fn add(&mut self, id: String, node: StreamedNode) -> anyhow::Result<()> {
+        let (index, _) = self.node_ids.insert_full(NodeId::from(id));
+        self.nodes.resize_with(self.node_ids.len(), || None);
+        anyhow::ensure!(
+            self.nodes[index].is_none(),
+            "Node {} appears twice",
+            self.node_ids[index]
+        );
+        let start = self.children.len() as u32;
+        for child in node.children {
+            let (child, _) = self.node_ids.insert_full(NodeId::from(child));
+            self.children.push(child as u32);
+        }
+        self.nodes.resize_with(self.node_ids.len(), || None);
+        self.nodes[index] = Some(LoadedNode {
+            class: self.class_ids.insert_full(ClassId::from(node.eclass)).0 as u32,
+            cost: Cost::new(node.cost)?,
+            children: start..self.children.len() as u32,
+        });
+        self.read.push(index as u32);
+        Ok(())
+    }
+
+    fn finish(self) -> anyhow::Result<FastEgraph> {
+        // A node is dead if a child was never read, or is in a class with no live
+        // nodes, see `schema::remove_dead_nodes`.
+        let mut live = vec![false; self.nodes.len()];
+        let mut live_in_class = vec![0u32; self.class_ids.len()];
+        for &i in &self.read {
+            live[i as usize] = true;
+            live_in_class[self.nodes[i as usize].as_ref().unwrap().class as usize] += 1;
+        }
+        loop {
+            let mut changed = false;
+            for &i in &self.read {
+                let node = self.nodes[i as usize].as_ref().unwrap();
+                if !live[i as usize] {
+                    continue;
+                }
+                let children =
+                    &self.children[node.children.start as usize..node.children.end as usize];
+                let dead = children
+                    .iter()
+                    .any(|&child| match &self.nodes[child as usize] {
+                        None => true,
+                        Some(child) => live_in_class[child.class as usize] == 0,
+                    });
+                if dead {
+                    live[i as usize] = false;
+                    live_in_class[node.class as usize] -= 1;
+                    changed = true;
+                }
+            }
+            if !changed {
+                break;
+            }
+        }
+        let dead = self.read.iter().filter(|&&i| !live[i as usize]).count();
+        if dead > 0 {
+            log::warn!("Removed {dead} nodes that depend on classes without nodes");
+        }
+
+        // Classes are numbered in the order of their first live node, and nodes by
+        // class, then in the order they were read.
+        let mut class_index = vec![u32::MAX; self.class_ids.len()];
+        let mut num_classes = 0;
+        for &i in self.read.iter().filter(|&&i| live[i as usize]) {
+            let class = self.nodes[i as usize].as_ref().unwrap().class as usize;
+            if class_index[class] == u32::MAX {
+                class_index[class] = num_classes;
+                num_classes += 1;
+            }
+        }
+        let mut class_node_start = vec![0u32; num_classes as usize + 1];
+        for &i in self.read.iter().filter(|&&i| live[i as usize]) {
+            let class = self.nodes[i as usize].as_ref().unwrap().class as usize;
+            class_node_start[class_index[class] as usize + 1] += 1;
+        }
+        for c in 0..num_classes as usize {
+            class_node_start[c + 1] += class_node_start[c];
+        }
+        let mut next = class_node_start.clone();
+        let mut order = vec![0u32; *class_node_start.last().unwrap() as usize];
+        for &i in self.read.iter().filter(|&&i| live[i as usize]) {
+            let class = self.nodes[i as usize].as_ref().unwrap().class as usize;
+            let slot = &mut next[class_index[class] as usize];
+            order[*slot as usize] = i;
+            *slot += 1;
+        }
+
+        let mut fast = FastEgraph {
+            class_ids: vec![],
+            node_ids: Vec::with_capacity(order.len()),
+            node_class: Vec::with_capacity(order.len()),
+            node_cost: Vec::with_capacity(order.len()),
+            class_min_cost: vec![INFINITY; num_classes as usize],
+            child_start: vec![0],
+            children: vec![],
+            class_node_start,
+            parent_start: vec![],
+            parents: vec![],
+            roots: vec![],
+        };
+        let mut class_ids = vec![None; num_classes as usize];
+        for (old, &new) in class_index.iter().enumerate() {
+            if new != u32::MAX {
+                class_ids[new as usize] = Some(self.class_ids[old].clone());
+            }
+        }
+        fast.class_ids = class_ids.into_iter().map(Option::unwrap).collect();
+        for &i in &order {
+            let node = self.nodes[i as usize].as_ref().unwrap();
+            let class = class_index[node.class as usize];
+            fast.node_ids.push(self.node_ids[i as usize].clone());
+            fast.node_class.push(ClassIndex(class));
+            fast.node_cost.push(node.cost);
+            let min_cost = &mut fast.class_min_cost[class as usize];
+            *min_cost = (*min_cost).min(node.cost);
+            for &child in &self.children[node.children.start as usize..node.children.end as usize] {
+                let child_class = self.nodes[child as usize].as_ref().unwrap().class;
+                fast.children
+                    .push(ClassIndex(class_index[child_class as usize]));
+            }
+            fast.child_start.push(fast.children.len() as u32);
+        }
+
+        // Repeated roots are dropped, as in `schema::normalize_roots`.
+        for root in self.roots {
+            let class = self
+                .class_ids
+                .get_index_of(&ClassId::from(root.clone()))
+                .map(|old| class_index[old])
+                .filter(|&new| new != u32::MAX)
+                .map(ClassIndex)
+                .ok_or_else(|| anyhow::anyhow!("Root class {root} has no nodes"))?;
+            if !fast.roots.contains(&class) {
+                fast.roots.push(class);
+            }
+        }
+        fast.index_parents();
+        Ok(fast)
+    }
+}
+
+struct EGraphSeed<'a> {
+    loader: &'a mut Loader,
+    lenient: bool,
+}
+
+impl<'de> DeserializeSeed<'de> for EGraphSeed<'_> {
+    type Value = ();
+
+    fn deserialize<D: serde::Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+        deserializer.deserialize_map(self)
+    }
+}
+
+impl<'de> Visitor<'de> for EGraphSeed<'_> {
+    type Value = ();
+
+    fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str("an egraph")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<(), A::Error> {
+        while let Some(key) = map.next_key::<String>()? {
+            match key.as_str() {
+                "nodes" => map.next_value_seed(NodesSeed(&mut *self.loader))?,
+                "root_eclasses" => self.loader.roots = map.next_value()?,
+                "version" => self.loader.version = map.next_value()?,
+                "class_data" | "comment" => {
+                    map.next_value::<IgnoredAny>()?;
+                }
+                _ if self.lenient => {
+                    map.next_value::<IgnoredAny>()?;
+                }
+                _ => {
+                    return Err(A::Error::custom(format!(
+                        "Unknown field {key} in strict mode, --lenient skips this check"
+                    )))
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+struct NodesSeed<'a>(&'a mut Loader);
+
+impl<'de> DeserializeSeed<'de> for NodesSeed<'_> {
+    type Value = ();
+
+    fn deserialize<D: serde::Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+        deserializer.deserialize_map(self)
+    }
+}
+
+impl<'de> Visitor<'de> for NodesSeed<'_> {
+    type Value = ();
+
+    fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str("a map of node ids to nodes")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<(), A::Error> {
+        while let Some(id) = map.next_key::<String>()? {
+            let node: StreamedNode = map.next_value()?;
+            self.0
+                .add(id, node)
+                .map_err(|e| A::Error::custom(format!("{e:#}")))?;
+        }
+        Ok(())
+    }
+}
@@ -16,52 +16,194 @@ use super::*;
 /// in a work list (UniqueQueue).
 pub struct FasterBottomUpExtractor;
 
+/// The faster bottom-up extractor, but a class whose cost has improved
+/// `revisit_cap` times stops queueing its parents when it improves again.
+/// On adversarial egraphs a class can otherwise improve thousands of times, each
+/// time revisiting all its parents. Once the work list is empty, if any class hit
+/// the cap, passes over all the nodes are made until nothing changes, so the
+/// result is the same fixed point as without the cap.
+#[doc(hidden)] // experimental
+pub struct CappedBottomUpExtractor {
+    pub revisit_cap: usize,
+}
+
+impl Extractor for CappedBottomUpExtractor {
+    fn extract(&self, egraph: &EGraph, roots: &[ClassId]) -> ExtractionResult {
+        self.extract_with_cache(egraph, roots, &CostCache::new(egraph))
+    }
+
+    fn extract_with_cache(
+        &self,
+        egraph: &EGraph,
+        _roots: &[ClassId],
+        cache: &CostCache,
+    ) -> ExtractionResult {
+        debug_assert!(std::ptr::eq(egraph, cache.egraph()));
+        let result = FasterBottomUpExtractor::extract_capped(
+            egraph,
+            cache,
+            None,
+            None,
+            Some(self.revisit_cap),
+        )
+        .into_owned();
+        soundness::check_tree_optimal(egraph, &result);
+        result
+    }
+}
+
 impl Extractor for FasterBottomUpExtractor {
-    fn extract(&self, egraph: &EGraph, _roots: &[ClassId]) -> ExtractionResult {
-        let mut parents = IndexMap::<ClassId, Vec<NodeId>>::with_capacity(egraph.classes().len());
-        let n2c = |nid: &NodeId| egraph.nid_to_cid(nid);
-        let mut analysis_pending = UniqueQueue::default();
+    fn extract(&self, egraph: &EGraph, roots: &[ClassId]) -> ExtractionResult {
+        self.extract_with_cache(egraph, roots, &CostCache::new(egraph))
+    }
 
-        for class in egraph.classes().values() {
-            parents.insert(class.id.clone(), Vec::new());
-        }
+    fn extract_with_cache(
+        &self,
+        egraph: &EGraph,
+        _roots: &[ClassId],
+        cache: &CostCache,
+    ) -> ExtractionResult {
+        let result = self.extract_ref(egraph, cache).into_owned();
+        soundness::check_tree_optimal(egraph, &result);
+        result
+    }
 
-        for class in egraph.classes().values() {
-            for node in &class.nodes {
-                for c in &egraph[node].children {
-                    // compute parents of this enode
-                    parents[n2c(c)].push(node.clone());
-                }
+    fn extract_with_hint(
+        &self,
+        egraph: &EGraph,
+        _roots: &[ClassId],
+        hint: &ExtractionResult,
+    ) -> ExtractionResult {
+        Self::extract_capped(egraph, &CostCache::new(egraph), Some(hint), None, None).into_owned()
+    }
 
-                // start the analysis from leaves
-                if egraph[node].is_leaf() {
-                    analysis_pending.insert(node.clone());
-                }
+    // Skips the nodes outside the view, rather than copying the egraph.
+    fn extract_view(&self, view: &view::EGraphView, _roots: &[ClassId]) -> ExtractionResult {
+        let egraph = view.egraph();
+        Self::extract_capped(egraph, &CostCache::new(egraph), None, Some(view), None).into_owned()
+    }
+}
+
+impl FasterBottomUpExtractor {
+    // Like `extract_with_cache`, but without cloning any ids.
+    pub fn extract_ref<'a>(
+        &self,
+        egraph: &'a EGraph,
+        cache: &CostCache,
+    ) -> ExtractionResultRef<'a> {
+        debug_assert!(std::ptr::eq(egraph, cache.egraph()));
+        Self::extract_capped(egraph, cache, None, None, None)
+    }
+
+    // The hint's choices are used as the initial costs, which are then improved on
+    // until we reach the same fixed point as without a hint.
+    fn extract_capped<'a>(
+        egraph: &'a EGraph,
+        cache: &CostCache,
+        hint: Option<&ExtractionResult>,
+        view: Option<&view::EGraphView>,
+        revisit_cap: Option<usize>,
+    ) -> ExtractionResultRef<'a> {
+        let in_view = |nid: &NodeId| view.is_none_or(|view| view.contains(nid));
+        let parents = cache.parents();
+        let mut analysis_pending = UniqueQueue::default();
+
+        for (node_id, node) in &egraph.nodes {
+            // start the analysis from leaves
+            if node.is_leaf() && in_view(node_id) {
+                analysis_pending.insert(node_id);
             }
         }
 
-        let mut result = ExtractionResult::default();
-        let mut costs = FxHashMap::<ClassId, Cost>::with_capacity_and_hasher(
+        let mut result = ExtractionResultRef::default();
+        let mut costs = FxHashMap::<&ClassId, Cost>::with_capacity_and_hasher(
             egraph.classes().len(),
             Default::default(),
         );
 
+        if let Some(hint) = hint {
+            for (class_id, cost) in hint.choice_tree_costs(egraph) {
+                let (node_id, node) = egraph
+                    .nodes
+                    .get_key_value(&hint.choices[&class_id])
+                    .unwrap();
+                // The parents wouldn't otherwise be visited if this class's cost never changes.
+                analysis_pending.extend(parents[&class_id].iter());
+                result.choose(&node.eclass, node_id);
+                costs.insert(&node.eclass, cost);
+            }
+        }
+
+        // How many times each class's cost improved, only counted when it's needed, as
+        // this is the hot loop.
+        let count_improvements = revisit_cap.is_some() || log::log_enabled!(log::Level::Info);
+        let mut improvements = FxHashMap::<&ClassId, usize>::default();
+        let mut capped = false;
+
         while let Some(node_id) = analysis_pending.pop() {
-            let class_id = n2c(&node_id);
-            let node = &egraph[&node_id];
+            let (node_id, node) = egraph.nodes.get_key_value(node_id).unwrap();
+            if !in_view(node_id) {
+                continue;
+            }
+            let class_id = &node.eclass;
             let prev_cost = costs.get(class_id).unwrap_or(&INFINITY);
-            let cost = result.node_sum_cost(egraph, node, &costs);
+            let cost = node_sum_cost(egraph, node, &costs);
             if cost < *prev_cost {
-                result.choose(class_id.clone(), node_id.clone());
-                costs.insert(class_id.clone(), cost);
-                analysis_pending.extend(parents[class_id].iter().cloned());
+                result.choose(class_id, node_id);
+                costs.insert(class_id, cost);
+                let within_cap = !count_improvements || {
+                    let count = improvements.entry(class_id).or_default();
+                    *count += 1;
+                    revisit_cap.is_none_or(|cap| *count <= cap)
+                };
+                if within_cap {
+                    analysis_pending.extend(parents[class_id].iter());
+                } else {
+                    capped = true;
+                }
             }
         }
 
+        if log::log_enabled!(log::Level::Info) {
+            let mut worst: Vec<_> = improvements.iter().collect();
+            worst.sort_by_key(|(_, count)| std::cmp::Reverse(**count));
+            worst.truncate(10);
+            log::info!("Most improved classes: {worst:?}");
+        }
+
+        // The classes that hit the cap may have parents that are out of date.
+        let mut passes = 0;
+        while capped {
+            capped = false;
+            passes += 1;
+            for (node_id, node) in egraph.nodes.iter().filter(|(nid, _)| in_view(nid)) {
+                let class_id = &node.eclass;
+                let cost = node_sum_cost(egraph, node, &costs);
+                if &cost < costs.get(class_id).unwrap_or(&INFINITY) {
+                    result.choose(class_id, node_id);
+                    costs.insert(class_id, cost);
+                    capped = true;
+                }
+            }
+        }
+        if passes > 0 {
+            log::info!("Revisit cap hit, {passes} final passes");
+        }
+
         result
     }
 }
 
+// `ExtractionResult::node_sum_cost`, for costs keyed by borrowed class ids.
+fn node_sum_cost(egraph: &EGraph, node: &Node, costs: &FxHashMap<&ClassId, Cost>) -> Cost {
+    node.cost
+        + node
+            .children
+            .iter()
+            .map(|n| costs.get(egraph.nid_to_cid(n)).unwrap_or(&INFINITY))
+            .sum::<Cost>()
+}
+
 /** A data structure to maintain a queue of unique elements.
 
 Notably, insert/pop operations have O(1) expected amortized runtime complexity.
@@ -5,15 +5,149 @@
 use super::*;
 use rustc_hash::{FxHashMap, FxHashSet};
 
+// The classes in a cost set are their indices in `egraph.classes()`, so the sets of a
+// node's children are merged by walking them in order rather than by hashing. On wide
+// egraphs, like tensat's, that's most of the extraction time.
+type ClassIndex = u32;
+
 struct CostSet {
-    // It's slightly faster if this is an HashMap rather than an fxHashMap.
-    costs: HashMap<ClassId, Cost>,
+    // Sorted by class index, with at most one entry per class.
+    costs: Vec<(ClassIndex, Cost)>,
     total: Cost,
     choice: NodeId,
 }
 
+impl CostSet {
+    fn contains(&self, class: ClassIndex) -> bool {
+        self.costs.binary_search_by_key(&class, |&(c, _)| c).is_ok()
+    }
+}
+
+fn class_index(egraph: &EGraph, cid: &ClassId) -> ClassIndex {
+    egraph.classes().get_index_of(cid).unwrap() as ClassIndex
+}
+
+// The union of two sorted cost sets. Where both have a class, `b`'s cost is kept.
+fn merge(a: &[(ClassIndex, Cost)], b: &[(ClassIndex, Cost)]) -> Vec<(ClassIndex, Cost)> {
+    let mut merged = Vec::with_capacity(a.len() + b.len());
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() && j < b.len() {
+        match a[i].0.cmp(&b[j].0) {
+            std::cmp::Ordering::Less => {
+                merged.push(a[i]);
+                i += 1;
+            }
+            std::cmp::Ordering::Greater => {
+                merged.push(b[j]);
+                j += 1;
+            }
+            std::cmp::Ordering::Equal => {
+                merged.push(b[j]);
+                i += 1;
+                j += 1;
+            }
+        }
+    }
+    merged.extend_from_slice(&a[i..]);
+    merged.extend_from_slice(&b[j..]);
+    merged
+}
+
 pub struct FasterGreedyDagExtractor;
 
+// Like `FasterGreedyDagExtractor`, which takes nodes off its worklist first in
+// first out, but taking them in the given order instead.
+pub struct OrderedGreedyDagExtractor {
+    pub order: NodeOrder,
+}
+
+// The order the greedy extractor processes nodes in, which changes which of
+// several equally cheap cost sets it keeps, and so the quality of the result.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NodeOrder {
+    // First in first out.
+    Fifo,
+    // Nodes in classes with more parents first.
+    FanOut,
+    // Nodes with the cheapest tree below them first.
+    MinSubtreeCost,
+    // Nodes in classes whose children come earlier first.
+    Topological,
+    // Nodes in an order shuffled with the seed.
+    Random(u64),
+}
+
+impl NodeOrder {
+    pub const ALL: [NodeOrder; 5] = [
+        NodeOrder::Fifo,
+        NodeOrder::FanOut,
+        NodeOrder::MinSubtreeCost,
+        NodeOrder::Topological,
+        NodeOrder::Random(0),
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            NodeOrder::Fifo => "fifo",
+            NodeOrder::FanOut => "fan-out",
+            NodeOrder::MinSubtreeCost => "min-subtree-cost",
+            NodeOrder::Topological => "topological",
+            NodeOrder::Random(_) => "random",
+        }
+    }
+
+    // Lower ranks are processed first, None means first in first out.
+    fn ranks(self, egraph: &EGraph, cache: &CostCache) -> Option<FxHashMap<NodeId, usize>> {
+        let n2c = |nid: &NodeId| egraph.nid_to_cid(nid);
+        let mut nodes: Vec<&NodeId> = egraph.nodes.keys().collect();
+        match self {
+            NodeOrder::Fifo => return None,
+            NodeOrder::FanOut => {
+                let parents = cache.parents();
+                nodes.sort_by_key(|nid| std::cmp::Reverse(parents[n2c(*nid)].len()));
+            }
+            NodeOrder::MinSubtreeCost => {
+                let tree_costs = super::faster_bottom_up::FasterBottomUpExtractor
+                    .extract_with_cache(egraph, &egraph.root_eclasses, cache)
+                    .choice_tree_costs(egraph);
+                let result = ExtractionResult::default();
+                nodes.sort_by_key(|nid| result.node_sum_cost(egraph, &egraph[*nid], &tree_costs));
+            }
+            NodeOrder::Topological => {
+                let position: FxHashMap<&ClassId, usize> = cache
+                    .topo_order()
+                    .iter()
+                    .enumerate()
+                    .map(|(i, cid)| (cid, i))
+                    .collect();
+                nodes.sort_by_key(|nid| position[n2c(*nid)]);
+            }
+            NodeOrder::Random(seed) => {
+                use rand::{seq::SliceRandom, SeedableRng};
+                nodes.shuffle(&mut rand::rngs::StdRng::seed_from_u64(seed));
+            }
+        }
+        Some(
+            nodes
+                .into_iter()
+                .enumerate()
+                .map(|(rank, nid)| (nid.clone(), rank))
+                .collect(),
+        )
+    }
+}
+
+impl std::str::FromStr for NodeOrder {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        NodeOrder::ALL
+            .into_iter()
+            .find(|order| order.name() == s)
+            .ok_or_else(|| format!("Unknown node order: {s}"))
+    }
+}
+
 impl FasterGreedyDagExtractor {
     fn calculate_cost_set(
         egraph: &EGraph,
@@ -23,10 +157,11 @@ impl FasterGreedyDagExtractor {
     ) -> CostSet {
         let node = &egraph[&node_id];
         let cid = egraph.nid_to_cid(&node_id);
+        let index = class_index(egraph, cid);
 
         if node.children.is_empty() {
             return CostSet {
-                costs: HashMap::from([(cid.clone(), node.cost)]),
+                costs: vec![(index, node.cost)],
                 total: node.cost,
                 choice: node_id.clone(),
             };
@@ -54,30 +189,30 @@ impl FasterGreedyDagExtractor {
             };
         }
 
-        // Clone the biggest set and insert the others into it.
+        // Start from the biggest set and merge the others into it.
         let id_of_biggest = childrens_classes
             .iter()
             .max_by_key(|s| costs.get(s).unwrap().costs.len())
             .unwrap();
-        let mut result = costs.get(&id_of_biggest).unwrap().costs.clone();
+        let biggest = costs.get(&id_of_biggest).unwrap();
+        let contains = childrens_classes
+            .iter()
+            .any(|child_cid| costs.get(child_cid).unwrap().contains(index));
+        let mut result = biggest.costs.clone();
         for child_cid in &childrens_classes {
             if child_cid == id_of_biggest {
                 continue;
             }
 
             let next_cost = &costs.get(child_cid).unwrap().costs;
-            for (key, value) in next_cost.iter() {
-                result.insert(key.clone(), value.clone());
-            }
+            result = merge(&result, next_cost);
         }
 
-        let contains = result.contains_key(&cid);
-        result.insert(cid.clone(), node.cost);
-
         let result_cost = if contains {
             INFINITY
         } else {
-            result.values().sum()
+            result = merge(&result, &[(index, node.cost)]);
+            result.iter().map(|&(_, cost)| cost).sum()
         };
 
         return CostSet {
@@ -89,22 +224,84 @@ impl FasterGreedyDagExtractor {
 }
 
 impl Extractor for FasterGreedyDagExtractor {
-    fn extract(&self, egraph: &EGraph, _roots: &[ClassId]) -> ExtractionResult {
-        let mut parents = IndexMap::<ClassId, Vec<NodeId>>::with_capacity(egraph.classes().len());
-        let n2c = |nid: &NodeId| egraph.nid_to_cid(nid);
-        let mut analysis_pending = UniqueQueue::default();
+    fn extract(&self, egraph: &EGraph, roots: &[ClassId]) -> ExtractionResult {
+        self.extract_with_cache(egraph, roots, &CostCache::new(egraph))
+    }
 
-        for class in egraph.classes().values() {
-            parents.insert(class.id.clone(), Vec::new());
-        }
+    fn extract_with_cache(
+        &self,
+        egraph: &EGraph,
+        roots: &[ClassId],
+        cache: &CostCache,
+    ) -> ExtractionResult {
+        debug_assert!(std::ptr::eq(egraph, cache.egraph()));
+        Self::extract_seeded(egraph, roots, cache, None, NodeOrder::Fifo)
+    }
+
+    fn extract_with_hint(
+        &self,
+        egraph: &EGraph,
+        roots: &[ClassId],
+        hint: &ExtractionResult,
+    ) -> ExtractionResult {
+        let cache = CostCache::new(egraph);
+        Self::extract_seeded(egraph, roots, &cache, Some(hint), NodeOrder::Fifo)
+    }
+}
+
+impl Extractor for OrderedGreedyDagExtractor {
+    fn extract(&self, egraph: &EGraph, roots: &[ClassId]) -> ExtractionResult {
+        self.extract_with_cache(egraph, roots, &CostCache::new(egraph))
+    }
+
+    fn extract_with_cache(
+        &self,
+        egraph: &EGraph,
+        roots: &[ClassId],
+        cache: &CostCache,
+    ) -> ExtractionResult {
+        debug_assert!(std::ptr::eq(egraph, cache.egraph()));
+        FasterGreedyDagExtractor::extract_seeded(egraph, roots, cache, None, self.order)
+    }
+
+    fn extract_with_hint(
+        &self,
+        egraph: &EGraph,
+        roots: &[ClassId],
+        hint: &ExtractionResult,
+    ) -> ExtractionResult {
+        let cache = CostCache::new(egraph);
+        FasterGreedyDagExtractor::extract_seeded(egraph, roots, &cache, Some(hint), self.order)
+    }
+
+    fn extract_with_seed(&self, egraph: &EGraph, roots: &[ClassId], seed: u64) -> ExtractionResult {
+        let order = match self.order {
+            NodeOrder::Random(_) => NodeOrder::Random(seed),
+            order => order,
+        };
+        OrderedGreedyDagExtractor { order }.extract(egraph, roots)
+    }
+}
+
+impl FasterGreedyDagExtractor {
+    // The hint's choices become the initial cost sets, which are kept unless a
+    // cheaper cost set is found for the class.
+    fn extract_seeded(
+        egraph: &EGraph,
+        roots: &[ClassId],
+        cache: &CostCache,
+        hint: Option<&ExtractionResult>,
+        order: NodeOrder,
+    ) -> ExtractionResult {
+        let parents = cache.parents();
+        let n2c = |nid: &NodeId| egraph.nid_to_cid(nid);
+        let mut analysis_pending = match order.ranks(egraph, cache) {
+            None => Worklist::Fifo(UniqueQueue::default()),
+            Some(ranks) => Worklist::Ranked(RankedQueue::new(ranks)),
+        };
 
         for class in egraph.classes().values() {
             for node in &class.nodes {
-                for c in &egraph[node].children {
-                    // compute parents of this enode
-                    parents[n2c(c)].push(node.clone());
-                }
-
                 // start the analysis from leaves
                 if egraph[node].is_leaf() {
                     analysis_pending.insert(node.clone());
@@ -118,7 +315,39 @@ impl Extractor for FasterGreedyDagExtractor {
             Default::default(),
         );
 
+        if let Some(hint) = hint {
+            // Children come before their parents, so their cost sets are usually already there.
+            for class_id in hint.choice_tree_costs(egraph).keys() {
+                let node_id = hint.choices[class_id].clone();
+                if !egraph[&node_id]
+                    .children
+                    .iter()
+                    .all(|c| costs.contains_key(n2c(c)))
+                {
+                    continue;
+                }
+                let cost_set = Self::calculate_cost_set(egraph, node_id, &costs, INFINITY);
+                if cost_set.total < INFINITY {
+                    // The parents wouldn't otherwise be visited if this class's cost never changes.
+                    analysis_pending.extend(parents[class_id].iter().cloned());
+                    costs.insert(class_id.clone(), cost_set);
+                }
+            }
+        }
+
+        let mut processed = 0;
         while let Some(node_id) = analysis_pending.pop() {
+            processed += 1;
+            if processed % memory_limit::CHECK_INTERVAL == 0 && memory_limit::exceeded() {
+                log::warn!("Soft memory limit exceeded, falling back to faster-bottom-up");
+                // The cost sets are what use the memory, so free them before falling back.
+                drop(costs);
+                let mut result = super::faster_bottom_up::FasterBottomUpExtractor
+                    .extract_with_cache(egraph, roots, cache);
+                result.memory_limit_exceeded = true;
+                return result;
+            }
+
             let class_id = n2c(&node_id);
             let node = &egraph[&node_id];
             if node.children.iter().all(|c| costs.contains_key(n2c(c))) {
@@ -144,6 +373,66 @@ impl Extractor for FasterGreedyDagExtractor {
     }
 }
 
+enum Worklist {
+    Fifo(UniqueQueue<NodeId>),
+    Ranked(RankedQueue),
+}
+
+impl Worklist {
+    fn insert(&mut self, node_id: NodeId) {
+        match self {
+            Worklist::Fifo(queue) => queue.insert(node_id),
+            Worklist::Ranked(queue) => queue.insert(node_id),
+        }
+    }
+
+    fn extend<I>(&mut self, iter: I)
+    where
+        I: IntoIterator<Item = NodeId>,
+    {
+        for node_id in iter {
+            self.insert(node_id);
+        }
+    }
+
+    fn pop(&mut self) -> Option<NodeId> {
+        match self {
+            Worklist::Fifo(queue) => queue.pop(),
+            Worklist::Ranked(queue) => queue.pop(),
+        }
+    }
+}
+
+// Like `UniqueQueue`, but popping the node with the lowest rank first.
+struct RankedQueue {
+    ranks: FxHashMap<NodeId, usize>,
+    set: FxHashSet<NodeId>,
+    heap: std::collections::BinaryHeap<std::cmp::Reverse<(usize, NodeId)>>,
+}
+
+impl RankedQueue {
+    fn new(ranks: FxHashMap<NodeId, usize>) -> Self {
+        RankedQueue {
+            ranks,
+            set: Default::default(),
+            heap: Default::default(),
+        }
+    }
+
+    fn insert(&mut self, node_id: NodeId) {
+        if self.set.insert(node_id.clone()) {
+            let rank = self.ranks[&node_id];
+            self.heap.push(std::cmp::Reverse((rank, node_id)));
+        }
+    }
+
+    fn pop(&mut self) -> Option<NodeId> {
+        let std::cmp::Reverse((_, node_id)) = self.heap.pop()?;
+        self.set.remove(&node_id);
+        Some(node_id)
+    }
+}
+
 /** A data structure to maintain a queue of unique elements.
 
 Notably, insert/pop operations have O(1) expected amortized runtime complexity.
@@ -53,7 +53,7 @@ use indexmap::IndexSet;
 use std::fmt;
 use std::time::SystemTime;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Config {
     pub pull_up_costs: bool,
     pub remove_self_loops: bool,
@@ -67,6 +67,10 @@ pub struct Config {
     pub remove_empty_classes: bool,
     pub return_improved_on_timeout: bool,
     pub remove_single_zero_cost: bool,
+    pub initialise_with_approx: bool,
+    // Egraphs with fewer nodes than this skip the more expensive simplifications,
+    // which mightn't save the solver as much time as they take, see `budget_simplification`.
+    pub min_nodes_to_simplify: usize,
 }
 
 impl Config {
@@ -84,6 +88,8 @@ impl Config {
             remove_empty_classes: true,
             return_improved_on_timeout: true,
             remove_single_zero_cost: true,
+            initialise_with_approx: true,
+            min_nodes_to_simplify: 500,
         }
     }
 }
@@ -177,7 +183,39 @@ impl<const TIMEOUT_IN_SECONDS: u32> Extractor
     for FasterCbcExtractorWithTimeout<TIMEOUT_IN_SECONDS>
 {
     fn extract(&self, egraph: &EGraph, roots: &[ClassId]) -> ExtractionResult {
-        return extract(egraph, roots, &Config::default(), TIMEOUT_IN_SECONDS);
+        self.extract_with_cache(egraph, roots, &CostCache::new(egraph))
+    }
+
+    fn extract_with_cache(
+        &self,
+        egraph: &EGraph,
+        roots: &[ClassId],
+        cache: &CostCache,
+    ) -> ExtractionResult {
+        return extract(
+            egraph,
+            roots,
+            &Config::default(),
+            TIMEOUT_IN_SECONDS,
+            None,
+            cache,
+        );
+    }
+
+    fn extract_with_hint(
+        &self,
+        egraph: &EGraph,
+        roots: &[ClassId],
+        hint: &ExtractionResult,
+    ) -> ExtractionResult {
+        return extract(
+            egraph,
+            roots,
+            &Config::default(),
+            TIMEOUT_IN_SECONDS,
+            Some(hint),
+            &CostCache::new(egraph),
+        );
     }
 }
 
@@ -185,7 +223,39 @@ pub struct FasterCbcExtractor;
 
 impl Extractor for FasterCbcExtractor {
     fn extract(&self, egraph: &EGraph, roots: &[ClassId]) -> ExtractionResult {
-        return extract(egraph, roots, &Config::default(), std::u32::MAX);
+        self.extract_with_cache(egraph, roots, &CostCache::new(egraph))
+    }
+
+    fn extract_with_cache(
+        &self,
+        egraph: &EGraph,
+        roots: &[ClassId],
+        cache: &CostCache,
+    ) -> ExtractionResult {
+        return extract(
+            egraph,
+            roots,
+            &Config::default(),
+            std::u32::MAX,
+            None,
+            cache,
+        );
+    }
+
+    fn extract_with_hint(
+        &self,
+        egraph: &EGraph,
+        roots: &[ClassId],
+        hint: &ExtractionResult,
+    ) -> ExtractionResult {
+        return extract(
+            egraph,
+            roots,
+            &Config::default(),
+            std::u32::MAX,
+            Some(hint),
+            &CostCache::new(egraph),
+        );
     }
 }
 
@@ -194,7 +264,37 @@ fn extract(
     roots_slice: &[ClassId],
     config: &Config,
     timeout: u32,
+    hint: Option<&ExtractionResult>,
+    cache: &CostCache,
+) -> ExtractionResult {
+    debug_assert!(std::ptr::eq(egraph, cache.egraph()));
+    let mut stats = SolverStats::default();
+    let mut result = extract_counting(
+        egraph,
+        roots_slice,
+        config,
+        timeout,
+        hint,
+        cache,
+        &mut stats,
+    );
+    if stats.solves > 0 {
+        result.solver_stats = Some(stats);
+    }
+    result
+}
+
+fn extract_counting(
+    egraph: &EGraph,
+    roots_slice: &[ClassId],
+    config: &Config,
+    timeout: u32,
+    hint: Option<&ExtractionResult>,
+    cache: &CostCache,
+    stats: &mut SolverStats,
 ) -> ExtractionResult {
+    let config = &budget_simplification(egraph, config, stats);
+
     // todo from now on we don't use roots_slice - be good to prevent using it any more.
     let mut roots = roots_slice.to_vec();
     roots.sort();
@@ -233,8 +333,26 @@ fn extract(
         })
         .collect();
 
-    let initial_result = super::faster_greedy_dag::FasterGreedyDagExtractor.extract(egraph, &roots);
-    let initial_result_cost = initial_result.dag_cost(egraph, &roots);
+    let mut parents = class_parents(egraph, cache);
+
+    let mut initial_result = super::faster_greedy_dag::FasterGreedyDagExtractor
+        .extract_with_cache(egraph, &roots, cache);
+    let mut initial_result_cost = initial_result.dag_cost(egraph, &roots);
+
+    // A hint is only used if, once the parts that don't fit this egraph are dropped,
+    // it still selects every root and is cheaper than the greedy result. Then it's a
+    // tighter bound for removing high-cost nodes and a better fallback on timeout.
+    if let Some(hint) = hint {
+        let hint = hint.valid_part(egraph);
+        if roots.iter().all(|r| hint.choices.contains_key(r)) {
+            let hint_cost = hint.dag_cost(egraph, &roots);
+            log::info!("Hint cost: {hint_cost}, greedy cost: {initial_result_cost}");
+            if hint_cost < initial_result_cost {
+                initial_result = hint;
+                initial_result_cost = hint_cost;
+            }
+        }
+    }
 
     // For classes where we know the choice already, we set the nodes early.
     let mut result = ExtractionResult::default();
@@ -246,11 +364,11 @@ fn extract(
         remove_high_cost(&mut vars, initial_result_cost, &roots, config);
         remove_more_expensive_subsumed_nodes(&mut vars, config);
         remove_unreachable_classes(&mut vars, &roots, config);
-        pull_up_with_single_parent(&mut vars, &roots, config);
-        pull_up_costs(&mut vars, &roots, config);
-        remove_single_zero_cost(&mut vars, &mut result, &roots, config);
+        pull_up_with_single_parent(&mut vars, &mut parents, &roots, config);
+        pull_up_costs(&mut vars, &parents, &roots, config);
+        remove_single_zero_cost(&mut vars, &parents, &mut result, &roots, config);
         find_extra_roots(&mut vars, &mut roots, config);
-        remove_empty_classes(&mut vars, config);
+        remove_empty_classes(&mut vars, &parents, config);
     }
 
     for (classid, class) in &vars {
@@ -359,11 +477,13 @@ fn extract(
 
     log::info!("Objective function terms: {}", objective_fn_terms);
 
-    if false {
-        //config.initialise_with_approx
-        // set initial solution based on a non-optimal extraction.
-        // using this causes the ILP solver to return unsound results.
-        set_initial_solution(&vars, &mut model, &initial_result);
+    if config.initialise_with_approx {
+        // Start the solver from the approximate extraction, repaired to fit the
+        // simplified problem.
+        match feasible_start(&vars, &parents, &roots, &initial_result) {
+            Some(start) => set_initial_solution(&vars, &mut model, &start),
+            None => log::info!("No feasible initial solution, solving without one"),
+        }
     }
 
     if false {
@@ -375,6 +495,8 @@ fn extract(
         simp_start_time.elapsed().as_millis()
     );
 
+    let components = cyclic_components(&vars);
+
     let start_time = SystemTime::now();
 
     loop {
@@ -389,6 +511,7 @@ fn extract(
         //This starts from scratch solving each time. I've looked quickly
         //at the API and didn't see how to call it incrementally.
         let solution = model.solve();
+        stats.record_solve(solution.raw());
         log::info!(
             "CBC status {:?}, {:?}, obj = {}",
             solution.raw().status(),
@@ -426,13 +549,17 @@ fn extract(
                 assert!(var.members() > 0);
                 let mut node_idx = 0;
                 if var.members() != 1 {
-                    assert_eq!(
-                        1,
-                        var.variables
-                            .iter()
-                            .filter(|&n| solution.col(*n) > 0.0)
-                            .count()
-                    );
+                    let active_nodes = var
+                        .variables
+                        .iter()
+                        .filter(|&n| solution.col(*n) > 0.0)
+                        .count();
+                    // An unfinished search mightn't have found a feasible solution.
+                    if stopped_without_finishing && active_nodes != 1 {
+                        log::info!("No feasible incumbent, returning initial result");
+                        return initial_result;
+                    }
+                    assert_eq!(1, active_nodes);
 
                     node_idx = var
                         .variables
@@ -447,7 +574,7 @@ fn extract(
             }
         }
 
-        let cycles = find_cycles_in_result(&result, &vars, &roots);
+        let cycles = find_cycles_in_result(&result, &vars, &components);
 
         log::info!("Cost of solution {cost}");
         log::info!("Initial result {}", initial_result_cost.into_inner());
@@ -486,6 +613,7 @@ fn extract(
             return result;
         } else {
             log::info!("Refining by blocking cycles: {}", cycles.len());
+            stats.cycle_constraints += cycles.len();
             for c in &cycles {
                 block_cycle(&mut model, c, &vars);
             }
@@ -504,31 +632,134 @@ fn extract(
             // Second, when used before solving, the ILP solver was sometimes unsound.
             // I didn't see unsound results from the ILP solver using this function here, but
             // it makes me wary, plus it doesn't speed up things noticeably.
-            set_initial_solution(&vars, &mut model, &result);
+            if let Some(start) = feasible_start(&vars, &parents, &roots, &result) {
+                set_initial_solution(&vars, &mut model, &start);
+            }
         }
     }
 }
 
 /*
-Using this caused wrong results from the solver. I don't have a good idea why.
+Simplifying the egraph takes time too, and on small egraphs the solver is quick
+anyway, so the more expensive passes mightn't pay for themselves. These are skipped
+for egraphs with fewer than `min_nodes_to_simplify` nodes, and removing subsumed
+nodes is skipped when no class has more than one node, as then no node can subsume
+another. The cheap passes, which only remove nodes and classes, always run.
+
+The skipped passes are logged and recorded in the solver stats.
 */
+fn budget_simplification(egraph: &EGraph, config: &Config, stats: &mut SolverStats) -> Config {
+    let mut budgeted = config.clone();
+    let small = egraph.nodes.len() < config.min_nodes_to_simplify;
+    let singleton_classes = egraph.classes().values().all(|c| c.nodes.len() <= 1);
+
+    let passes: [(&'static str, &mut bool, bool); 4] = [
+        (
+            "remove_more_expensive_subsumed_nodes",
+            &mut budgeted.remove_more_expensive_subsumed_nodes,
+            small || singleton_classes,
+        ),
+        ("pull_up_costs", &mut budgeted.pull_up_costs, small),
+        (
+            "pull_up_single_parent",
+            &mut budgeted.pull_up_single_parent,
+            small,
+        ),
+        ("find_extra_roots", &mut budgeted.find_extra_roots, small),
+    ];
+    for (name, enabled, skip) in passes {
+        if *enabled && skip {
+            *enabled = false;
+            stats.skipped_passes.push(name);
+        }
+    }
+    if !stats.skipped_passes.is_empty() {
+        log::info!(
+            "Skipping {:?} on an egraph with {} nodes",
+            stats.skipped_passes,
+            egraph.nodes.len()
+        );
+    }
+    budgeted
+}
+
+/*
+Repairs an extraction into a feasible starting point for the solver.
+
+Passing the approximate extraction straight to the solver caused wrong results. It
+was chosen on the whole egraph, but by now the problem has been simplified: nodes
+have been removed, children dropped, and extra roots found. So it could choose
+nodes that don't have a variable any more, or leave a class that must be active
+inactive. CBC doesn't reject a start like that.
+
+Instead the start is built bottom-up on what's left of the problem. A class is
+ready once it has a member whose children are all ready. First only the members
+that the extraction chose are used, then any member, for the classes that are still
+missing. Each pass starts with every class on a worklist, and a class that becomes
+ready puts its parents back on it, so a class is only looked at again when one of
+its children has changed. Either way a member is only taken once its children are ready, so the
+start is acyclic and satisfies any cycle blocking constraints too. Then only the
+classes reachable from the roots are made active.
+
+Returns the index of the chosen member of each active class, or None if some root
+isn't ready.
+*/
+fn feasible_start(
+    vars: &IndexMap<ClassId, ClassILP>,
+    parents: &Parents,
+    roots: &[ClassId],
+    extraction: &ExtractionResult,
+) -> Option<IndexMap<ClassId, usize>> {
+    let mut ready: IndexMap<ClassId, usize> = IndexMap::default();
+    for use_any_member in [false, true] {
+        let mut todo: std::collections::VecDeque<&ClassId> = vars.keys().collect();
+        while let Some(classid) = todo.pop_front() {
+            if ready.contains_key(classid) {
+                continue;
+            }
+            let class = &vars[classid];
+            let preferred = extraction
+                .choices
+                .get(classid)
+                .and_then(|n| class.members.iter().position(|m| m == n));
+            let found = (0..class.members())
+                .filter(|&idx| use_any_member || Some(idx) == preferred)
+                .find(|&idx| {
+                    class.childrens_classes[idx]
+                        .iter()
+                        .all(|c| ready.contains_key(c))
+                });
+            if let Some(idx) = found {
+                ready.insert(classid.clone(), idx);
+                todo.extend(parents_of(vars, parents, classid));
+            }
+        }
+    }
+
+    let mut start: IndexMap<ClassId, usize> = IndexMap::default();
+    let mut todo: Vec<ClassId> = roots.to_vec();
+    while let Some(classid) = todo.pop() {
+        if start.contains_key(&classid) {
+            continue;
+        }
+        let idx = *ready.get(&classid)?;
+        todo.extend(vars[&classid].childrens_classes[idx].iter().cloned());
+        start.insert(classid, idx);
+    }
+    Some(start)
+}
+
 fn set_initial_solution(
     vars: &IndexMap<ClassId, ClassILP>,
     model: &mut Model,
-    initial_result: &ExtractionResult,
+    start: &IndexMap<ClassId, usize>,
 ) {
     for (class, class_vars) in vars {
-        for col in class_vars.variables.clone() {
-            model.set_col_initial_solution(col, 0.0);
-        }
-
-        if let Some(node_id) = initial_result.choices.get(class) {
-            model.set_col_initial_solution(class_vars.active, 1.0);
-            if let Some(var) = vars[class].get_variable_for_node(node_id) {
-                model.set_col_initial_solution(var, 1.0);
-            }
-        } else {
-            model.set_col_initial_solution(class_vars.active, 0.0);
+        let chosen = start.get(class);
+        model.set_col_initial_solution(class_vars.active, chosen.map_or(0.0, |_| 1.0));
+        for (idx, &col) in class_vars.variables.iter().enumerate() {
+            let value = if chosen == Some(&idx) { 1.0 } else { 0.0 };
+            model.set_col_initial_solution(col, value);
         }
     }
 }
@@ -548,6 +779,7 @@ and here we delete just children of nodes in the parent classes.
 */
 fn remove_single_zero_cost(
     vars: &mut IndexMap<ClassId, ClassILP>,
+    parents: &Parents,
     extraction_result: &mut ExtractionResult,
     roots: &[ClassId],
     config: &Config,
@@ -570,13 +802,11 @@ fn remove_single_zero_cost(
 
         let mut removed = 0;
         let mut extras = 0;
-        let fresh = IndexSet::<ClassId>::new();
-        let child_to_parents = child_to_parents(&vars);
 
         // Remove all references to those in zero.
         for e in &zero {
-            let parents = child_to_parents.get(e).unwrap_or(&fresh);
-            for parent in parents {
+            let e_parents: Vec<ClassId> = parents_of(vars, parents, e).cloned().collect();
+            for parent in &e_parents {
                 for i in (0..vars[parent].childrens_classes.len()).rev() {
                     if vars[parent].childrens_classes[i].contains(e) {
                         vars[parent].childrens_classes[i].remove(e);
@@ -611,20 +841,37 @@ fn remove_single_zero_cost(
     }
 }
 
-fn child_to_parents(vars: &IndexMap<ClassId, ClassILP>) -> IndexMap<ClassId, IndexSet<ClassId>> {
-    let mut child_to_parents: IndexMap<ClassId, IndexSet<ClassId>> = IndexMap::new();
+// For each class, the classes with a node that has it as a child. It's built once, from
+// the cache's parents, and the reductions only remove children, except for
+// `pull_up_with_single_parent`, which adds the classes it pulls up. So it's a
+// superset of the parents, which `parents_of` narrows down to the current ones.
+type Parents = IndexMap<ClassId, IndexSet<ClassId>>;
+
+fn class_parents(egraph: &EGraph, cache: &CostCache) -> Parents {
+    cache
+        .parents()
+        .iter()
+        .map(|(class_id, nodes)| {
+            let classes = nodes.iter().map(|n| egraph.nid_to_cid(n).clone());
+            (class_id.clone(), classes.collect())
+        })
+        .collect()
+}
 
-    for (class_id, class_vars) in vars.iter() {
-        for kids in &class_vars.childrens_classes {
-            for child_class in kids {
-                child_to_parents
-                    .entry(child_class.clone())
-                    .or_insert_with(IndexSet::new)
-                    .insert(class_id.clone());
-            }
-        }
-    }
-    child_to_parents
+// The classes left in `vars` with a member that has `class_id` as a child.
+fn parents_of<'a>(
+    vars: &'a IndexMap<ClassId, ClassILP>,
+    parents: &'a Parents,
+    class_id: &'a ClassId,
+) -> impl Iterator<Item = &'a ClassId> + 'a {
+    parents
+        .get(class_id)
+        .into_iter()
+        .flatten()
+        .filter(move |p| {
+            vars.get(*p)
+                .is_some_and(|v| v.childrens_classes.iter().any(|c| c.contains(class_id)))
+        })
 }
 
 /* If a node in a class has (a) equal or higher cost compared to another in that same class, and (b) its
@@ -679,7 +926,11 @@ fn remove_unreachable_classes(
 
 // Any node that has an empty class as a child, can't be selected, so remove the node,
 // if that makes another empty class, then remove its parents
-fn remove_empty_classes(vars: &mut IndexMap<ClassId, ClassILP>, config: &Config) {
+fn remove_empty_classes(
+    vars: &mut IndexMap<ClassId, ClassILP>,
+    parents: &Parents,
+    config: &Config,
+) {
     if config.remove_empty_classes {
         let mut empty_classes: std::collections::VecDeque<ClassId> = Default::default();
         for (classid, detail) in vars.iter() {
@@ -689,29 +940,14 @@ fn remove_empty_classes(vars: &mut IndexMap<ClassId, ClassILP>, config: &Config)
         }
 
         let mut removed = 0;
-        let fresh = IndexSet::<ClassId>::new();
-
-        let mut child_to_parents: IndexMap<ClassId, IndexSet<ClassId>> = IndexMap::new();
-
-        for (class_id, class_vars) in vars.iter() {
-            for kids in &class_vars.childrens_classes {
-                for child_class in kids {
-                    child_to_parents
-                        .entry(child_class.clone())
-                        .or_insert_with(IndexSet::new)
-                        .insert(class_id.clone());
-                }
-            }
-        }
-
         let mut done = FxHashSet::<ClassId>::default();
 
         while let Some(e) = empty_classes.pop_front() {
             if !done.insert(e.clone()) {
                 continue;
             }
-            let parents = child_to_parents.get(&e).unwrap_or(&fresh);
-            for parent in parents {
+            let e_parents: Vec<ClassId> = parents_of(vars, parents, &e).cloned().collect();
+            for parent in &e_parents {
                 for i in (0..vars[parent].childrens_classes.len()).rev() {
                     if vars[parent].childrens_classes[i].contains(&e) {
                         vars[parent].remove(i);
@@ -774,11 +1010,16 @@ For each class with one parent, move the minimum costs of the members to each no
 
 if we iterated through these in order, from child to parent, to parent, to parent.. it could be done in one pass.
 */
-fn pull_up_costs(vars: &mut IndexMap<ClassId, ClassILP>, roots: &[ClassId], config: &Config) {
+fn pull_up_costs(
+    vars: &mut IndexMap<ClassId, ClassILP>,
+    parents: &Parents,
+    roots: &[ClassId],
+    config: &Config,
+) {
     if config.pull_up_costs {
         let mut count = 0;
         let mut changed = true;
-        let child_to_parent = classes_with_single_parent(&*vars);
+        let child_to_parent = classes_with_single_parent(&*vars, parents);
 
         while (count < 10) && changed {
             log::info!("Classes with a single parent: {}", child_to_parent.len());
@@ -843,12 +1084,13 @@ There could be a long chain of single parent classes - which this handles
 
 fn pull_up_with_single_parent(
     vars: &mut IndexMap<ClassId, ClassILP>,
+    parents: &mut Parents,
     roots: &[ClassId],
     config: &Config,
 ) {
     if config.pull_up_single_parent {
         for _i in 0..10 {
-            let child_to_parent = classes_with_single_parent(&*vars);
+            let child_to_parent = classes_with_single_parent(&*vars, parents);
             log::info!("Classes with a single parent: {}", child_to_parent.len());
 
             let mut pull_up_count = 0;
@@ -902,6 +1144,7 @@ fn pull_up_with_single_parent(
 
                 for e in &child_descendants {
                     parent_descendants.insert(e.clone());
+                    parents.entry(e.clone()).or_default().insert(parent.clone());
                 }
 
                 vars.get_mut(child)
@@ -988,29 +1231,17 @@ fn remove_with_loops(vars: &mut IndexMap<ClassId, ClassILP>, roots: &[ClassId],
     }
 }
 
-// Mapping from child class to parent classes
-fn classes_with_single_parent(vars: &IndexMap<ClassId, ClassILP>) -> IndexMap<ClassId, ClassId> {
-    let mut child_to_parents: IndexMap<ClassId, IndexSet<ClassId>> = IndexMap::new();
-
-    for (class_id, class_vars) in vars.iter() {
-        for kids in &class_vars.childrens_classes {
-            for child_class in kids {
-                child_to_parents
-                    .entry(child_class.clone())
-                    .or_insert_with(IndexSet::new)
-                    .insert(class_id.clone());
-            }
-        }
-    }
-
-    // return classes with only one parent
-    child_to_parents
-        .into_iter()
-        .filter_map(|(child_class, parents)| {
-            if parents.len() == 1 {
-                Some((child_class, parents.into_iter().next().unwrap()))
-            } else {
-                None
+// Mapping from child class to parent class, for the classes with only one parent.
+fn classes_with_single_parent(
+    vars: &IndexMap<ClassId, ClassILP>,
+    parents: &Parents,
+) -> IndexMap<ClassId, ClassId> {
+    vars.keys()
+        .filter_map(|child_class| {
+            let mut child_parents = parents_of(vars, parents, child_class);
+            match (child_parents.next(), child_parents.next()) {
+                (Some(parent), None) => Some((child_class.clone(), parent.clone())),
+                _ => None,
             }
         })
         .collect()
@@ -1075,80 +1306,66 @@ fn block_cycle(model: &mut Model, cycle: &Vec<ClassId>, vars: &IndexMap<ClassId,
     }
 }
 
-#[derive(Clone)]
-enum TraverseStatus {
-    Doing,
-    Done,
-}
-
 /*
-Returns the simple cycles possible from the roots.
+Returns cycles of the extraction, see `cycles::find_cycles`.
 
-Because the number of simple cycles can be factorial in the number
-of nodes, this can be very slow.
-
-Imagine a 20 node complete graph with one root. From the first node you have
-19 choices, then from the second 18 choices, etc.  When you get to the second
-last node you go back to the root. There are about 10^17 length 18 cycles.
+That's at most one per edge, but on a big egraph that's still a lot of
+constraints to add in one go.
 
 So we limit how many can be found.
+
+Every cycle of an extraction is within one of the `cyclic_components` of the
+classes, which don't change between solves, so the search only starts from chosen
+classes in those, and only follows edges within a component. An egraph whose
+simplified classes have no cycles isn't searched at all. Cycles the roots don't
+reach are blocked too, which no acyclic extraction is kept from.
 */
 const CYCLE_LIMIT: usize = 1000;
 
 fn find_cycles_in_result(
     extraction_result: &ExtractionResult,
     vars: &IndexMap<ClassId, ClassILP>,
-    roots: &[ClassId],
+    components: &FxHashMap<ClassId, usize>,
 ) -> Vec<Vec<ClassId>> {
-    let mut status = IndexMap::<ClassId, TraverseStatus>::default();
-    let mut cycles = vec![];
-    for root in roots {
-        let mut stack = vec![];
-        cycle_dfs(
-            extraction_result,
-            vars,
-            root,
-            &mut status,
-            &mut cycles,
-            &mut stack,
-        )
+    if components.is_empty() {
+        return vec![];
     }
-    cycles
+    cycles::find_cycles(
+        extraction_result
+            .choices
+            .keys()
+            .filter(|cid| components.contains_key(*cid)),
+        |cid| {
+            let component = components[cid];
+            vars[cid]
+                .get_children_of_node(&extraction_result.choices[cid])
+                .iter()
+                .filter(move |c| components.get(*c) == Some(&component))
+        },
+        CYCLE_LIMIT,
+    )
 }
 
-fn cycle_dfs(
-    extraction_result: &ExtractionResult,
-    vars: &IndexMap<ClassId, ClassILP>,
-    class_id: &ClassId,
-    status: &mut IndexMap<ClassId, TraverseStatus>,
-    cycles: &mut Vec<Vec<ClassId>>,
-    stack: &mut Vec<ClassId>,
-) {
-    match status.get(class_id).cloned() {
-        Some(TraverseStatus::Done) => (),
-        Some(TraverseStatus::Doing) => {
-            // Get the part of the stack between the first visit to the class and now.
-            let mut cycle = vec![];
-            if let Some(pos) = stack.iter().position(|id| id == class_id) {
-                cycle.extend_from_slice(&stack[pos..]);
-            }
-            cycles.push(cycle);
-        }
-        None => {
-            if cycles.len() > CYCLE_LIMIT {
-                return;
-            }
-            status.insert(class_id.clone(), TraverseStatus::Doing);
-            stack.push(class_id.clone());
-            let node_id = &extraction_result.choices[class_id];
-            for child_cid in vars[class_id].get_children_of_node(node_id) {
-                cycle_dfs(extraction_result, vars, child_cid, status, cycles, stack)
-            }
-            let last = stack.pop();
-            assert_eq!(*class_id, last.unwrap());
-            status.insert(class_id.clone(), TraverseStatus::Done);
+// The strongly connected component of each class that's in one with cycles, over the
+// children of all of each class's nodes.
+fn cyclic_components(vars: &IndexMap<ClassId, ClassILP>) -> FxHashMap<ClassId, usize> {
+    let classes: Vec<&ClassId> = vars.keys().collect();
+    let children = |cid: &ClassId| vars[cid].childrens_classes.iter().flatten();
+    let mut components = FxHashMap::default();
+    for (i, component) in cycles::strongly_connected_components(&classes, children)
+        .into_iter()
+        .enumerate()
+    {
+        if component.len() > 1 || children(&component[0]).any(|c| *c == component[0]) {
+            components.extend(component.into_iter().map(|cid| (cid, i)));
         }
     }
+    log::info!(
+        "{} of {} classes are in components with cycles",
+        components.len(),
+        vars.len()
+    );
+    components
 }
 
 #[cfg(test)]
@@ -1175,6 +1392,8 @@ mod test {
             remove_empty_classes: rng.gen(),
             return_improved_on_timeout: rng.gen(),
             remove_single_zero_cost: rng.gen(),
+            initialise_with_approx: rng.gen(),
+            min_nodes_to_simplify: if rng.gen() { 0 } else { usize::MAX },
         }
     }
 
@@ -1192,6 +1411,8 @@ mod test {
             remove_empty_classes: false,
             return_improved_on_timeout: false,
             remove_single_zero_cost: false,
+            initialise_with_approx: false,
+            min_nodes_to_simplify: 0,
         };
     }
 
@@ -1213,8 +1434,13 @@ mod test {
 
             let mut results: Option<Cost> = None;
             for c in config {
-                let extraction = extract(&egraph, &egraph.root_eclasses, c, u32::MAX);
+                let cache = CostCache::new(&egraph);
+                let extraction = extract(&egraph, &egraph.root_eclasses, c, u32::MAX, None, &cache);
                 extraction.check(&egraph);
+                if let Some(stats) = &extraction.solver_stats {
+                    assert!(stats.solves > 0);
+                    assert!(stats.best_bound <= stats.objective + EPSILON_ALLOWANCE);
+                }
                 let dag_cost = extraction.dag_cost(&egraph, &egraph.root_eclasses);
                 if results.is_some() {
                     assert!(
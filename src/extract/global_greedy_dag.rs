@@ -6,9 +6,12 @@ use super::*;
 
 type TermId = usize;
 
+// The class is part of the term, as a serialized egraph can have nodes with the
+// same op and children in different classes, and a term stands for one node.
 #[derive(Clone, PartialEq, Eq, Hash)]
 struct Term {
     op: String,
+    eclass: ClassId,
     children: Vec<TermId>,
 }
 
@@ -51,6 +54,7 @@ impl TermDag {
     ) -> Option<TermId> {
         let term = Term {
             op: node.op.clone(),
+            eclass: node.eclass.clone(),
             children: children.clone(),
         };
 
@@ -153,7 +157,27 @@ impl TermDag {
 
 pub struct GlobalGreedyDagExtractor;
 impl Extractor for GlobalGreedyDagExtractor {
-    fn extract(&self, egraph: &EGraph, _roots: &[ClassId]) -> ExtractionResult {
+    fn extract(&self, egraph: &EGraph, roots: &[ClassId]) -> ExtractionResult {
+        BudgetedGlobalGreedyDagExtractor::default().extract(egraph, roots)
+    }
+}
+
+/// The global greedy dag extractor, stopping early once any of the limits are
+/// reached, rather than only when a pass over all the nodes improves nothing.
+/// The limits are checked after each pass, so a pass that's underway when the
+/// time runs out is finished first.
+#[doc(hidden)] // experimental
+#[derive(Default)]
+pub struct BudgetedGlobalGreedyDagExtractor {
+    pub max_iterations: Option<usize>,
+    // Stop once the roots' terms together cost this much or less.
+    pub target_cost: Option<Cost>,
+    pub time_budget: Option<std::time::Duration>,
+}
+
+impl Extractor for BudgetedGlobalGreedyDagExtractor {
+    fn extract(&self, egraph: &EGraph, roots: &[ClassId]) -> ExtractionResult {
+        let start_time = std::time::Instant::now();
         let mut keep_going = true;
 
         let nodes = egraph.nodes.clone();
@@ -163,7 +187,6 @@ impl Extractor for GlobalGreedyDagExtractor {
         let mut i = 0;
         while keep_going {
             i += 1;
-            println!("iteration {}", i);
             keep_going = false;
 
             'node_loop: for (node_id, node) in &nodes {
@@ -192,12 +215,49 @@ impl Extractor for GlobalGreedyDagExtractor {
                     }
                 }
             }
+
+            // Shared subterms of different roots are counted for each, so this can
+            // be more than the dag cost.
+            let roots_cost: Cost = roots
+                .iter()
+                .map(|r| {
+                    best_in_class
+                        .get(r)
+                        .map_or(INFINITY, |id| termdag.total_cost(*id))
+                })
+                .sum();
+            log::info!("Iteration {i}: roots cost {roots_cost}");
+
+            if self.max_iterations.is_some_and(|max| i >= max) {
+                log::info!("Stopping after {i} iterations");
+                break;
+            }
+            if self.target_cost.is_some_and(|target| roots_cost <= target) {
+                log::info!("Stopping at the target cost");
+                break;
+            }
+            if self
+                .time_budget
+                .is_some_and(|budget| start_time.elapsed() >= budget)
+            {
+                log::info!("Stopping after {i} iterations, out of time");
+                break;
+            }
         }
 
         let mut result = ExtractionResult::default();
         for (class, term) in best_in_class {
             result.choose(class, termdag.info[term].node.clone());
         }
-        result
+
+        // A class's best term can be replaced after its parents' terms were made from
+        // it, so the choices can be cyclic, and a search cut short can miss roots.
+        let result = result.valid_part(egraph);
+        if roots.iter().all(|r| result.choices.contains_key(r)) {
+            result
+        } else {
+            log::warn!("No acyclic term for every root, falling back to faster-greedy-dag");
+            super::faster_greedy_dag::FasterGreedyDagExtractor.extract(egraph, roots)
+        }
     }
 }
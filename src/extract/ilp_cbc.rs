@@ -2,11 +2,58 @@
 
 This extractor is simple so that it's easy to see that it's correct.
 
-If the timeout is reached, it will return the result of the faster-greedy-dag extractor.
+If the timeout is reached, it will return the best solution the solver found before then,
+or the result of the faster-greedy-dag extractor if that's cheaper or the solver didn't
+find a valid one.
+
+`CbcExtractorWithRules` also adds the requirements of a suite's rules as constraints,
+see `suite_rules`.
+
+`CbcExtractorWithPolicy` meets an `op_policy::OpPolicy`. Each op of an exclusive group
+gets a binary variable for whether it's used, which each of the op's nodes implies, and
+at most one of a group's ops is used.
+
+`CbcExtractorDiverse` finds the cheapest extraction that differs from a reference
+extraction in at least `distance` of the reference's choices, to generate diverse
+candidates: extract once, then again with the first result as the reference, and so
+on. A reference choice counts as different if its class isn't chosen, or is chosen
+with another node. Encoded as: the sum of the reference's nodes' variables is at most
+the number of reference choices less `distance`. If no extraction is far enough
+away, the result is empty.
+
+`CbcExtractorMinDepth` finds the cheapest of the shallowest extractions, see
+`ExtractionResult::depth_cost`. The least depth is found by `DepthBottomUpExtractor`,
+and then each class gets a continuous variable for its depth, which is at least the
+depth of each of the active node's children plus the node's cost. These are bounded
+above by the least depth, so the solver minimises the dag cost among the extractions
+of that depth.
+
+`CbcExtractorMinTree` minimises the tree cost instead, to certify the tree costs of
+the extractors that claim to be tree optimal. Each node gets an integer variable for
+how many times it's used in the terms of the roots, and its cost counts once per use.
+A class is used once per root it is and once per occurrence as a child of each use of
+a node, and each use of a class is a use of one of its nodes:
+  sum(uses of class's nodes) - sum(child occurrences * uses of parent) >= root count
+Different uses of a class can pick different nodes, but the cheapest term for a class
+doesn't depend on where it's used, so that doesn't make the optimum any cheaper. A
+cycle in the used nodes needs ever more uses, so only finite terms are feasible and
+there are no constraints against cycles. The extraction is the cheapest per class
+among the used nodes, which costs no more than the optimum. Tree costs grow
+exponentially with depth in egraphs with a lot of sharing, so the use counts can be
+too big for the solver to be exact there.
+
+`pareto_front` trades the dag cost off against the depth, by the epsilon-constraint
+method: first the cheapest extraction, then the cheapest that's shallower than that,
+and so on until the least depth is reached.
+
+`lp_lower_bound` solves the linear relaxation of the same encoding, without
+integrality or the constraints against cycles, which is a lower bound on the dag cost
+of any extraction. It's much quicker than the ILP, so bounds how far another
+extractor's result is from optimal, see `--lower-bound`.
 */
 
 use super::*;
-use coin_cbc::{Col, Model, Sense};
+use coin_cbc::{Col, Model, Row, Sense};
 use indexmap::IndexSet;
 
 struct ClassVars {
@@ -18,7 +65,7 @@ pub struct CbcExtractorWithTimeout<const TIMEOUT_IN_SECONDS: u32>;
 
 impl<const TIMEOUT_IN_SECONDS: u32> Extractor for CbcExtractorWithTimeout<TIMEOUT_IN_SECONDS> {
     fn extract(&self, egraph: &EGraph, roots: &[ClassId]) -> ExtractionResult {
-        return extract(egraph, roots, TIMEOUT_IN_SECONDS);
+        return extract(egraph, roots, TIMEOUT_IN_SECONDS, &Constraints::default());
     }
 }
 
@@ -26,11 +73,296 @@ pub struct CbcExtractor;
 
 impl Extractor for CbcExtractor {
     fn extract(&self, egraph: &EGraph, roots: &[ClassId]) -> ExtractionResult {
-        return extract(egraph, roots, std::u32::MAX);
+        return extract(egraph, roots, std::u32::MAX, &Constraints::default());
     }
 }
 
-fn extract(egraph: &EGraph, roots: &[ClassId], timeout_seconds: u32) -> ExtractionResult {
+pub struct CbcExtractorWithRules<'a> {
+    pub rule: &'a dyn suite_rules::SuiteRule,
+    pub timeout_seconds: u32,
+}
+
+impl Extractor for CbcExtractorWithRules<'_> {
+    fn extract(&self, egraph: &EGraph, roots: &[ClassId]) -> ExtractionResult {
+        let constraints = Constraints {
+            requirements: self.rule.requirements(egraph),
+            ..Default::default()
+        };
+        return extract(egraph, roots, self.timeout_seconds, &constraints);
+    }
+}
+
+pub struct CbcExtractorWithPolicy<'a> {
+    pub policy: &'a op_policy::OpPolicy,
+    pub timeout_seconds: u32,
+}
+
+impl Extractor for CbcExtractorWithPolicy<'_> {
+    fn extract(&self, egraph: &EGraph, roots: &[ClassId]) -> ExtractionResult {
+        let constraints = Constraints {
+            exclusive: self.policy.exclusive_nodes(egraph),
+            ..Default::default()
+        };
+        return extract(egraph, roots, self.timeout_seconds, &constraints);
+    }
+}
+
+pub struct CbcExtractorDiverse<'a> {
+    pub reference: &'a ExtractionResult,
+    pub distance: usize,
+    pub timeout_seconds: u32,
+}
+
+impl Extractor for CbcExtractorDiverse<'_> {
+    fn extract(&self, egraph: &EGraph, roots: &[ClassId]) -> ExtractionResult {
+        let constraints = Constraints {
+            diverse_from: Some((self.reference, self.distance)),
+            ..Default::default()
+        };
+        return extract(egraph, roots, self.timeout_seconds, &constraints);
+    }
+}
+
+pub struct CbcExtractorMinDepth {
+    pub timeout_seconds: u32,
+}
+
+impl Extractor for CbcExtractorMinDepth {
+    fn extract(&self, egraph: &EGraph, roots: &[ClassId]) -> ExtractionResult {
+        let shallowest = bottom_up::DepthBottomUpExtractor.extract(egraph, roots);
+        let constraints = Constraints {
+            max_depth: Some(shallowest.depth_cost(egraph, roots)),
+            ..Default::default()
+        };
+        return extract(egraph, roots, self.timeout_seconds, &constraints);
+    }
+}
+
+pub struct CbcExtractorMinTree {
+    pub timeout_seconds: u32,
+}
+
+impl Extractor for CbcExtractorMinTree {
+    fn extract(&self, egraph: &EGraph, roots: &[ClassId]) -> ExtractionResult {
+        return extract_tree(egraph, roots, self.timeout_seconds);
+    }
+}
+
+pub struct ParetoPoint {
+    pub dag: Cost,
+    pub depth: Cost,
+    pub result: ExtractionResult,
+}
+
+// The extractions that no other is both cheaper and shallower than, cheapest first.
+// With a timeout, each point is only as good as the solver found in that time.
+pub fn pareto_front(egraph: &EGraph, roots: &[ClassId], timeout_seconds: u32) -> Vec<ParetoPoint> {
+    let least_depth = bottom_up::DepthBottomUpExtractor
+        .extract(egraph, roots)
+        .depth_cost(egraph, roots);
+
+    let mut front: Vec<ParetoPoint> = vec![];
+    let mut max_depth = None;
+    loop {
+        let constraints = Constraints {
+            max_depth,
+            ..Default::default()
+        };
+        let mut result = extract(egraph, roots, timeout_seconds, &constraints);
+        if !roots.iter().all(|root| result.choices.contains_key(root)) {
+            break;
+        }
+        result.retain_reachable(egraph, roots);
+        let point = ParetoPoint {
+            dag: result.dag_cost(egraph, roots),
+            depth: result.depth_cost(egraph, roots),
+            result,
+        };
+
+        // A point that's no cheaper than the next, shallower one is dominated.
+        if let Some(last) = front.last() {
+            if last.dag + EPSILON_ALLOWANCE >= point.dag {
+                front.pop();
+            }
+        }
+        let depth = point.depth;
+        front.push(point);
+        if depth.into_inner() <= least_depth.into_inner() + EPSILON_ALLOWANCE {
+            break;
+        }
+        // Strictly shallower next time, the bound having EPSILON_ALLOWANCE of slack.
+        max_depth = Some(depth - 2.0 * EPSILON_ALLOWANCE);
+    }
+    front
+}
+
+// No extraction has a lower dag cost than this. None if the relaxation is infeasible,
+// in which case there's no extraction at all.
+pub fn lp_lower_bound(egraph: &EGraph, roots: &[ClassId]) -> Option<Cost> {
+    let mut model = Model::default();
+    model.set_parameter("loglevel", "0");
+
+    let mut relaxed = || {
+        let col = model.add_col();
+        model.set_col_upper(col, 1.0);
+        col
+    };
+    let vars: IndexMap<ClassId, ClassVars> = egraph
+        .classes()
+        .values()
+        .map(|class| {
+            let cvars = ClassVars {
+                active: relaxed(),
+                nodes: class.nodes.iter().map(|_| relaxed()).collect(),
+            };
+            (class.id.clone(), cvars)
+        })
+        .collect();
+
+    for (class_id, class) in &vars {
+        // sum(for node_active in class) == class_active
+        let row = model.add_row();
+        model.set_row_equal(row, 0.0);
+        model.set_weight(row, class.active, -1.0);
+        for &node_active in &class.nodes {
+            model.set_weight(row, node_active, 1.0);
+        }
+
+        for (node_id, &node_active) in egraph[class_id].nodes.iter().zip(&class.nodes) {
+            let children: IndexSet<&ClassId> = egraph[node_id]
+                .children
+                .iter()
+                .map(|c| egraph.nid_to_cid(c))
+                .collect();
+            for child in children {
+                //   node_active - child_active <= 0
+                let row = model.add_row();
+                model.set_row_upper(row, 0.0);
+                model.set_weight(row, node_active, 1.0);
+                model.set_weight(row, vars[child].active, -1.0);
+            }
+            model.set_obj_coeff(node_active, egraph[node_id].cost.into_inner());
+        }
+    }
+    model.set_obj_sense(Sense::Minimize);
+    for root in roots {
+        model.set_col_lower(vars[root].active, 1.0);
+    }
+
+    let solution = model.solve();
+    if solution.raw().is_proven_infeasible() {
+        return None;
+    }
+    // The relaxation's optimum can be a hair over the true optimum in floating point.
+    let bound = (solution.raw().obj_value() - EPSILON_ALLOWANCE).max(0.0);
+    Some(Cost::new(bound).unwrap())
+}
+
+fn extract_tree(egraph: &EGraph, roots: &[ClassId], timeout_seconds: u32) -> ExtractionResult {
+    let mut model = Model::default();
+    model.set_parameter("seconds", &timeout_seconds.to_string());
+
+    let class_rows: IndexMap<&ClassId, Row> = egraph
+        .classes()
+        .keys()
+        .map(|cid| {
+            let row = model.add_row();
+            let root_count = roots.iter().filter(|root| *root == cid).count();
+            model.set_row_lower(row, root_count as f64);
+            (cid, row)
+        })
+        .collect();
+
+    let mut uses: IndexMap<&NodeId, Col> = IndexMap::with_capacity(egraph.nodes.len());
+    for (node_id, node) in &egraph.nodes {
+        let node_uses = model.add_integer();
+        model.set_obj_coeff(node_uses, node.cost.into_inner());
+        // A node can be a child of its own class, so the weights are summed first.
+        let mut weights = IndexMap::<&ClassId, f64>::default();
+        *weights.entry(&node.eclass).or_default() += 1.0;
+        for child in &node.children {
+            *weights.entry(egraph.nid_to_cid(child)).or_default() -= 1.0;
+        }
+        for (cid, weight) in weights {
+            if weight != 0.0 {
+                model.set_weight(class_rows[cid], node_uses, weight);
+            }
+        }
+        uses.insert(node_id, node_uses);
+    }
+    model.set_obj_sense(Sense::Minimize);
+
+    let solution = model.solve();
+    let mut stats = SolverStats::default();
+    stats.record_solve(solution.raw());
+    log::info!(
+        "CBC status {:?}, {:?}, obj = {}",
+        solution.raw().status(),
+        solution.raw().secondary_status(),
+        solution.raw().obj_value(),
+    );
+
+    if solution.raw().is_proven_infeasible() {
+        log::info!("Infeasible, returning empty solution");
+        let mut result = ExtractionResult::default();
+        result.solver_stats = Some(stats);
+        return result;
+    }
+    if solution.raw().status() != coin_cbc::raw::Status::Finished {
+        assert!(timeout_seconds != std::u32::MAX);
+        log::info!("Unfinished CBC solution, returning the bottom-up extraction");
+        let mut result = bottom_up::BottomUpExtractor.extract(egraph, roots);
+        result.solver_stats = Some(stats);
+        return result;
+    }
+
+    // The cheapest term for each class among the used nodes, as `BottomUpExtractor`
+    // finds it among all of them.
+    let used: Vec<&NodeId> = uses
+        .iter()
+        .filter(|(_, node_uses)| solution.col(**node_uses) > 0.5)
+        .map(|(&node_id, _)| node_id)
+        .collect();
+    let mut result = ExtractionResult::default();
+    let mut costs = FxHashMap::<&ClassId, Cost>::default();
+    let mut did_something = true;
+    while did_something {
+        did_something = false;
+        for &node_id in &used {
+            let node = &egraph[node_id];
+            let cost = node.cost
+                + node
+                    .children
+                    .iter()
+                    .map(|c| *costs.get(egraph.nid_to_cid(c)).unwrap_or(&INFINITY))
+                    .sum::<Cost>();
+            if cost < *costs.get(&node.eclass).unwrap_or(&INFINITY) {
+                result.choose(node.eclass.clone(), node_id.clone());
+                costs.insert(&node.eclass, cost);
+                did_something = true;
+            }
+        }
+    }
+    result.solver_stats = Some(stats);
+    result
+}
+
+// Constraints beyond those of a valid extraction.
+#[derive(Default)]
+struct Constraints<'a> {
+    requirements: Vec<suite_rules::Requirement>,
+    // For each exclusive group, for each of its ops, the nodes with that op.
+    exclusive: Vec<Vec<Vec<NodeId>>>,
+    diverse_from: Option<(&'a ExtractionResult, usize)>,
+    max_depth: Option<Cost>,
+}
+
+fn extract(
+    egraph: &EGraph,
+    roots: &[ClassId],
+    timeout_seconds: u32,
+    constraints: &Constraints,
+) -> ExtractionResult {
     let mut model = Model::default();
 
     model.set_parameter("seconds", &timeout_seconds.to_string());
@@ -96,9 +428,101 @@ fn extract(egraph: &EGraph, roots: &[ClassId], timeout_seconds: u32) -> Extracti
         model.set_col_lower(vars[root].active, 1.0);
     }
 
+    let node_var = |nid: &NodeId| {
+        let cid = egraph.nid_to_cid(nid);
+        let idx = egraph[cid].nodes.iter().position(|n| n == nid).unwrap();
+        vars[cid].nodes[idx]
+    };
+    for requirement in &constraints.requirements {
+        if requirement.one_of.contains(&requirement.node) {
+            continue;
+        }
+        // node active implies one of the required nodes active, encoded as:
+        //   node_active - sum(required_active) <= 0
+        let row = model.add_row();
+        model.set_row_upper(row, 0.0);
+        model.set_weight(row, node_var(&requirement.node), 1.0);
+        for nid in requirement.one_of.iter() {
+            model.set_weight(row, node_var(nid), -1.0);
+        }
+    }
+
+    for group in &constraints.exclusive {
+        // at most one of the group's ops used, encoded as:
+        //   node_active - op_used <= 0, for each node with the op
+        //   sum(op_used) <= 1
+        let group_row = model.add_row();
+        model.set_row_upper(group_row, 1.0);
+        for nodes in group.iter().filter(|nodes| !nodes.is_empty()) {
+            let op_used = model.add_binary();
+            model.set_weight(group_row, op_used, 1.0);
+            for nid in nodes {
+                let row = model.add_row();
+                model.set_row_upper(row, 0.0);
+                model.set_weight(row, node_var(nid), 1.0);
+                model.set_weight(row, op_used, -1.0);
+            }
+        }
+    }
+
+    if let Some((reference, distance)) = constraints.diverse_from {
+        // Reference choices that aren't in this egraph can't be made, so always differ.
+        //   sum(reference_node_active) <= reference_choices - distance
+        let row = model.add_row();
+        model.set_row_upper(row, reference.choices.len() as f64 - distance as f64);
+        for (cid, nid) in &reference.choices {
+            if egraph.nodes.get(nid).is_some_and(|n| n.eclass == *cid) {
+                model.set_weight(row, node_var(nid), 1.0);
+            }
+        }
+    }
+
+    if let Some(max_depth) = constraints.max_depth {
+        // Big enough that a constraint for an inactive node always holds.
+        let max_cost = egraph
+            .nodes
+            .values()
+            .map(|n| n.cost)
+            .max()
+            .unwrap_or_default();
+        let big_m = (max_depth + max_cost).into_inner() + 1.0;
+
+        let depths: IndexMap<&ClassId, Col> = vars
+            .keys()
+            .map(|cid| {
+                let depth = model.add_col();
+                model.set_col_upper(depth, max_depth.into_inner() + EPSILON_ALLOWANCE);
+                (cid, depth)
+            })
+            .collect();
+
+        for (class_id, class) in &vars {
+            for (node_id, &node_active) in egraph[class_id].nodes.iter().zip(&class.nodes) {
+                let node = &egraph[node_id];
+                let children_classes: IndexSet<&ClassId> =
+                    node.children.iter().map(|c| egraph.nid_to_cid(c)).collect();
+                for child in children_classes {
+                    // Self loops are already blocked by `block_cycles`.
+                    if child == class_id {
+                        continue;
+                    }
+                    // node active implies class depth >= node cost + child depth, encoded as:
+                    //   depth - child_depth - big_m * node_active >= cost - big_m
+                    let row = model.add_row();
+                    model.set_row_lower(row, node.cost.into_inner() - big_m);
+                    model.set_weight(row, depths[class_id], 1.0);
+                    model.set_weight(row, depths[child], -1.0);
+                    model.set_weight(row, node_active, -big_m);
+                }
+            }
+        }
+    }
+
     block_cycles(&mut model, &vars, &egraph);
 
     let solution = model.solve();
+    let mut stats = SolverStats::default();
+    stats.record_solve(solution.raw());
     log::info!(
         "CBC status {:?}, {:?}, obj = {}",
         solution.raw().status(),
@@ -106,31 +530,103 @@ fn extract(egraph: &EGraph, roots: &[ClassId], timeout_seconds: u32) -> Extracti
         solution.raw().obj_value(),
     );
 
+    if solution.raw().is_proven_infeasible() {
+        log::info!("Infeasible, returning empty solution");
+        let mut result = ExtractionResult::default();
+        result.solver_stats = Some(stats);
+        return result;
+    }
+
+    // Only an extraction that meets the constraints will do, however cheap.
+    let acceptable = |result: &ExtractionResult| {
+        let far_enough = constraints
+            .diverse_from
+            .is_none_or(|(reference, distance)| {
+                let mut result = result.clone();
+                result.retain_reachable(egraph, roots);
+                result.differing_choices(reference) >= distance
+            });
+        let shallow_enough = constraints.max_depth.is_none_or(|max_depth| {
+            result.depth_cost(egraph, roots).into_inner()
+                <= max_depth.into_inner() + EPSILON_ALLOWANCE
+        });
+        let exclusive_enough = constraints.exclusive.is_empty() || {
+            let mut result = result.clone();
+            result.retain_reachable(egraph, roots);
+            let chosen: FxHashSet<&NodeId> = result.choices.values().collect();
+            constraints.exclusive.iter().all(|group| {
+                let used = group
+                    .iter()
+                    .filter(|nodes| nodes.iter().any(|nid| chosen.contains(nid)));
+                used.count() <= 1
+            })
+        };
+        far_enough && shallow_enough && exclusive_enough
+    };
+
     if solution.raw().status() != coin_cbc::raw::Status::Finished {
         assert!(timeout_seconds != std::u32::MAX);
 
-        let initial_result =
-            super::faster_greedy_dag::FasterGreedyDagExtractor.extract(egraph, roots);
+        let mut initial_result = match constraints.max_depth {
+            None => super::faster_greedy_dag::FasterGreedyDagExtractor.extract(egraph, roots),
+            Some(_) => bottom_up::DepthBottomUpExtractor.extract(egraph, roots),
+        };
         log::info!("Unfinished CBC solution");
+        if !acceptable(&initial_result) {
+            initial_result = ExtractionResult::default();
+        }
+
+        // The columns hold the best solution found before the time ran out, if any.
+        // It's only used if it's a complete, acyclic extraction that's cheaper.
+        if let Some(mut incumbent) = read_solution(egraph, &vars, &solution) {
+            let tree_costs = incumbent.choice_tree_costs(egraph);
+            if roots.iter().all(|root| tree_costs.contains_key(root))
+                && acceptable(&incumbent)
+                && (initial_result.choices.is_empty()
+                    || incumbent.dag_cost(egraph, roots) < initial_result.dag_cost(egraph, roots))
+            {
+                log::info!("Returning the incumbent of the unfinished search");
+                incumbent.solver_stats = Some(stats);
+                return incumbent;
+            }
+        }
+
+        initial_result.solver_stats = Some(stats);
         return initial_result;
     }
 
-    let mut result = ExtractionResult::default();
+    let mut result = read_solution(egraph, &vars, &solution).unwrap_or_else(|| {
+        log::warn!(
+            "CBC's solution doesn't choose one node per active class, returning empty solution"
+        );
+        ExtractionResult::default()
+    });
+    result.solver_stats = Some(stats);
+    return result;
+}
 
-    for (id, var) in &vars {
-        let active = solution.col(var.active) > 0.0;
+// None unless each active class has exactly one active node. The columns are binary,
+// but within the solver's tolerances, so a column is active if it's above a half.
+fn read_solution(
+    egraph: &EGraph,
+    vars: &IndexMap<ClassId, ClassVars>,
+    solution: &coin_cbc::Solution,
+) -> Option<ExtractionResult> {
+    let mut result = ExtractionResult::default();
+    for (id, var) in vars {
+        let active = solution.col(var.active) > 0.5;
         if active {
-            let node_idx = var
-                .nodes
-                .iter()
-                .position(|&n| solution.col(n) > 0.0)
-                .unwrap();
-            let node_id = egraph[id].nodes[node_idx].clone();
+            let active_nodes: Vec<usize> = (0..var.nodes.len())
+                .filter(|&i| solution.col(var.nodes[i]) > 0.5)
+                .collect();
+            if active_nodes.len() != 1 {
+                return None;
+            }
+            let node_id = egraph[id].nodes[active_nodes[0]].clone();
             result.choose(id.clone(), node_id);
         }
     }
-
-    return result;
+    Some(result)
 }
 
 /*
@@ -0,0 +1,61 @@
+/*
+A soft limit on the process's memory use, so that an extractor can give up on its
+memory-hungry approach and fall back to a cheaper one before being OOM-killed.
+
+The limit is process wide and set once from the command line with `--soft-memory-limit-mb`.
+Extractors that support it call `exceeded()` periodically. Memory use is read from
+the resident set size in /proc/self/status, so on other platforms the limit is never
+reported as exceeded.
+
+The same file gives the peak resident set size, which each result records as
+`peak_bytes`. Before the extraction the peak is reset with /proc/self/clear_refs, so
+it's the extraction's peak, including the egraph it was given. Where the peak can't
+be reset, it's the process's peak, which parsing the egraph may set.
+*/
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+// Zero means no limit.
+static SOFT_LIMIT_BYTES: AtomicU64 = AtomicU64::new(0);
+
+// How many units of work extractors should do between checks, reading
+// /proc/self/status each time would dominate the runtime otherwise.
+pub const CHECK_INTERVAL: usize = 4096;
+
+pub fn set_soft_limit_bytes(bytes: u64) {
+    SOFT_LIMIT_BYTES.store(bytes, Ordering::Relaxed);
+}
+
+pub fn soft_limit_bytes() -> Option<u64> {
+    match SOFT_LIMIT_BYTES.load(Ordering::Relaxed) {
+        0 => None,
+        bytes => Some(bytes),
+    }
+}
+
+pub fn resident_bytes() -> Option<u64> {
+    status_bytes("VmRSS:")
+}
+
+pub fn peak_resident_bytes() -> Option<u64> {
+    status_bytes("VmHWM:")
+}
+
+// Whether the peak was reset to the current resident set size.
+pub fn reset_peak() -> bool {
+    std::fs::write("/proc/self/clear_refs", "5").is_ok()
+}
+
+fn status_bytes(field: &str) -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|l| l.starts_with(field))?;
+    let kb: u64 = line.split_whitespace().nth(1)?.parse().ok()?;
+    Some(kb * 1024)
+}
+
+pub fn exceeded() -> bool {
+    match (soft_limit_bytes(), resident_bytes()) {
+        (Some(limit), Some(used)) => used > limit,
+        _ => false,
+    }
+}
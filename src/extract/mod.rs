@@ -3,8 +3,24 @@ use rustc_hash::{FxHashMap, FxHashSet};
 use std::collections::HashMap;
 
 pub use crate::*;
+pub use cost_cache::CostCache;
+pub use cost_model::CostModel;
 
+#[doc(hidden)] // experimental
+pub mod a_star;
 pub mod bottom_up;
+pub mod canonicalize;
+pub mod cost_cache;
+pub mod cost_model;
+#[doc(hidden)] // experimental
+pub mod cse_greedy_dag;
+pub mod cycles;
+pub mod decompose;
+#[cfg(feature = "egg-interop")]
+pub mod egg_interop;
+#[cfg(feature = "exact-costs")]
+pub mod exact_cost;
+pub mod fast_egraph;
 pub mod faster_bottom_up;
 pub mod faster_greedy_dag;
 #[cfg(feature = "ilp-cbc")]
@@ -13,6 +29,22 @@ pub mod global_greedy_dag;
 pub mod greedy_dag;
 #[cfg(feature = "ilp-cbc")]
 pub mod ilp_cbc;
+pub mod memory_limit;
+pub mod op_filter;
+pub mod op_policy;
+pub mod parallel;
+pub mod per_root;
+pub mod preprocess;
+pub mod repair;
+pub mod restarts;
+#[cfg(feature = "smt")]
+pub mod smt_z3;
+pub mod soundness;
+pub mod suite_rules;
+pub mod timeout;
+#[doc(hidden)] // experimental
+pub mod top_down;
+pub mod view;
 
 // Allowance for floating point values to be considered equal
 pub const EPSILON_ALLOWANCE: f64 = 0.00001;
@@ -20,6 +52,45 @@ pub const EPSILON_ALLOWANCE: f64 = 0.00001;
 pub trait Extractor: Sync {
     fn extract(&self, egraph: &EGraph, roots: &[ClassId]) -> ExtractionResult;
 
+    // Like `extract`, but may reuse analysis of the egraph that's shared with
+    // other extractors run on the same egraph. The cache must be for `egraph`.
+    fn extract_with_cache(
+        &self,
+        egraph: &EGraph,
+        roots: &[ClassId],
+        _cache: &CostCache,
+    ) -> ExtractionResult {
+        self.extract(egraph, roots)
+    }
+
+    // Like `extract`, but starting from a previous extraction, which may be for a
+    // slightly different egraph. Extractors that don't support hints ignore it.
+    fn extract_with_hint(
+        &self,
+        egraph: &EGraph,
+        roots: &[ClassId],
+        _hint: &ExtractionResult,
+    ) -> ExtractionResult {
+        self.extract(egraph, roots)
+    }
+
+    // Like `extract`, for extractors that make random choices, which make them with
+    // `seed`. Deterministic extractors ignore it.
+    fn extract_with_seed(
+        &self,
+        egraph: &EGraph,
+        roots: &[ClassId],
+        _seed: u64,
+    ) -> ExtractionResult {
+        self.extract(egraph, roots)
+    }
+
+    // Like `extract`, but only choosing nodes in the view of the egraph. By default
+    // the view is copied into a new egraph first, see `view::EGraphView`.
+    fn extract_view(&self, view: &view::EGraphView, roots: &[ClassId]) -> ExtractionResult {
+        self.extract(&view.materialize(), roots)
+    }
+
     fn boxed(self) -> Box<dyn Extractor>
     where
         Self: Sized + 'static,
@@ -62,6 +133,66 @@ where
 #[derive(Default, Clone)]
 pub struct ExtractionResult {
     pub choices: IndexMap<ClassId, NodeId>,
+    // Set if the extractor hit the soft memory limit and fell back to a cheaper method.
+    pub memory_limit_exceeded: bool,
+    // Set by the ILP extractors if they called the solver.
+    pub solver_stats: Option<SolverStats>,
+}
+
+// How much work the ILP solver did, so changes to the encoding can be compared
+// by solver effort as well as by the noisier wall-clock time.
+#[derive(Default, Clone, Debug, serde::Serialize)]
+pub struct SolverStats {
+    // How many times the solver was called, more than once if cycles were blocked.
+    pub solves: usize,
+    // The size of the model the last time it was solved.
+    pub rows: usize,
+    pub cols: usize,
+    // Constraints added between solves to block cycles in the solution.
+    pub cycle_constraints: usize,
+    // The objective of the last solution, and the solver's bound on the best possible.
+    pub objective: f64,
+    pub best_bound: f64,
+    // Simplification passes that were enabled but skipped as unlikely to pay off.
+    pub skipped_passes: Vec<&'static str>,
+}
+
+impl SolverStats {
+    #[cfg(feature = "ilp-cbc")]
+    pub(crate) fn record_solve(&mut self, model: &coin_cbc::raw::Model) {
+        self.solves += 1;
+        self.rows = model.number_rows();
+        self.cols = model.number_cols();
+        self.objective = model.obj_value();
+        self.best_bound = model.best_possible_value();
+    }
+}
+
+// Like `ExtractionResult`, but borrowing the ids from the egraph rather than
+// cloning them, which adds up when extracting in a loop. Extractors that can
+// produce one convert it with `into_owned` only when an owned result is needed.
+#[derive(Default, Clone)]
+pub struct ExtractionResultRef<'a> {
+    pub choices: IndexMap<&'a ClassId, &'a NodeId>,
+}
+
+impl<'a> ExtractionResultRef<'a> {
+    pub fn choose(&mut self, class_id: &'a ClassId, node_id: &'a NodeId) {
+        self.choices.insert(class_id, node_id);
+    }
+
+    pub fn into_owned(self) -> ExtractionResult {
+        let mut result = ExtractionResult::default();
+        for (class_id, node_id) in self.choices {
+            result.choose(class_id.clone(), node_id.clone());
+        }
+        result
+    }
+}
+
+pub struct RootCost {
+    pub tree: Cost,
+    pub dag: Cost,
 }
 
 #[derive(Clone, Copy)]
@@ -71,6 +202,41 @@ enum Status {
 }
 
 impl ExtractionResult {
+    // Reads the choices written by `to_json_file`.
+    pub fn from_json_file(path: impl AsRef<std::path::Path>) -> anyhow::Result<Self> {
+        let file = std::fs::File::open(path)?;
+        let json: serde_json::Value = serde_json::from_reader(std::io::BufReader::new(file))?;
+        let choices = json
+            .get("choices")
+            .and_then(|c| c.as_object())
+            .ok_or_else(|| anyhow::anyhow!("Expected an object with a \"choices\" field"))?;
+
+        let mut result = ExtractionResult::default();
+        for (class_id, node_id) in choices {
+            let node_id = node_id
+                .as_str()
+                .ok_or_else(|| anyhow::anyhow!("Expected a node id for class {class_id}"))?;
+            result.choose(class_id.clone().into(), node_id.to_string().into());
+        }
+        Ok(result)
+    }
+
+    // Writes the choices as {"choices": {class_id: node_id, ...}}, sorted by class id,
+    // so the same choices always give the same file, see `sort_choices`.
+    pub fn to_json_file(&self, path: impl AsRef<std::path::Path>) -> anyhow::Result<()> {
+        let mut sorted = self.clone();
+        sorted.sort_choices();
+        let choices: serde_json::Map<String, serde_json::Value> = sorted
+            .choices
+            .iter()
+            .map(|(cid, nid)| (cid.to_string(), nid.to_string().into()))
+            .collect();
+        let json = serde_json::json!({ "choices": choices });
+        let file = std::fs::File::create(path)?;
+        serde_json::to_writer_pretty(std::io::BufWriter::new(file), &json)?;
+        Ok(())
+    }
+
     pub fn check(&self, egraph: &EGraph) {
         // should be a root
         assert!(!egraph.root_eclasses.is_empty());
@@ -104,42 +270,130 @@ impl ExtractionResult {
         }
     }
 
+    // The choices are in the order the extractor made them, which differs between
+    // extractors, and between runs of some. Sorting them by class id makes two
+    // results with the same choices iterate, and so serialize, the same way.
+    pub fn sort_choices(&mut self) {
+        self.choices.sort_keys();
+    }
+
+    // How many of the reference's choices this result doesn't make, either because
+    // it doesn't choose the class or chooses another node for it.
+    pub fn differing_choices(&self, reference: &ExtractionResult) -> usize {
+        reference
+            .choices
+            .iter()
+            .filter(|(cid, nid)| self.choices.get(*cid) != Some(*nid))
+            .count()
+    }
+
     pub fn choose(&mut self, class_id: ClassId, node_id: NodeId) {
         self.choices.insert(class_id, node_id);
     }
 
-    pub fn find_cycles(&self, egraph: &EGraph, roots: &[ClassId]) -> Vec<ClassId> {
-        // let mut status = vec![Status::Todo; egraph.classes().len()];
+    // Removes the choices for classes that can't be reached from the roots.
+    // Returns how many were removed.
+    pub fn retain_reachable(&mut self, egraph: &EGraph, roots: &[ClassId]) -> usize {
+        let mut todo: Vec<ClassId> = roots.to_vec();
+        let mut reachable: FxHashSet<ClassId> = Default::default();
+        while let Some(cid) = todo.pop() {
+            if !reachable.insert(cid.clone()) {
+                continue;
+            }
+            if let Some(nid) = self.choices.get(&cid) {
+                for child in &egraph[nid].children {
+                    todo.push(egraph.nid_to_cid(child).clone());
+                }
+            }
+        }
+
+        let before = self.choices.len();
+        self.choices.retain(|cid, _| reachable.contains(cid));
+        before - self.choices.len()
+    }
+
+    // A copy with only the choices the roots need, see `retain_reachable`.
+    pub fn minimize(&self, egraph: &EGraph, roots: &[ClassId]) -> ExtractionResult {
+        let mut minimized = self.clone();
+        minimized.retain_reachable(egraph, roots);
+        minimized
+    }
+
+    // The tree cost of the term selected for each class, for the classes whose
+    // selected term exists in `egraph`, is complete, and is acyclic.
+    // Children come before their parents.
+    pub fn choice_tree_costs(&self, egraph: &EGraph) -> IndexMap<ClassId, Cost> {
         let mut status = IndexMap::<ClassId, Status>::default();
-        let mut cycles = vec![];
-        for root in roots {
-            // let root_index = egraph.classes().get_index_of(root).unwrap();
-            self.cycle_dfs(egraph, root, &mut status, &mut cycles)
+        let mut costs = IndexMap::<ClassId, Cost>::default();
+        for class_id in self.choices.keys() {
+            self.choice_tree_cost_rec(egraph, class_id, &mut status, &mut costs);
         }
-        cycles
+        costs
     }
 
-    fn cycle_dfs(
+    fn choice_tree_cost_rec(
         &self,
         egraph: &EGraph,
         class_id: &ClassId,
         status: &mut IndexMap<ClassId, Status>,
-        cycles: &mut Vec<ClassId>,
-    ) {
-        match status.get(class_id).cloned() {
-            Some(Status::Done) => (),
-            Some(Status::Doing) => cycles.push(class_id.clone()),
-            None => {
-                status.insert(class_id.clone(), Status::Doing);
-                let node_id = &self.choices[class_id];
-                let node = &egraph[node_id];
+        costs: &mut IndexMap<ClassId, Cost>,
+    ) -> Option<Cost> {
+        match status.get(class_id) {
+            Some(Status::Done) => return costs.get(class_id).copied(),
+            // The selected term is cyclic.
+            Some(Status::Doing) => return None,
+            None => (),
+        }
+        status.insert(class_id.clone(), Status::Doing);
+
+        let mut cost = None;
+        let node = self
+            .choices
+            .get(class_id)
+            .and_then(|nid| egraph.nodes.get(nid));
+        if let Some(node) = node {
+            if node.eclass == *class_id {
+                cost = Some(node.cost);
                 for child in &node.children {
                     let child_cid = egraph.nid_to_cid(child);
-                    self.cycle_dfs(egraph, child_cid, status, cycles)
+                    let child_cost = self.choice_tree_cost_rec(egraph, child_cid, status, costs);
+                    cost = cost.zip(child_cost).map(|(a, b)| a + b);
                 }
-                status.insert(class_id.clone(), Status::Done);
             }
         }
+
+        status.insert(class_id.clone(), Status::Done);
+        if let Some(cost) = cost {
+            costs.insert(class_id.clone(), cost);
+        }
+        cost
+    }
+
+    // Keeps only the choices whose selected term is complete and acyclic in `egraph`.
+    // This makes a result from an earlier version of an egraph safe to use as a starting point.
+    pub fn valid_part(&self, egraph: &EGraph) -> ExtractionResult {
+        let mut result = ExtractionResult::default();
+        for class_id in self.choice_tree_costs(egraph).keys() {
+            result.choose(class_id.clone(), self.choices[class_id].clone());
+        }
+        result
+    }
+
+    // The classes an edge points back to, see `cycles::find_cycles`.
+    pub fn find_cycles(&self, egraph: &EGraph, roots: &[ClassId]) -> Vec<ClassId> {
+        cycles::find_cycles(
+            roots,
+            |cid| {
+                egraph[&self.choices[cid]]
+                    .children
+                    .iter()
+                    .map(move |c| egraph.nid_to_cid(c))
+            },
+            usize::MAX,
+        )
+        .into_iter()
+        .map(|mut cycle| cycle.swap_remove(0))
+        .collect()
     }
 
     pub fn tree_cost(&self, egraph: &EGraph, roots: &[ClassId]) -> Cost {
@@ -171,8 +425,86 @@ impl ExtractionResult {
         cost
     }
 
+    // Like `tree_cost`, but a node at depth d (the roots being at depth 0)
+    // contributes gamma^d times its cost.
+    pub fn discounted_tree_cost(&self, egraph: &EGraph, roots: &[ClassId], gamma: f64) -> Cost {
+        let node_roots = roots
+            .iter()
+            .map(|cid| self.choices[cid].clone())
+            .collect::<Vec<NodeId>>();
+        self.discounted_tree_cost_rec(egraph, &node_roots, gamma, &mut HashMap::new())
+    }
+
+    // The memo holds each subterm's cost as if it were at depth 0,
+    // which is then discounted according to where it's used.
+    fn discounted_tree_cost_rec(
+        &self,
+        egraph: &EGraph,
+        roots: &[NodeId],
+        gamma: f64,
+        memo: &mut HashMap<NodeId, Cost>,
+    ) -> Cost {
+        let mut cost = Cost::default();
+        for root in roots {
+            if let Some(c) = memo.get(root) {
+                cost += *c;
+                continue;
+            }
+            let class = egraph.nid_to_cid(root);
+            let node = &egraph[&self.choices[class]];
+            let inner = node.cost
+                + self.discounted_tree_cost_rec(egraph, &node.children, gamma, memo) * gamma;
+            memo.insert(root.clone(), inner);
+            cost += inner;
+        }
+        cost
+    }
+
+    // The critical path: the most that any path from a root down to a leaf costs,
+    // each node's cost being its latency. Sharing doesn't shorten paths, so unlike the
+    // tree and dag costs, the tree and dag depths are the same.
+    pub fn depth_cost(&self, egraph: &EGraph, roots: &[ClassId]) -> Cost {
+        let mut memo = HashMap::new();
+        roots
+            .iter()
+            .map(|root| self.depth_cost_rec(egraph, root, &mut memo))
+            .max()
+            .unwrap_or_default()
+    }
+
+    fn depth_cost_rec(
+        &self,
+        egraph: &EGraph,
+        class: &ClassId,
+        memo: &mut HashMap<ClassId, Cost>,
+    ) -> Cost {
+        if let Some(depth) = memo.get(class) {
+            return *depth;
+        }
+        let node = &egraph[&self.choices[class]];
+        let depth = node.cost
+            + node
+                .children
+                .iter()
+                .map(|c| self.depth_cost_rec(egraph, egraph.nid_to_cid(c), memo))
+                .max()
+                .unwrap_or_default();
+        memo.insert(class.clone(), depth);
+        depth
+    }
+
     // this will loop if there are cycles
     pub fn dag_cost(&self, egraph: &EGraph, roots: &[ClassId]) -> Cost {
+        self.dag_cost_breakdown(egraph, roots).values().sum()
+    }
+
+    // Each class used by the roots, with the cost of its selected node. Shared classes
+    // appear once, so the values sum to the dag cost.
+    pub fn dag_cost_breakdown(
+        &self,
+        egraph: &EGraph,
+        roots: &[ClassId],
+    ) -> IndexMap<ClassId, Cost> {
         let mut costs: IndexMap<ClassId, Cost> = IndexMap::new();
         let mut todo: Vec<ClassId> = roots.to_vec();
         while let Some(cid) = todo.pop() {
@@ -185,7 +517,112 @@ impl ExtractionResult {
                 todo.push(egraph.nid_to_cid(child).clone());
             }
         }
-        costs.values().sum()
+        costs
+    }
+
+    // Like `dag_cost`, but selected subterms that are structurally identical (the
+    // same op, with identical children subterms) are only counted once, even if
+    // they're in different classes. Each distinct subterm costs what its node
+    // costs where it's first reached. A cyclic selection has no finite term, so
+    // costs INFINITY.
+    pub fn cse_dag_cost(&self, egraph: &EGraph, roots: &[ClassId]) -> Cost {
+        let mut terms = FxHashMap::<(&str, Vec<usize>), usize>::default();
+        let mut class_terms = FxHashMap::<&ClassId, usize>::default();
+        let mut on_stack = FxHashSet::<&ClassId>::default();
+        let mut cost = Cost::default();
+
+        // Depth first, with an explicit stack, as big egraphs can be very deep. A class
+        // is hash-consed once its children have been.
+        let mut stack: Vec<(&ClassId, bool)> = roots.iter().map(|r| (r, false)).collect();
+        while let Some((class_id, children_done)) = stack.pop() {
+            let node = &egraph[&self.choices[class_id]];
+            if children_done {
+                let children: Vec<usize> = node
+                    .children
+                    .iter()
+                    .map(|c| class_terms[egraph.nid_to_cid(c)])
+                    .collect();
+                let next = terms.len();
+                let term = *terms
+                    .entry((node.op.as_str(), children))
+                    .or_insert_with(|| {
+                        cost += node.cost;
+                        next
+                    });
+                class_terms.insert(class_id, term);
+                on_stack.remove(class_id);
+                continue;
+            }
+            if class_terms.contains_key(class_id) {
+                continue;
+            }
+            if !on_stack.insert(class_id) {
+                return INFINITY;
+            }
+            stack.push((class_id, true));
+            for child in &node.children {
+                stack.push((egraph.nid_to_cid(child), false));
+            }
+        }
+        cost
+    }
+
+    // The costs of each root, in the order of `roots`. A root's tree cost only
+    // counts sharing within its own term, so they sum to `tree_cost`. A root's dag
+    // cost only includes the classes no earlier root uses, so sharing between roots
+    // is counted once, and they sum to `dag_cost`.
+    pub fn root_costs(&self, egraph: &EGraph, roots: &[ClassId]) -> Vec<RootCost> {
+        let mut counted: FxHashSet<ClassId> = Default::default();
+        roots
+            .iter()
+            .map(|root| {
+                let root = std::slice::from_ref(root);
+                let dag = self
+                    .dag_cost_breakdown(egraph, root)
+                    .into_iter()
+                    .filter(|(cid, _)| counted.insert(cid.clone()))
+                    .map(|(_, cost)| cost)
+                    .sum();
+                RootCost {
+                    tree: self.tree_cost(egraph, root),
+                    dag,
+                }
+            })
+            .collect()
+    }
+
+    // The term selected for `root` as an S-expression, with ops quoted where they
+    // wouldn't read back as a single atom. Shared subterms are written out each
+    // time they're used, so this can be much larger than the dag.
+    pub fn to_sexp(&self, egraph: &EGraph, root: &ClassId) -> String {
+        let mut sexp = String::new();
+        self.write_sexp(egraph, root, &mut sexp);
+        sexp
+    }
+
+    fn write_sexp(&self, egraph: &EGraph, class_id: &ClassId, sexp: &mut String) {
+        let node = &egraph[&self.choices[class_id]];
+        let needs_quotes = node.op.is_empty()
+            || node
+                .op
+                .contains(|c: char| c.is_whitespace() || "()\";".contains(c));
+        let op = if needs_quotes {
+            format!("{:?}", node.op)
+        } else {
+            node.op.clone()
+        };
+
+        if node.children.is_empty() {
+            sexp.push_str(&op);
+            return;
+        }
+        sexp.push('(');
+        sexp.push_str(&op);
+        for child in &node.children {
+            sexp.push(' ');
+            self.write_sexp(egraph, egraph.nid_to_cid(child), sexp);
+        }
+        sexp.push(')');
     }
 
     pub fn node_sum_cost<M>(&self, egraph: &EGraph, node: &Node, costs: &M) -> Cost
@@ -202,4 +639,27 @@ impl ExtractionResult {
                 })
                 .sum::<Cost>()
     }
+
+    // The cost of `node` where each child class costs `costs` discounted by `gamma`.
+    pub fn node_discounted_cost<M>(
+        &self,
+        egraph: &EGraph,
+        node: &Node,
+        costs: &M,
+        gamma: f64,
+    ) -> Cost
+    where
+        M: MapGet<ClassId, Cost>,
+    {
+        node.cost
+            + node
+                .children
+                .iter()
+                .map(|n| {
+                    let cid = egraph.nid_to_cid(n);
+                    costs.get(cid).unwrap_or(&INFINITY)
+                })
+                .sum::<Cost>()
+                * gamma
+    }
 }
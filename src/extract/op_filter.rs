@@ -0,0 +1,75 @@
+/*
+Restricts which nodes are eligible for selection, based on their op. This is useful
+to model backends that lack certain instructions.
+
+The filter is applied to the egraph before extraction, so it works with every extractor.
+Nodes with a disallowed op are removed, as are nodes that depend on a class that no
+longer has any nodes. It's an error if a root can no longer be extracted.
+*/
+
+use super::*;
+
+#[derive(Default, Debug)]
+pub struct OpFilter {
+    // If set, only these ops may be selected.
+    pub only: Option<FxHashSet<String>>,
+    // These ops may not be selected.
+    pub exclude: FxHashSet<String>,
+}
+
+impl OpFilter {
+    // Builds a filter from comma separated lists of ops, as given on the command line.
+    pub fn from_lists(only: Option<&str>, exclude: Option<&str>) -> Self {
+        let split = |ops: &str| -> FxHashSet<String> {
+            ops.split(',')
+                .map(str::trim)
+                .filter(|op| !op.is_empty())
+                .map(String::from)
+                .collect()
+        };
+        Self {
+            only: only.map(split),
+            exclude: exclude.map(split).unwrap_or_default(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.only.is_none() && self.exclude.is_empty()
+    }
+
+    pub fn allows(&self, op: &str) -> bool {
+        if self.exclude.contains(op) {
+            return false;
+        }
+        match &self.only {
+            Some(only) => only.contains(op),
+            None => true,
+        }
+    }
+
+    pub fn apply(&self, egraph: &EGraph) -> anyhow::Result<EGraph> {
+        let kept: FxHashSet<NodeId> = egraph
+            .nodes
+            .iter()
+            .filter(|(_, node)| self.allows(&node.op))
+            .map(|(nid, _)| nid.clone())
+            .collect();
+
+        let filtered = canonicalize::rebuild_with(egraph, kept);
+
+        let extractable = canonicalize::extractable_classes(&filtered);
+        for root in &filtered.root_eclasses {
+            if !extractable.contains(root) {
+                anyhow::bail!("Root class {root:?} can't be extracted using only the allowed ops");
+            }
+        }
+
+        log::info!(
+            "Op filter removed {} of {} nodes",
+            egraph.nodes.len() - filtered.nodes.len(),
+            egraph.nodes.len()
+        );
+
+        Ok(filtered)
+    }
+}
@@ -0,0 +1,160 @@
+/*
+Policies some backends have on which ops an extraction can use together. An
+exclusive group is a list of ops, such as different implementations of the same
+operation, of which an extraction may use at most one, however many nodes it chooses
+with that op. Policies are read from a JSON file given with `--policy`:
+
+    {"exclusive": [["MulDsp", "MulLut"], ["AddFast", "AddSmall"]]}
+
+The ILP extractors, with the ilp-cbc feature, meet the policy exactly, see
+`ilp_cbc::CbcExtractorWithPolicy`. Any other extractor meets it approximately with
+`PenalizedExtractor`: while the extraction uses more than one op of a group, every
+op of the group except the one the most chosen nodes use gets a penalty, which makes
+its nodes cost more than any extraction without them, and the egraph is extracted
+again. That can still break the policy, if no extraction of the roots avoids the
+penalized ops, or if keeping another op would have been better, so the extraction is
+checked, and its violations are reported.
+*/
+
+use super::*;
+use serde::Deserialize;
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct OpPolicy {
+    pub exclusive: Vec<Vec<String>>,
+}
+
+// An extraction that uses more than one op of the exclusive group at `group`.
+#[derive(Debug)]
+pub struct Violation {
+    pub group: usize,
+    // The ops of the group it uses, in the group's order.
+    pub ops: Vec<String>,
+}
+
+impl OpPolicy {
+    pub fn from_json_file(path: &std::path::Path) -> anyhow::Result<Self> {
+        let file = std::fs::File::open(path)?;
+        Ok(serde_json::from_reader(std::io::BufReader::new(file))?)
+    }
+
+    // For each exclusive group, and each of its ops, the nodes with that op.
+    pub fn exclusive_nodes(&self, egraph: &EGraph) -> Vec<Vec<Vec<NodeId>>> {
+        self.exclusive
+            .iter()
+            .map(|ops| {
+                ops.iter()
+                    .map(|op| {
+                        egraph
+                            .nodes
+                            .iter()
+                            .filter(|(_, node)| node.op == *op)
+                            .map(|(nid, _)| nid.clone())
+                            .collect()
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    // Only the choices reachable from the roots count.
+    pub fn violations(
+        &self,
+        egraph: &EGraph,
+        result: &ExtractionResult,
+        roots: &[ClassId],
+    ) -> Vec<Violation> {
+        let uses = self.op_uses(egraph, result, roots);
+        self.exclusive
+            .iter()
+            .enumerate()
+            .filter_map(|(group, ops)| {
+                let used: Vec<String> = ops
+                    .iter()
+                    .filter(|op| uses.contains_key(op.as_str()))
+                    .cloned()
+                    .collect();
+                (used.len() > 1).then_some(Violation { group, ops: used })
+            })
+            .collect()
+    }
+
+    // Errors if the extraction breaks the policy.
+    pub fn check(
+        &self,
+        egraph: &EGraph,
+        result: &ExtractionResult,
+        roots: &[ClassId],
+    ) -> anyhow::Result<()> {
+        match self.violations(egraph, result, roots).first() {
+            None => Ok(()),
+            Some(violation) => anyhow::bail!(
+                "The extraction uses {:?}, of which at most one may be used",
+                violation.ops
+            ),
+        }
+    }
+
+    // How many reachable chosen nodes have each op.
+    fn op_uses<'a>(
+        &self,
+        egraph: &'a EGraph,
+        result: &ExtractionResult,
+        roots: &[ClassId],
+    ) -> FxHashMap<&'a str, usize> {
+        let mut reachable = result.clone();
+        reachable.retain_reachable(egraph, roots);
+        let mut uses = FxHashMap::<&str, usize>::default();
+        for nid in reachable.choices.values() {
+            *uses.entry(egraph[nid].op.as_str()).or_default() += 1;
+        }
+        uses
+    }
+}
+
+pub struct PenalizedExtractor<'a> {
+    pub extractor: &'a dyn Extractor,
+    pub policy: &'a OpPolicy,
+}
+
+impl Extractor for PenalizedExtractor<'_> {
+    fn extract(&self, egraph: &EGraph, roots: &[ClassId]) -> ExtractionResult {
+        // More than every node costs together, so more than any extraction.
+        let penalty =
+            egraph.nodes.values().map(|node| node.cost).sum::<Cost>() + Cost::new(1.0).unwrap();
+
+        let mut penalized = FxHashSet::<String>::default();
+        let mut current = egraph.clone();
+        loop {
+            let result = self.extractor.extract(&current, roots);
+            let uses = self.policy.op_uses(egraph, &result, roots);
+            let mut changed = false;
+            for violation in self.policy.violations(egraph, &result, roots) {
+                // The first op used by the most nodes.
+                let keep = violation
+                    .ops
+                    .iter()
+                    .rev()
+                    .max_by_key(|op| uses[op.as_str()])
+                    .unwrap();
+                for op in &violation.ops {
+                    if op != keep {
+                        changed |= penalized.insert(op.clone());
+                    }
+                }
+            }
+            // Either the policy is met, or the ops it breaks are already penalized.
+            if !changed {
+                return result;
+            }
+            log::info!("Penalizing ops {penalized:?} to meet the policy");
+            current = egraph.clone();
+            for node in current.nodes.values_mut() {
+                if penalized.contains(&node.op) {
+                    node.cost += penalty;
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,38 @@
+/*
+A minimal worker pool for running independent jobs, such as the roots of an
+egraph or the files of a batch, on a fixed number of threads.
+
+Each thread takes the next job that nobody has started on yet, so long jobs don't
+hold up the others. The results are returned in the order of the jobs, whatever
+order they finished in.
+*/
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+// Runs `job(i)` for each i in 0..jobs, on up to `threads` threads.
+pub fn run_jobs<T: Send>(jobs: usize, threads: usize, job: impl Fn(usize) -> T + Sync) -> Vec<T> {
+    assert!(threads > 0);
+    let next_job = AtomicUsize::new(0);
+    let mut done: Vec<(usize, T)> = std::thread::scope(|s| {
+        let handles: Vec<_> = (0..threads.min(jobs))
+            .map(|_| {
+                s.spawn(|| {
+                    let mut done = vec![];
+                    loop {
+                        let i = next_job.fetch_add(1, Ordering::Relaxed);
+                        if i >= jobs {
+                            return done;
+                        }
+                        done.push((i, job(i)));
+                    }
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .flat_map(|h| h.join().unwrap())
+            .collect()
+    });
+    done.sort_by_key(|(i, _)| *i);
+    done.into_iter().map(|(_, result)| result).collect()
+}
@@ -0,0 +1,73 @@
+/*
+Extracts each root separately, on up to `threads` threads, and merges the results.
+The threads share out the roots as in `parallel::run_jobs`.
+
+For multi-root egraphs whose roots share little, this gives most of the speedup
+of running the roots in parallel. Where the per-root results overlap, the merge
+keeps the choice already made for a class, so the term of a root extracted later
+may reuse a different term for a shared class than it would have picked itself.
+
+Each per-root result is walked from its root, stopping at classes that already
+have a choice. Those classes' terms are complete in the merged result already,
+so they can't lead back to a newly added class, and the merge stays acyclic.
+
+The roots share `cache`, so its analysis of the egraph is only done once rather
+than once per root.
+*/
+
+use super::*;
+
+pub struct PerRootResult {
+    pub result: ExtractionResult,
+    // How long extracting each root took, in the order of the roots.
+    pub root_micros: Vec<(ClassId, u128)>,
+}
+
+pub fn extract_per_root(
+    extractor: &dyn Extractor,
+    cache: &CostCache,
+    roots: &[ClassId],
+    threads: usize,
+) -> PerRootResult {
+    let egraph = cache.egraph();
+    let per_root = parallel::run_jobs(roots.len(), threads, |i| {
+        let start_time = std::time::Instant::now();
+        let result = extractor.extract_with_cache(egraph, std::slice::from_ref(&roots[i]), cache);
+        (result, start_time.elapsed().as_micros())
+    });
+
+    let mut merged = ExtractionResult::default();
+    let mut root_micros = vec![];
+    for (root, (result, micros)) in roots.iter().zip(per_root) {
+        merge_from(&mut merged, &result, egraph, root);
+        merged.memory_limit_exceeded |= result.memory_limit_exceeded;
+        root_micros.push((root.clone(), micros));
+    }
+
+    PerRootResult {
+        result: merged,
+        root_micros,
+    }
+}
+
+// Adds the choices `result` makes for the term of `root` that `merged` doesn't have yet.
+fn merge_from(
+    merged: &mut ExtractionResult,
+    result: &ExtractionResult,
+    egraph: &EGraph,
+    root: &ClassId,
+) {
+    let mut todo = vec![root.clone()];
+    while let Some(cid) = todo.pop() {
+        if merged.choices.contains_key(&cid) {
+            continue;
+        }
+        // Missing choices are left for `check` to report.
+        if let Some(nid) = result.choices.get(&cid) {
+            merged.choose(cid, nid.clone());
+            for child in &egraph[nid].children {
+                todo.push(egraph.nid_to_cid(child).clone());
+            }
+        }
+    }
+}
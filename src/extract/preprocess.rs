@@ -0,0 +1,111 @@
+/*
+Shrinks an egraph before extraction without changing the optimal tree, dag or depth
+cost, so any extractor can run on the smaller problem with `--preprocess`:
+
+- Nodes with a child in their own class are removed, as are, when there's a single
+  root, nodes with the root as a child. Neither can be part of a loop-free extraction.
+- A node is removed if another node in its class costs no more and its children
+  classes are a sub-multiset of the node's. Choosing the other node instead never
+  costs more, nor adds an edge, so the optimum doesn't need the removed node.
+- Nodes that depend on a class with no nodes left are removed, see
+  `canonicalize::rebuild_with`.
+- Classes the roots can't reach are removed.
+
+It's an error if a root is left without nodes, as it then has no loop-free term.
+
+These are the reductions the faster ILP extractor makes that only remove nodes.
+Its others move costs and children between classes, which only makes sense in its
+model. Node and class ids are unchanged, so undoing the simplification needs no
+mapping: an extraction of the simplified egraph is also an extraction of the
+original, with the same costs.
+
+`decompose` goes further for the dag cost, splitting the extraction into regions that
+can be extracted separately.
+*/
+
+use super::*;
+
+#[derive(Default, Debug)]
+pub struct PreprocessStats {
+    pub loops: usize,
+    pub subsumed: usize,
+    pub dead: usize,
+    pub unreachable: usize,
+}
+
+pub fn preprocess(egraph: &EGraph) -> anyhow::Result<(EGraph, PreprocessStats)> {
+    let mut stats = PreprocessStats::default();
+    let n2c = |nid: &NodeId| egraph.nid_to_cid(nid);
+    let roots = &egraph.root_eclasses;
+
+    let mut kept: FxHashSet<NodeId> = Default::default();
+    for class in egraph.classes().values() {
+        // Fewest children, then cheapest, first, so a node can only be subsumed by
+        // one that comes before it.
+        let mut candidates: Vec<(&NodeId, Vec<&ClassId>)> = vec![];
+        for nid in &class.nodes {
+            let mut children: Vec<&ClassId> = egraph[nid].children.iter().map(n2c).collect();
+            if children
+                .iter()
+                .any(|&cid| *cid == class.id || (roots.len() == 1 && *cid == roots[0]))
+            {
+                stats.loops += 1;
+                continue;
+            }
+            children.sort();
+            candidates.push((nid, children));
+        }
+        candidates.sort_by_key(|(nid, children)| (children.len(), egraph[*nid].cost));
+
+        let mut class_kept: Vec<(&NodeId, Vec<&ClassId>)> = vec![];
+        for (nid, children) in candidates {
+            let subsumed = class_kept.iter().any(|(other, other_children)| {
+                egraph[*other].cost <= egraph[nid].cost
+                    && is_sub_multiset(other_children, &children)
+            });
+            if subsumed {
+                stats.subsumed += 1;
+            } else {
+                class_kept.push((nid, children));
+            }
+        }
+        kept.extend(class_kept.into_iter().map(|(nid, _)| nid.clone()));
+    }
+
+    let before = kept.len();
+    let rebuilt = canonicalize::rebuild_with(egraph, kept);
+    stats.dead = before - rebuilt.nodes.len();
+    for root in roots {
+        anyhow::ensure!(
+            rebuilt.classes().contains_key(root),
+            "Root class {root} has no loop-free term"
+        );
+    }
+
+    let mut reachable: FxHashSet<&ClassId> = Default::default();
+    let mut todo: Vec<&ClassId> = roots.iter().collect();
+    while let Some(cid) = todo.pop() {
+        if reachable.insert(cid) {
+            for nid in &rebuilt[cid].nodes {
+                todo.extend(rebuilt[nid].children.iter().map(|c| rebuilt.nid_to_cid(c)));
+            }
+        }
+    }
+    stats.unreachable = rebuilt.classes().len() - reachable.len();
+    let kept = rebuilt
+        .nodes
+        .keys()
+        .filter(|nid| reachable.contains(rebuilt.nid_to_cid(nid)))
+        .cloned()
+        .collect();
+    let simplified = canonicalize::rebuild_with(&rebuilt, kept);
+
+    log::info!("Preprocessing removed {stats:?}");
+    Ok((simplified, stats))
+}
+
+// Whether every class in `a` is in `b` at least as often. Both are sorted.
+fn is_sub_multiset(a: &[&ClassId], b: &[&ClassId]) -> bool {
+    let mut b = b.iter();
+    a.iter().all(|x| b.any(|y| y == x))
+}
@@ -0,0 +1,108 @@
+/*
+Turns an invalid extraction (missing choices, cycles, nodes that aren't in the egraph)
+into a valid one, changing as few choices as possible, so that experimental extractors
+that are almost right can still be scored.
+
+The choices whose selected term is complete and acyclic are kept. Each other class
+needed by the roots is re-chosen: to the cheapest node whose children are all already
+fine if there is one, otherwise to the bottom-up extractor's choice for the class,
+whose children are then repaired in turn.
+*/
+
+use super::*;
+
+pub struct Repaired {
+    pub result: ExtractionResult,
+    // How many classes needed by the roots had their choice changed or added.
+    pub repaired_classes: usize,
+}
+
+pub fn repair(
+    result: &ExtractionResult,
+    egraph: &EGraph,
+    roots: &[ClassId],
+) -> anyhow::Result<Repaired> {
+    let mut good_costs = result.choice_tree_costs(egraph);
+    let mut repaired = result.valid_part(egraph);
+    let fallback = super::faster_bottom_up::FasterBottomUpExtractor.extract(egraph, roots);
+
+    let mut repaired_classes = 0;
+    let mut in_progress: FxHashSet<ClassId> = Default::default();
+    for root in roots {
+        repair_class(
+            egraph,
+            root,
+            &fallback,
+            &mut repaired,
+            &mut good_costs,
+            &mut in_progress,
+            &mut repaired_classes,
+        )?;
+    }
+
+    Ok(Repaired {
+        result: repaired,
+        repaired_classes,
+    })
+}
+
+fn repair_class(
+    egraph: &EGraph,
+    class_id: &ClassId,
+    fallback: &ExtractionResult,
+    repaired: &mut ExtractionResult,
+    good_costs: &mut IndexMap<ClassId, Cost>,
+    in_progress: &mut FxHashSet<ClassId>,
+    repaired_classes: &mut usize,
+) -> anyhow::Result<()> {
+    if good_costs.contains_key(class_id) || in_progress.contains(class_id) {
+        return Ok(());
+    }
+    *repaired_classes += 1;
+
+    // Nodes whose children are all fine can't make a cycle.
+    let cheapest = egraph[class_id]
+        .nodes
+        .iter()
+        .map(|nid| {
+            (
+                nid,
+                repaired.node_sum_cost(egraph, &egraph[nid], &*good_costs),
+            )
+        })
+        .filter(|(_, cost)| *cost < INFINITY)
+        .min_by_key(|(_, cost)| *cost);
+
+    if let Some((node_id, cost)) = cheapest {
+        repaired.choose(class_id.clone(), node_id.clone());
+        good_costs.insert(class_id.clone(), cost);
+        return Ok(());
+    }
+
+    // The bottom-up choices are acyclic, and repaired classes only lead back
+    // to classes that are fine, so following them can't make a cycle either.
+    let node_id = fallback
+        .choices
+        .get(class_id)
+        .ok_or_else(|| anyhow::anyhow!("Class {class_id:?} can't be extracted"))?;
+    repaired.choose(class_id.clone(), node_id.clone());
+
+    in_progress.insert(class_id.clone());
+    for child in &egraph[node_id].children {
+        let child_cid = egraph.nid_to_cid(child);
+        repair_class(
+            egraph,
+            child_cid,
+            fallback,
+            repaired,
+            good_costs,
+            in_progress,
+            repaired_classes,
+        )?;
+    }
+    in_progress.remove(class_id);
+
+    let cost = repaired.node_sum_cost(egraph, &egraph[node_id], &*good_costs);
+    good_costs.insert(class_id.clone(), cost);
+    Ok(())
+}
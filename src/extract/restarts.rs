@@ -0,0 +1,62 @@
+/*
+Runs a stochastic extractor several times with different seeds, on up to `threads`
+threads, and keeps the cheapest valid result. The dag cost of every run is kept
+too, to see how much the result varies between seeds.
+
+The seeds are 0 to restarts - 1, so runs can be reproduced. Extractors that don't
+make random choices give the same result every time, see `Extractor::extract_with_seed`.
+*/
+
+use super::*;
+
+pub struct RestartRun {
+    pub seed: u64,
+    // None if the run's result wasn't valid.
+    pub dag: Option<Cost>,
+    pub micros: u128,
+}
+
+pub struct Restarts {
+    // The cheapest valid result, or the first if none were valid.
+    pub result: ExtractionResult,
+    pub runs: Vec<RestartRun>,
+}
+
+pub fn extract_with_restarts(
+    extractor: &dyn Extractor,
+    egraph: &EGraph,
+    roots: &[ClassId],
+    restarts: usize,
+    threads: usize,
+) -> Restarts {
+    assert!(restarts > 0);
+    let (mut results, runs): (Vec<ExtractionResult>, Vec<RestartRun>) =
+        parallel::run_jobs(restarts, threads, |i| {
+            let seed = i as u64;
+            let start_time = std::time::Instant::now();
+            let result = extractor.extract_with_seed(egraph, roots, seed);
+            let micros = start_time.elapsed().as_micros();
+
+            // Valid if every root's term is complete and acyclic, see `choice_tree_costs`.
+            let tree_costs = result.choice_tree_costs(egraph);
+            let dag = roots
+                .iter()
+                .all(|root| tree_costs.contains_key(root))
+                .then(|| result.dag_cost(egraph, roots));
+            (result, RestartRun { seed, dag, micros })
+        })
+        .into_iter()
+        .unzip();
+
+    // The first of the cheapest, so ties go to the lowest seed.
+    let best = runs
+        .iter()
+        .enumerate()
+        .filter_map(|(i, run)| Some((run.dag?, i)))
+        .min()
+        .map_or(0, |(_, i)| i);
+    Restarts {
+        result: results.swap_remove(best),
+        runs,
+    }
+}
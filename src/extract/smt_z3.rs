@@ -0,0 +1,115 @@
+/* A dag-cost optimal extractor that uses Z3's optimizer, as an implementation of
+the same problem as the ILP extractors that shares no code with them, to cross-check
+them, see `verify`.
+
+Each class has a boolean for whether it's active, and each node one for whether it's
+chosen. A class is active exactly when one of its nodes is chosen, a chosen node's
+children classes are active, and the roots are active. Cycles are ruled out by giving
+each class an integer level, which must be greater than the levels of the children
+classes of its chosen node. The objective is the sum of the costs of the chosen nodes,
+as rationals, so unlike the ILP's floating point the sums aren't rounded.
+
+If Z3 times out, or can't decide, the result is faster-greedy-dag's.
+*/
+
+use super::*;
+use z3::ast::{self, Ast};
+use z3::{Config, Context, Optimize, Params, SatResult};
+
+pub struct Z3Extractor {
+    // None for no timeout.
+    pub timeout_ms: Option<u32>,
+}
+
+impl Extractor for Z3Extractor {
+    fn extract(&self, egraph: &EGraph, roots: &[ClassId]) -> ExtractionResult {
+        let ctx = Context::new(&Config::new());
+        let opt = Optimize::new(&ctx);
+        if let Some(timeout_ms) = self.timeout_ms {
+            let mut params = Params::new(&ctx);
+            params.set_u32("timeout", timeout_ms);
+            opt.set_params(&params);
+        }
+
+        let classes = egraph.classes();
+        let active: IndexMap<&ClassId, ast::Bool> = classes
+            .keys()
+            .enumerate()
+            .map(|(i, cid)| (cid, ast::Bool::new_const(&ctx, format!("active_{i}"))))
+            .collect();
+        let levels: IndexMap<&ClassId, ast::Int> = classes
+            .keys()
+            .enumerate()
+            .map(|(i, cid)| (cid, ast::Int::new_const(&ctx, format!("level_{i}"))))
+            .collect();
+        let chosen: IndexMap<&NodeId, ast::Bool> = egraph
+            .nodes
+            .keys()
+            .enumerate()
+            .map(|(i, nid)| (nid, ast::Bool::new_const(&ctx, format!("chosen_{i}"))))
+            .collect();
+
+        let zero = exact_cost(&ctx, Cost::default());
+        let mut costs = vec![];
+        for (cid, class) in classes {
+            // active == some node chosen
+            let nodes: Vec<&ast::Bool> = class.nodes.iter().map(|nid| &chosen[nid]).collect();
+            opt.assert(&active[cid]._eq(&ast::Bool::or(&ctx, &nodes)));
+
+            for nid in &class.nodes {
+                let node = &egraph[nid];
+                for child in &node.children {
+                    let child_cid = egraph.nid_to_cid(child);
+                    // chosen implies the child is active, and below this class
+                    opt.assert(&chosen[nid].implies(&active[child_cid]));
+                    opt.assert(&chosen[nid].implies(&levels[cid].gt(&levels[child_cid])));
+                }
+                costs.push(chosen[nid].ite(&exact_cost(&ctx, node.cost), &zero));
+            }
+        }
+        for root in roots {
+            opt.assert(&active[root]);
+        }
+        if !costs.is_empty() {
+            let costs: Vec<&ast::Real> = costs.iter().collect();
+            opt.minimize(&ast::Real::add(&ctx, &costs));
+        }
+
+        let model = match opt.check(&[]) {
+            SatResult::Sat => opt.get_model().unwrap(),
+            SatResult::Unsat => {
+                log::info!("Infeasible, returning empty solution");
+                return ExtractionResult::default();
+            }
+            SatResult::Unknown => {
+                log::info!("Z3 gave up, returning the faster-greedy-dag result");
+                return faster_greedy_dag::FasterGreedyDagExtractor.extract(egraph, roots);
+            }
+        };
+
+        let mut result = ExtractionResult::default();
+        for (cid, class) in classes {
+            let is_chosen = |nid: &&NodeId| {
+                model
+                    .eval(&chosen[*nid], true)
+                    .and_then(|b| b.as_bool())
+                    .unwrap_or(false)
+            };
+            if let Some(nid) = class.nodes.iter().find(is_chosen) {
+                result.choose(cid.clone(), nid.clone());
+            }
+        }
+        result
+    }
+}
+
+// The cost as a rational, from the shortest decimal that reads back as the same float.
+// That never has an exponent, so it's split at the point into a numerator and a power
+// of ten.
+fn exact_cost<'ctx>(ctx: &'ctx Context, cost: Cost) -> ast::Real<'ctx> {
+    let decimal = cost.into_inner().to_string();
+    let (whole, fraction) = decimal.split_once('.').unwrap_or((&decimal, ""));
+    let numerator = format!("{whole}{fraction}");
+    let denominator = format!("1{}", "0".repeat(fraction.len()));
+    ast::Real::from_real_str(ctx, &numerator, &denominator).unwrap()
+}
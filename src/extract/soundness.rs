@@ -0,0 +1,63 @@
+/*
+Invariants that extractors can check on their own results. They're too slow to
+check in full on every run, so debug builds check a random sample of the classes,
+the same one on every run so failures reproduce, and release builds don't check at
+all. `--paranoid` checks every class, in any build.
+
+The setting is process wide, like the soft memory limit.
+*/
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use super::*;
+
+static PARANOID: AtomicBool = AtomicBool::new(false);
+
+// The fraction of classes that debug builds check.
+pub const SAMPLE_FRACTION: f64 = 0.05;
+
+// The seed the sample is drawn with.
+const SAMPLE_SEED: u64 = 0;
+
+pub fn set_paranoid(paranoid: bool) {
+    PARANOID.store(paranoid, Ordering::Relaxed);
+}
+
+// The fraction of classes to check.
+pub fn fraction() -> f64 {
+    if PARANOID.load(Ordering::Relaxed) {
+        1.0
+    } else if cfg!(debug_assertions) {
+        SAMPLE_FRACTION
+    } else {
+        0.0
+    }
+}
+
+// For extractors that are optimal for the tree cost: no node is cheaper than the
+// one chosen for its class, given the costs of the terms chosen for its children.
+pub fn check_tree_optimal(egraph: &EGraph, result: &ExtractionResult) {
+    check_tree_optimal_sampled(egraph, result, fraction());
+}
+
+pub fn check_tree_optimal_sampled(egraph: &EGraph, result: &ExtractionResult, fraction: f64) {
+    if fraction <= 0.0 {
+        return;
+    }
+    let costs = result.choice_tree_costs(egraph);
+    let mut rng = StdRng::seed_from_u64(SAMPLE_SEED);
+    for (class_id, cost) in &costs {
+        if fraction < 1.0 && !rng.gen_bool(fraction) {
+            continue;
+        }
+        for node_id in &egraph[class_id].nodes {
+            let node_cost = result.node_sum_cost(egraph, &egraph[node_id], &costs);
+            assert!(
+                node_cost.into_inner() + EPSILON_ALLOWANCE >= cost.into_inner(),
+                "Node {node_id} costs {node_cost}, less than {cost} for the node chosen for class {class_id}"
+            );
+        }
+    }
+}
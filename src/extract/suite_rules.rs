@@ -0,0 +1,94 @@
+/*
+Validity rules that only some benchmark suites have, beyond the usual ones that
+`ExtractionResult::check` enforces. For example, a suite might require that when a
+node with one op is chosen, a node with some other op is chosen too.
+
+A rule can veto an extraction after the fact with `check`, and it can describe
+itself as `Requirement`s, which the ILP extractor adds as constraints so that its
+extraction satisfies the rule rather than being vetoed.
+
+Rules are compiled in by implementing `SuiteRule`. `CoSelectRule` is read from a
+JSON file given with `--rules`, so op-based rules don't need a rebuild:
+
+    {"co_select": [{"op": "Split0", "requires_one_of": ["Split1"]}]}
+*/
+
+use super::*;
+use serde::Deserialize;
+use std::sync::Arc;
+
+// If `node` is chosen, at least one of `one_of` must be chosen too. Requirements from
+// the same rule share `one_of`, which can be most of the egraph.
+#[derive(Debug, Clone)]
+pub struct Requirement {
+    pub node: NodeId,
+    pub one_of: Arc<[NodeId]>,
+}
+
+pub trait SuiteRule: Sync {
+    fn requirements(&self, egraph: &EGraph) -> Vec<Requirement>;
+
+    // Errors if the extraction breaks the rule. Only the choices reachable from the
+    // roots count, as the others aren't part of the extracted term.
+    fn check(&self, egraph: &EGraph, result: &ExtractionResult) -> anyhow::Result<()> {
+        let mut reachable = result.clone();
+        reachable.retain_reachable(egraph, &egraph.root_eclasses);
+        let chosen: FxHashSet<&NodeId> = reachable.choices.values().collect();
+        for requirement in self.requirements(egraph) {
+            if chosen.contains(&requirement.node)
+                && !requirement.one_of.iter().any(|nid| chosen.contains(nid))
+            {
+                anyhow::bail!(
+                    "Node {} ({}) was chosen without any of the nodes it requires",
+                    requirement.node,
+                    egraph[&requirement.node].op
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct CoSelectRule {
+    pub co_select: Vec<CoSelect>,
+}
+
+// Choosing a node with `op` requires choosing a node with one of `requires_one_of`.
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct CoSelect {
+    pub op: String,
+    pub requires_one_of: Vec<String>,
+}
+
+impl CoSelectRule {
+    pub fn from_json_file(path: &std::path::Path) -> anyhow::Result<Self> {
+        let file = std::fs::File::open(path)?;
+        Ok(serde_json::from_reader(std::io::BufReader::new(file))?)
+    }
+}
+
+impl SuiteRule for CoSelectRule {
+    fn requirements(&self, egraph: &EGraph) -> Vec<Requirement> {
+        let mut requirements = vec![];
+        for co_select in &self.co_select {
+            let one_of: Arc<[NodeId]> = egraph
+                .nodes
+                .iter()
+                .filter(|(_, node)| co_select.requires_one_of.contains(&node.op))
+                .map(|(nid, _)| nid.clone())
+                .collect();
+            for (nid, node) in &egraph.nodes {
+                if node.op == co_select.op {
+                    requirements.push(Requirement {
+                        node: nid.clone(),
+                        one_of: one_of.clone(),
+                    });
+                }
+            }
+        }
+        requirements
+    }
+}
@@ -0,0 +1,35 @@
+/*
+A deadline for any extractor, not just the ILP ones that have a timeout of their own.
+
+The extraction runs on a worker thread while the caller waits for it. If the deadline
+passes first, the caller stops waiting and can fall back to something quicker. The
+worker can't be stopped, so it runs on in the background until it finishes or the
+process exits. That's why it takes ownership of everything it needs: the extractor
+is made on the worker thread, and the egraph is shared with it.
+*/
+
+use std::sync::mpsc::{channel, RecvTimeoutError};
+use std::sync::Arc;
+use std::time::Duration;
+
+use super::*;
+
+// None if the deadline passed before the extraction finished.
+pub fn extract_with_timeout(
+    make_extractor: impl FnOnce() -> Box<dyn Extractor> + Send + 'static,
+    egraph: Arc<EGraph>,
+    roots: Vec<ClassId>,
+    timeout: Duration,
+) -> Option<ExtractionResult> {
+    let (sender, receiver) = channel();
+    std::thread::spawn(move || {
+        let result = make_extractor().extract(&egraph, &roots);
+        // Nobody is listening any more if the deadline has passed.
+        let _ = sender.send(result);
+    });
+    match receiver.recv_timeout(timeout) {
+        Ok(result) => Some(result),
+        Err(RecvTimeoutError::Timeout) => None,
+        Err(RecvTimeoutError::Disconnected) => panic!("The extraction panicked"),
+    }
+}
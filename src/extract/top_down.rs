@@ -0,0 +1,181 @@
+use super::*;
+
+/// A greedy extractor that works from the roots down, unlike the others which
+/// build up from the leaves.
+///
+/// Each class is given a lower bound on the dag cost of any term for it: the
+/// cheapest over its nodes of the node's cost plus the largest bound of its
+/// children. Starting at the roots, each class picks the node with the lowest
+/// optimistic estimate, the node's cost plus the bounds of its children's classes
+/// that haven't been picked yet, then picks its children in the same way. Classes
+/// already picked cost nothing more, which favours reusing them.
+///
+/// Nodes with a child class that's currently being picked higher up would make a
+/// cycle, so they're skipped. If that leaves a class with no nodes, the choices
+/// made below its parent are undone and the parent tries its next node.
+///
+/// That backtracking is exponential in the worst case, so after `max_attempts` nodes
+/// have been tried it gives up and returns the faster-greedy-dag extraction. The
+/// search keeps its own stack, so deep egraphs don't overflow the thread's.
+pub struct TopDownGreedyExtractor {
+    pub max_attempts: usize,
+}
+
+impl Extractor for TopDownGreedyExtractor {
+    fn extract(&self, egraph: &EGraph, roots: &[ClassId]) -> ExtractionResult {
+        let bounds = dag_lower_bounds(egraph);
+
+        let mut result = ExtractionResult::default();
+        let mut attempts_left = self.max_attempts;
+        for root in roots {
+            // Roots that can't be extracted are left for `check` to report.
+            if pick(egraph, root, &bounds, &mut result, &mut attempts_left).is_none() {
+                log::info!("Top-down gave up after {} attempts", self.max_attempts);
+                return super::faster_greedy_dag::FasterGreedyDagExtractor.extract(egraph, roots);
+            }
+        }
+        result
+    }
+}
+
+// Fixed point from above, like the bottom-up extractor, with max in place of sum.
+fn dag_lower_bounds(egraph: &EGraph) -> FxHashMap<ClassId, Cost> {
+    let mut bounds = FxHashMap::<ClassId, Cost>::default();
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for class in egraph.classes().values() {
+            for nid in &class.nodes {
+                let node = &egraph[nid];
+                let children = node
+                    .children
+                    .iter()
+                    .map(|c| *bounds.get(egraph.nid_to_cid(c)).unwrap_or(&INFINITY))
+                    .max()
+                    .unwrap_or_default();
+                let bound = node.cost + children;
+                if &bound < bounds.get(&class.id).unwrap_or(&INFINITY) {
+                    bounds.insert(class.id.clone(), bound);
+                    changed = true;
+                }
+            }
+        }
+    }
+    bounds
+}
+
+// A class being picked: the nodes it has left to try, best first, and the node it's
+// trying, with the next of that node's children to pick and the number of choices
+// made before it, to undo them if a child can't be picked.
+struct Frame<'a> {
+    class_id: &'a ClassId,
+    candidates: std::vec::IntoIter<&'a NodeId>,
+    trying: Option<(&'a NodeId, usize, usize)>,
+}
+
+fn frame<'a>(
+    egraph: &'a EGraph,
+    class_id: &'a ClassId,
+    bounds: &FxHashMap<ClassId, Cost>,
+    result: &ExtractionResult,
+    path: &FxHashSet<&ClassId>,
+) -> Frame<'a> {
+    let mut candidates: Vec<(Cost, &NodeId)> = egraph[class_id]
+        .nodes
+        .iter()
+        .filter_map(|nid| {
+            let node = &egraph[nid];
+            let mut child_classes: Vec<&ClassId> =
+                node.children.iter().map(|c| egraph.nid_to_cid(c)).collect();
+            child_classes.sort();
+            child_classes.dedup();
+            if child_classes
+                .iter()
+                .any(|c| *c == class_id || path.contains(*c))
+            {
+                return None;
+            }
+            let estimate = node.cost
+                + child_classes
+                    .iter()
+                    .filter(|c| !result.choices.contains_key(**c))
+                    .map(|c| *bounds.get(*c).unwrap_or(&INFINITY))
+                    .sum::<Cost>();
+            (estimate < INFINITY).then_some((estimate, nid))
+        })
+        .collect();
+    candidates.sort();
+    Frame {
+        class_id,
+        candidates: candidates
+            .into_iter()
+            .map(|(_, nid)| nid)
+            .collect::<Vec<_>>()
+            .into_iter(),
+        trying: None,
+    }
+}
+
+// Returns whether a term was found for `root`, or None if it ran out of attempts.
+// If no term was found, `result` is unchanged.
+fn pick<'a>(
+    egraph: &'a EGraph,
+    root: &'a ClassId,
+    bounds: &FxHashMap<ClassId, Cost>,
+    result: &mut ExtractionResult,
+    attempts_left: &mut usize,
+) -> Option<bool> {
+    if result.choices.contains_key(root) {
+        return Some(true);
+    }
+
+    // The classes on the stack, whose nodes' children can't include them.
+    let mut path = FxHashSet::<&ClassId>::default();
+    path.insert(root);
+    let mut stack = vec![frame(egraph, root, bounds, result, &path)];
+    // Whether the child last picked has a term, for the frame on top to act on.
+    let mut found = None;
+    while let Some(top) = stack.last_mut() {
+        match found.take() {
+            Some(true) => top.trying.as_mut().unwrap().1 += 1,
+            Some(false) => {
+                // Undo this class's choice and everything picked below it.
+                let (_, _, before) = top.trying.take().unwrap();
+                result.choices.truncate(before);
+            }
+            None => {}
+        }
+
+        if top.trying.is_none() {
+            let Some(nid) = top.candidates.next() else {
+                path.remove(top.class_id);
+                stack.pop();
+                found = Some(false);
+                continue;
+            };
+            *attempts_left = attempts_left.checked_sub(1)?;
+            let before = result.choices.len();
+            result.choose(top.class_id.clone(), nid.clone());
+            top.trying = Some((nid, 0, before));
+        }
+
+        let (nid, child, _) = top.trying.unwrap();
+        match egraph[nid].children.get(child) {
+            None => {
+                path.remove(top.class_id);
+                stack.pop();
+                found = Some(true);
+            }
+            Some(child) => {
+                let child_class = egraph.nid_to_cid(child);
+                if result.choices.contains_key(child_class) {
+                    found = Some(true);
+                } else {
+                    path.insert(child_class);
+                    stack.push(frame(egraph, child_class, bounds, result, &path));
+                }
+            }
+        }
+    }
+    Some(found == Some(true))
+}
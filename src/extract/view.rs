@@ -0,0 +1,74 @@
+/*
+A view of an egraph with some nodes left out, for experiments like "what would we
+extract without these 100 nodes" or "extract using only this region of the egraph",
+without copying the egraph for each one.
+
+A node is in the view unless it's been excluded, or its class is outside the allowed
+classes. Nodes whose children can no longer be extracted are left for the extractor
+to avoid, as it would any node with no finite cost.
+
+Extractors take a view with `Extractor::extract_view`. By default that copies the
+nodes in the view into a new egraph, see `materialize`, so it works with every
+extractor. Extractors that can skip the left out nodes as they go, like the faster
+bottom-up extractor, override it to avoid the copy. Either way the result's ids are
+those of the base egraph.
+*/
+
+use super::*;
+
+pub struct EGraphView<'a> {
+    egraph: &'a EGraph,
+    excluded: FxHashSet<NodeId>,
+    // If set, only nodes in these classes are in the view.
+    classes: Option<FxHashSet<ClassId>>,
+}
+
+impl<'a> EGraphView<'a> {
+    // A view of the whole egraph.
+    pub fn new(egraph: &'a EGraph) -> Self {
+        Self {
+            egraph,
+            excluded: Default::default(),
+            classes: None,
+        }
+    }
+
+    pub fn without_nodes(mut self, nodes: impl IntoIterator<Item = NodeId>) -> Self {
+        self.excluded.extend(nodes);
+        self
+    }
+
+    pub fn within_classes(mut self, classes: impl IntoIterator<Item = ClassId>) -> Self {
+        let classes = classes.into_iter().collect();
+        self.classes = Some(match self.classes {
+            Some(previous) => previous.intersection(&classes).cloned().collect(),
+            None => classes,
+        });
+        self
+    }
+
+    pub fn egraph(&self) -> &'a EGraph {
+        self.egraph
+    }
+
+    pub fn contains(&self, nid: &NodeId) -> bool {
+        !self.excluded.contains(nid)
+            && self
+                .classes
+                .as_ref()
+                .is_none_or(|classes| classes.contains(self.egraph.nid_to_cid(nid)))
+    }
+
+    // A copy of the egraph with just the nodes in the view, less those with a child
+    // class that has no nodes left, see `canonicalize::rebuild_with`.
+    pub fn materialize(&self) -> EGraph {
+        let kept = self
+            .egraph
+            .nodes
+            .keys()
+            .filter(|nid| self.contains(nid))
+            .cloned()
+            .collect();
+        canonicalize::rebuild_with(self.egraph, kept)
+    }
+}
@@ -0,0 +1,118 @@
+/*
+Finds the single-class changes that would make an extraction cheaper, to explain
+where a heuristic extractor, like faster-greedy-dag, loses to the ILP on an egraph.
+
+A change picks another node for a chosen class. Only nodes whose children classes
+are all already chosen are considered, so the change needs no new choices: it only
+pays off because it reuses those classes, which the rest of the extraction pays for
+anyway. That's the sharing a greedy extractor can't see when it costs each class
+on its own. A change is an improvement if, with it, the extraction still has no
+cycles and its dag cost is lower. The saving is exact: it counts the classes only
+the old node needed, which the change leaves unused.
+
+Improvements aren't independent, so their savings don't add up, but the biggest
+are where the gap is.
+
+Run with `extraction-gym find-improvements [--extractor <name>] [--top <n>] <egraph.json>`.
+*/
+
+use crate::*;
+use anyhow::Context;
+use indexmap::IndexSet;
+
+pub struct Improvement {
+    pub class: ClassId,
+    pub from: NodeId,
+    pub to: NodeId,
+    pub saving: Cost,
+    // The chosen classes the new node reuses.
+    pub reused: Vec<ClassId>,
+}
+
+// The improvements to `result`, biggest saving first.
+pub fn find_improvements(
+    egraph: &EGraph,
+    result: &ExtractionResult,
+    roots: &[ClassId],
+) -> Vec<Improvement> {
+    let mut base = result.clone();
+    base.retain_reachable(egraph, roots);
+    let dag = base.dag_cost(egraph, roots);
+
+    let mut improvements = vec![];
+    for (cid, nid) in &base.choices {
+        for other in &egraph[cid].nodes {
+            if other == nid || egraph[other].cost > dag {
+                continue;
+            }
+            let reused: IndexSet<&ClassId> = egraph[other]
+                .children
+                .iter()
+                .map(|c| egraph.nid_to_cid(c))
+                .collect();
+            if !reused.iter().all(|c| base.choices.contains_key(*c)) {
+                continue;
+            }
+
+            let mut changed = base.clone();
+            changed.choose(cid.clone(), other.clone());
+            if !changed.find_cycles(egraph, roots).is_empty() {
+                continue;
+            }
+            let new_dag = changed.dag_cost(egraph, roots);
+            if new_dag.into_inner() + EPSILON_ALLOWANCE < dag.into_inner() {
+                improvements.push(Improvement {
+                    class: cid.clone(),
+                    from: nid.clone(),
+                    to: other.clone(),
+                    saving: dag - new_dag,
+                    reused: reused.into_iter().cloned().collect(),
+                });
+            }
+        }
+    }
+    improvements.sort_by(|a, b| b.saving.cmp(&a.saving));
+    improvements
+}
+
+pub fn find_improvements_command(mut args: pico_args::Arguments) {
+    let extractor_name: String = args
+        .opt_value_from_str("--extractor")
+        .unwrap()
+        .unwrap_or_else(|| "faster-greedy-dag".into());
+    let top: usize = args.opt_value_from_str("--top").unwrap().unwrap_or(20);
+    let lenient = args.contains("--lenient");
+    let filename: String = args.free_from_str().unwrap();
+
+    let rest = args.finish();
+    if !rest.is_empty() {
+        panic!("Unknown arguments: {:?}", rest);
+    }
+
+    let egraph = schema::read_egraph(&filename, lenient)
+        .with_context(|| format!("Failed to parse {filename}"))
+        .unwrap();
+    let extractors = extractors();
+    let ed = extractors
+        .get(extractor_name.as_str())
+        .with_context(|| format!("Unknown extractor: {extractor_name}"))
+        .unwrap();
+    let roots = &egraph.root_eclasses;
+    let result = ed.extractor.extract(&egraph, roots);
+    result.check(&egraph);
+
+    let improvements = find_improvements(&egraph, &result, roots);
+    println!("{} improvements found", improvements.len());
+    println!("class\tfrom\tto\tsaving\treused");
+    for improvement in improvements.iter().take(top) {
+        let reused: Vec<String> = improvement.reused.iter().map(|c| c.to_string()).collect();
+        println!(
+            "{}\t{}\t{}\t{}\t{}",
+            improvement.class,
+            improvement.from,
+            improvement.to,
+            improvement.saving,
+            reused.join(",")
+        );
+    }
+}
@@ -0,0 +1,312 @@
+/*!
+The extractors of extraction-gym, for use from other crates.
+
+Each extractor implements [`Extractor`], and [`extractors`] lists them all by the
+name the command line uses. [`extract_with`] runs one by name, and the
+[`ExtractionResult`] it returns has the chosen node for each class, and methods to
+check and cost the extraction.
+
+```
+use extraction_gym::prelude::*;
+
+let egraph = extraction_gym::schema::parse_egraph(
+    r#"{
+        "nodes": {
+            "x": { "op": "x", "children": [], "eclass": "X", "cost": 1 },
+            "y": { "op": "y", "children": [], "eclass": "X", "cost": 2 },
+            "f": { "op": "f", "children": ["x", "x"], "eclass": "F", "cost": 1 }
+        },
+        "root_eclasses": ["F"]
+    }"#,
+    false,
+)
+.unwrap();
+let roots = &egraph.root_eclasses;
+
+let result = extract_with("faster-greedy-dag", &egraph, roots).unwrap();
+result.check(&egraph);
+assert_eq!(result.tree_cost(&egraph, roots), 3.0);
+assert_eq!(result.dag_cost(&egraph, roots), 2.0);
+```
+
+The [`prelude`] is the stable API, which only changes in a semver-incompatible
+release. Everything else, including the extractor modules, may change between
+minor releases, and the experimental extractors are hidden from the documentation.
+*/
+
+pub mod aggregate;
+pub mod batch;
+pub mod convert;
+pub mod corpus;
+pub mod cost_diff;
+pub mod extract;
+pub mod improve;
+pub mod lint;
+pub mod merge;
+pub mod monotonic;
+#[cfg(feature = "ilp-cbc")]
+pub mod pareto;
+pub mod random;
+pub mod schema;
+pub mod sweep;
+pub mod verify;
+
+pub use extract::*;
+
+pub mod prelude {
+    pub use crate::{
+        extract_with, extractors, ClassId, Cost, CostCache, EGraph, ExtractionResult, Extractor,
+        ExtractorDetail, Node, NodeId, Optimal, INFINITY,
+    };
+}
+
+use egraph_serialize::*;
+pub use egraph_serialize::{ClassId, EGraph, Node, NodeId};
+
+use indexmap::IndexMap;
+use ordered_float::NotNan;
+
+use anyhow::Context;
+
+pub type Cost = NotNan<f64>;
+pub const INFINITY: Cost = unsafe { NotNan::new_unchecked(std::f64::INFINITY) };
+
+// Both are non-exhaustive, so extractors can be described in more ways without
+// breaking code outside the crate that reads them.
+#[derive(PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Optimal {
+    Tree,
+    DAG,
+    Neither,
+}
+
+#[non_exhaustive]
+pub struct ExtractorDetail {
+    pub extractor: Box<dyn Extractor>,
+    pub description: &'static str,
+    // The cargo feature the extractor needs, if any.
+    pub feature: Option<&'static str>,
+    pub optimal: Optimal,
+    pub use_for_bench: bool,
+    // Whether the extractor terminates with a valid result on egraphs with cycles.
+    pub supports_cycles: bool,
+    // Whether the extractor always gives the same result for the same input.
+    pub deterministic: bool,
+}
+
+// Every extractor, by the name `--extractor` takes.
+pub fn extractors() -> IndexMap<&'static str, ExtractorDetail> {
+    let extractors: IndexMap<&'static str, ExtractorDetail> = [
+        (
+            "a-star",
+            ExtractorDetail {
+                extractor: extract::a_star::AStarExtractor {
+                    max_expansions: 100_000,
+                }
+                .boxed(),
+                description: "A* search for the cheapest dag, falling back to faster-greedy-dag after 100,000 states",
+                feature: None,
+                optimal: Optimal::Neither, // optimal unless it gives up
+                use_for_bench: true,
+                supports_cycles: true,
+                deterministic: true,
+            },
+        ),
+        (
+            "bottom-up",
+            ExtractorDetail {
+                extractor: extract::bottom_up::BottomUpExtractor.boxed(),
+                description: "Chooses the node with the cheapest tree in each class, until nothing changes",
+                feature: None,
+                optimal: Optimal::Tree,
+                use_for_bench: true,
+                supports_cycles: true,
+                deterministic: true,
+            },
+        ),
+        (
+            "discounted-bottom-up",
+            ExtractorDetail {
+                extractor: extract::bottom_up::DiscountedBottomUpExtractor::new(0.5).unwrap().boxed(),
+                description: "Bottom-up for a tree cost discounted by 0.5 per level of depth, or by --gamma",
+                feature: None,
+                optimal: Optimal::Neither,
+                use_for_bench: false, // optimises a different objective
+                supports_cycles: true,
+                deterministic: true,
+            },
+        ),
+        (
+            "cse-greedy-dag",
+            ExtractorDetail {
+                extractor: extract::cse_greedy_dag::CseGreedyDagExtractor.boxed(),
+                description: "Like faster-greedy-dag, but counting identical subterms in different classes once",
+                feature: None,
+                optimal: Optimal::Neither,
+                use_for_bench: false, // optimises a different objective
+                supports_cycles: true,
+                deterministic: true,
+            },
+        ),
+        (
+            "faster-bottom-up",
+            ExtractorDetail {
+                extractor: extract::faster_bottom_up::FasterBottomUpExtractor.boxed(),
+                description: "Bottom-up with a worklist, revisiting only the parents of classes that got cheaper",
+                feature: None,
+                optimal: Optimal::Tree,
+                use_for_bench: true,
+                supports_cycles: true,
+                deterministic: true,
+            },
+        ),
+        (
+            "faster-bottom-up-capped",
+            ExtractorDetail {
+                extractor: extract::faster_bottom_up::CappedBottomUpExtractor { revisit_cap: 16 }
+                    .boxed(),
+                description: "Faster bottom-up that revisits each class at most 16 times",
+                feature: None,
+                optimal: Optimal::Tree,
+                use_for_bench: false,
+                supports_cycles: true,
+                deterministic: true,
+            },
+        ),
+        (
+            "faster-greedy-dag",
+            ExtractorDetail {
+                extractor: extract::faster_greedy_dag::FasterGreedyDagExtractor.boxed(),
+                description: "Chooses the node whose set of classes below it is cheapest, counting shared classes once",
+                feature: None,
+                optimal: Optimal::Neither,
+                use_for_bench: true,
+                supports_cycles: true,
+                deterministic: true,
+            },
+        ),
+        (
+            "top-down-greedy",
+            ExtractorDetail {
+                extractor: extract::top_down::TopDownGreedyExtractor {
+                    max_attempts: 100_000,
+                }
+                .boxed(),
+                description: "Chooses nodes from the roots down by a lower bound on their dag cost, favouring classes already chosen, falling back to faster-greedy-dag after trying 100,000 nodes",
+                feature: None,
+                optimal: Optimal::Neither,
+                use_for_bench: true,
+                supports_cycles: true,
+                deterministic: true,
+            },
+        ),
+        (
+            "global-greedy-dag",
+            ExtractorDetail {
+                extractor: extract::global_greedy_dag::GlobalGreedyDagExtractor.boxed(),
+                description: "Greedy dag extraction over hash-consed terms, until a pass improves nothing or --max-iterations, --target-cost or --time-budget is reached",
+                feature: None,
+                optimal: Optimal::Neither,
+                use_for_bench: false, // experimental
+                supports_cycles: true,
+                deterministic: true,
+            },
+        ),
+        #[cfg(feature = "ilp-cbc")]
+        (
+            "ilp-cbc-timeout",
+            ExtractorDetail {
+                extractor: extract::ilp_cbc::CbcExtractorWithTimeout::<10>.boxed(),
+                description: "The dag ILP solved by CBC, giving up after 10 seconds",
+                feature: Some("ilp-cbc"),
+                optimal: Optimal::DAG,
+                use_for_bench: true,
+                supports_cycles: true,
+                deterministic: false, // depends on when it times out
+            },
+        ),
+        #[cfg(feature = "ilp-cbc")]
+        (
+            "ilp-cbc",
+            ExtractorDetail {
+                extractor: extract::ilp_cbc::CbcExtractor.boxed(),
+                description: "The dag ILP solved by CBC",
+                feature: Some("ilp-cbc"),
+                optimal: Optimal::DAG,
+                use_for_bench: false, // takes >10 hours sometimes
+                supports_cycles: true,
+                deterministic: true,
+            },
+        ),
+        #[cfg(feature = "ilp-cbc")]
+        (
+            "faster-ilp-cbc-timeout",
+            ExtractorDetail {
+                extractor: extract::faster_ilp_cbc::FasterCbcExtractorWithTimeout::<10>.boxed(),
+                description: "The simplified dag ILP solved by CBC, giving up after 10 seconds",
+                feature: Some("ilp-cbc"),
+                optimal: Optimal::DAG,
+                use_for_bench: true,
+                supports_cycles: true,
+                deterministic: false, // depends on when it times out
+            },
+        ),
+        #[cfg(feature = "ilp-cbc")]
+        (
+            "faster-ilp-cbc",
+            ExtractorDetail {
+                extractor: extract::faster_ilp_cbc::FasterCbcExtractor.boxed(),
+                description: "The simplified dag ILP solved by CBC",
+                feature: Some("ilp-cbc"),
+                optimal: Optimal::DAG,
+                use_for_bench: true,
+                supports_cycles: true,
+                deterministic: true,
+            },
+        ),
+        #[cfg(feature = "smt")]
+        (
+            "smt-z3",
+            ExtractorDetail {
+                extractor: extract::smt_z3::Z3Extractor { timeout_ms: None }.boxed(),
+                description: "The dag extraction problem with integer levels against cycles, optimised by Z3",
+                feature: Some("smt"),
+                optimal: Optimal::DAG,
+                use_for_bench: false, // for cross-checking the ILP extractors
+                supports_cycles: true,
+                deterministic: true,
+            },
+        ),
+    ]
+    .into_iter()
+    .collect();
+    return extractors;
+}
+
+// The extractor called `name` in `extractors`, whether or not it's used for
+// benchmarks, as `--extractor` takes it.
+pub fn extractor_named(name: &str) -> anyhow::Result<ExtractorDetail> {
+    extractors()
+        .swap_remove(name)
+        .with_context(|| format!("Unknown extractor: {name}"))
+}
+
+/// Runs the extractor called `name` in [`extractors`].
+///
+/// ```
+/// # use extraction_gym::prelude::*;
+/// let egraph = EGraph::default();
+/// assert!(extract_with("no-such-extractor", &egraph, &[]).is_err());
+/// ```
+pub fn extract_with(
+    name: &str,
+    egraph: &EGraph,
+    roots: &[ClassId],
+) -> anyhow::Result<ExtractionResult> {
+    let ed = extractor_named(name)?;
+    Ok(ed.extractor.extract(egraph, roots))
+}
+
+#[cfg(test)]
+pub mod test;
@@ -0,0 +1,183 @@
+/*
+Flags cost patterns in an egraph that tend to give confusing extraction results,
+so they can be spotted before spending hours running the extractors:
+
+- Negative costs, which most extractors assume can't happen.
+- Cycles made only of zero-cost nodes, where "cheapest" doesn't pick a single term.
+- Classes with several nodes that all have the same cost, so every choice is a tie.
+  Nearly every egraph in the corpus has some, so they get one summary line, which
+  doesn't fail the exit code the way the other lints do.
+- Infinite costs, or costs spanning so many orders of magnitude that the ILP
+  solver's tolerances can't tell them apart.
+
+Run with `extraction-gym lint-costs [--lenient] <egraph.json>`.
+*/
+
+use crate::*;
+use anyhow::Context;
+use std::fmt;
+
+// CBC's default tolerances are around 1e-6 to 1e-9, so costs spanning a wider range
+// than this can't be reliably distinguished by the ILP extractors.
+const MAX_COST_RANGE: f64 = 1e7;
+
+// Don't report more cycles than this, there can be very many of them.
+const CYCLE_LIMIT: usize = 100;
+
+pub enum CostLint {
+    NegativeCost {
+        node: NodeId,
+        cost: Cost,
+    },
+    InfiniteCost {
+        node: NodeId,
+    },
+    ZeroCostCycle {
+        classes: Vec<ClassId>,
+    },
+    // How many classes are tied, and the first of them as an example.
+    AllTied {
+        classes: usize,
+        example: ClassId,
+        nodes: usize,
+        cost: Cost,
+    },
+    WideCostRange {
+        min: Cost,
+        max: Cost,
+    },
+}
+
+impl fmt::Display for CostLint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CostLint::NegativeCost { node, cost } => {
+                write!(f, "node {node:?} has negative cost {cost}")
+            }
+            CostLint::InfiniteCost { node } => write!(f, "node {node:?} has infinite cost"),
+            CostLint::ZeroCostCycle { classes } => {
+                write!(f, "zero-cost cycle through classes {classes:?}")
+            }
+            CostLint::AllTied {
+                classes,
+                example,
+                nodes,
+                cost,
+            } => write!(
+                f,
+                "{classes} classes have several nodes that all cost the same, e.g. all {nodes} nodes in class {example:?} cost {cost}"
+            ),
+            CostLint::WideCostRange { min, max } => write!(
+                f,
+                "non-zero costs range from {min} to {max}, which may cause ILP numeric issues"
+            ),
+        }
+    }
+}
+
+impl CostLint {
+    // Ties are worth knowing about, but aren't a problem with the egraph.
+    pub fn is_error(&self) -> bool {
+        !matches!(self, CostLint::AllTied { .. })
+    }
+}
+
+pub fn lint_costs(egraph: &EGraph) -> Vec<CostLint> {
+    let mut lints = vec![];
+
+    let mut min_nonzero: Option<Cost> = None;
+    let mut max: Option<Cost> = None;
+    for (node_id, node) in &egraph.nodes {
+        if node.cost.is_infinite() {
+            lints.push(CostLint::InfiniteCost {
+                node: node_id.clone(),
+            });
+            continue;
+        }
+        if node.cost < Cost::default() {
+            lints.push(CostLint::NegativeCost {
+                node: node_id.clone(),
+                cost: node.cost,
+            });
+        }
+        let magnitude = Cost::new(node.cost.abs()).unwrap();
+        if magnitude != 0.0 {
+            min_nonzero = Some(min_nonzero.map_or(magnitude, |m| m.min(magnitude)));
+            max = Some(max.map_or(magnitude, |m| m.max(magnitude)));
+        }
+    }
+
+    if let (Some(min), Some(max)) = (min_nonzero, max) {
+        if max.into_inner() / min.into_inner() > MAX_COST_RANGE {
+            lints.push(CostLint::WideCostRange { min, max });
+        }
+    }
+
+    let mut tied = egraph.classes().values().filter(|class| {
+        class.nodes.len() >= 2
+            && class
+                .nodes
+                .iter()
+                .all(|n| egraph[n].cost == egraph[&class.nodes[0]].cost)
+    });
+    if let Some(example) = tied.next() {
+        lints.push(CostLint::AllTied {
+            classes: 1 + tied.count(),
+            example: example.id.clone(),
+            nodes: example.nodes.len(),
+            cost: egraph[&example.nodes[0]].cost,
+        });
+    }
+
+    for classes in zero_cost_cycles(egraph) {
+        lints.push(CostLint::ZeroCostCycle { classes });
+    }
+
+    lints
+}
+
+// Cycles in the class graph that only use edges from zero-cost nodes.
+fn zero_cost_cycles(egraph: &EGraph) -> Vec<Vec<ClassId>> {
+    let classes = egraph.classes();
+    extract::cycles::find_cycles(
+        classes.keys(),
+        |cid| {
+            classes[cid]
+                .nodes
+                .iter()
+                .filter(move |nid| egraph[*nid].cost == 0.0)
+                .flat_map(move |nid| {
+                    egraph[nid]
+                        .children
+                        .iter()
+                        .map(move |c| egraph.nid_to_cid(c))
+                })
+        },
+        CYCLE_LIMIT,
+    )
+}
+
+pub fn lint_costs_command(mut args: pico_args::Arguments) {
+    let lenient = args.contains("--lenient");
+    let filename: String = args.free_from_str().unwrap();
+
+    let rest = args.finish();
+    if !rest.is_empty() {
+        panic!("Unknown arguments: {:?}", rest);
+    }
+
+    let egraph = schema::read_egraph(&filename, lenient)
+        .with_context(|| format!("Failed to parse {filename}"))
+        .unwrap();
+
+    let lints = lint_costs(&egraph);
+    for lint in &lints {
+        println!("{filename}: {lint}");
+    }
+
+    if lints.is_empty() {
+        println!("{filename}: no suspicious costs found");
+    } else if lints.iter().any(CostLint::is_error) {
+        std::process::exit(1);
+    }
+}
@@ -1,124 +1,97 @@
-mod extract;
-
-pub use extract::*;
-
-use egraph_serialize::*;
-
-use indexmap::IndexMap;
-use ordered_float::NotNan;
+use extraction_gym::*;
 
 use anyhow::Context;
+use indexmap::IndexMap;
 
 use std::io::Write;
 use std::path::PathBuf;
 
-pub type Cost = NotNan<f64>;
-pub const INFINITY: Cost = unsafe { NotNan::new_unchecked(std::f64::INFINITY) };
-
-#[derive(PartialEq, Eq)]
-enum Optimal {
-    Tree,
-    DAG,
-    Neither,
-}
-
-struct ExtractorDetail {
-    extractor: Box<dyn Extractor>,
-    optimal: Optimal,
-    use_for_bench: bool,
-}
-
-fn extractors() -> IndexMap<&'static str, ExtractorDetail> {
-    let extractors: IndexMap<&'static str, ExtractorDetail> = [
-        (
-            "bottom-up",
-            ExtractorDetail {
-                extractor: extract::bottom_up::BottomUpExtractor.boxed(),
-                optimal: Optimal::Tree,
-                use_for_bench: true,
-            },
-        ),
-        (
-            "faster-bottom-up",
-            ExtractorDetail {
-                extractor: extract::faster_bottom_up::FasterBottomUpExtractor.boxed(),
-                optimal: Optimal::Tree,
-                use_for_bench: true,
-            },
-        ),
-        (
-            "faster-greedy-dag",
-            ExtractorDetail {
-                extractor: extract::faster_greedy_dag::FasterGreedyDagExtractor.boxed(),
-                optimal: Optimal::Neither,
-                use_for_bench: true,
-            },
-        ),
-        /*(
-            "global-greedy-dag",
-            ExtractorDetail {
-                extractor: extract::global_greedy_dag::GlobalGreedyDagExtractor.boxed(),
-                optimal: Optimal::Neither,
-                use_for_bench: true,
-            },
-        ),*/
-        #[cfg(feature = "ilp-cbc")]
-        (
-            "ilp-cbc-timeout",
-            ExtractorDetail {
-                extractor: extract::ilp_cbc::CbcExtractorWithTimeout::<10>.boxed(),
-                optimal: Optimal::DAG,
-                use_for_bench: true,
-            },
-        ),
-        #[cfg(feature = "ilp-cbc")]
-        (
-            "ilp-cbc",
-            ExtractorDetail {
-                extractor: extract::ilp_cbc::CbcExtractor.boxed(),
-                optimal: Optimal::DAG,
-                use_for_bench: false, // takes >10 hours sometimes
-            },
-        ),
-        #[cfg(feature = "ilp-cbc")]
-        (
-            "faster-ilp-cbc-timeout",
-            ExtractorDetail {
-                extractor: extract::faster_ilp_cbc::FasterCbcExtractorWithTimeout::<10>.boxed(),
-                optimal: Optimal::DAG,
-                use_for_bench: true,
-            },
-        ),
-        #[cfg(feature = "ilp-cbc")]
-        (
-            "faster-ilp-cbc",
-            ExtractorDetail {
-                extractor: extract::faster_ilp_cbc::FasterCbcExtractor.boxed(),
-                optimal: Optimal::DAG,
-                use_for_bench: true,
-            },
-        ),
-    ]
-    .into_iter()
-    .collect();
-    return extractors;
-}
-
 fn main() {
     env_logger::init();
 
-    let mut extractors = extractors();
-    extractors.retain(|_, ed| ed.use_for_bench);
-
     let mut args = pico_args::Arguments::from_env();
 
+    // Subcommands are given as the first argument, e.g. `extraction-gym lint-costs a.json`.
+    // Otherwise the arguments describe a single extraction.
+    match std::env::args().nth(1).as_deref() {
+        Some("aggregate") => {
+            args.subcommand().unwrap();
+            aggregate::aggregate_command(args);
+            return;
+        }
+        Some("lint-costs") => {
+            args.subcommand().unwrap();
+            lint::lint_costs_command(args);
+            return;
+        }
+        Some("compare-cost-models") => {
+            args.subcommand().unwrap();
+            cost_diff::compare_cost_models_command(args);
+            return;
+        }
+        Some("find-improvements") => {
+            args.subcommand().unwrap();
+            improve::find_improvements_command(args);
+            return;
+        }
+        Some("check-monotonic") => {
+            args.subcommand().unwrap();
+            monotonic::check_monotonic_command(args);
+            return;
+        }
+        #[cfg(feature = "ilp-cbc")]
+        Some("pareto-front") => {
+            args.subcommand().unwrap();
+            pareto::pareto_front_command(args);
+            return;
+        }
+        #[cfg(not(feature = "ilp-cbc"))]
+        Some("pareto-front") => panic!("pareto-front needs the ilp-cbc feature"),
+        Some("verify-encodings") => {
+            args.subcommand().unwrap();
+            verify::verify_encodings_command(args);
+            return;
+        }
+        Some("convert") => {
+            args.subcommand().unwrap();
+            convert::convert_command(args);
+            return;
+        }
+        Some("merge-egraphs") => {
+            args.subcommand().unwrap();
+            merge::merge_egraphs_command(args);
+            return;
+        }
+        Some("gen-random") => {
+            args.subcommand().unwrap();
+            random::gen_random_command(args);
+            return;
+        }
+        Some("sweep-node-orders") => {
+            args.subcommand().unwrap();
+            sweep::sweep_node_orders_command(args);
+            return;
+        }
+        _ => (),
+    }
+
+    // Every extractor, including those not used for benchmarks, with what it supports.
+    if args.contains("--list-extractors-json") {
+        let listing = schema::ExtractorListing::all();
+        println!("{}", serde_json::to_string_pretty(&listing).unwrap());
+        return;
+    }
+
     let extractor_name: String = args
         .opt_value_from_str("--extractor")
         .unwrap()
         .unwrap_or_else(|| "bottom-up".into());
+    // The extractors to benchmark, see the Makefile. Any extractor can be run by name.
     if extractor_name == "print" {
-        for name in extractors.keys() {
-            println!("{}", name);
+        for (name, ed) in extractors() {
+            if ed.use_for_bench {
+                println!("{}", name);
+            }
         }
         return;
     }
@@ -128,6 +101,208 @@ fn main() {
         .unwrap()
         .unwrap_or_else(|| "out.json".into());
 
+    // Override the serialized node costs, see `CostModel`.
+    let cost_model_filename: Option<PathBuf> = args.opt_value_from_str("--cost-model").unwrap();
+
+    // Also report the extraction's costs under each of these comma-separated cost models.
+    let also_eval_filenames: Vec<PathBuf> = args
+        .opt_value_from_str::<_, String>("--also-eval")
+        .unwrap()
+        .map(|list| list.split(',').map(PathBuf::from).collect())
+        .unwrap_or_default();
+
+    let only_ops: Option<String> = args.opt_value_from_str("--only-ops").unwrap();
+    let exclude_ops: Option<String> = args.opt_value_from_str("--exclude-ops").unwrap();
+
+    let soft_memory_limit_mb: Option<u64> =
+        args.opt_value_from_str("--soft-memory-limit-mb").unwrap();
+    if let Some(mb) = soft_memory_limit_mb {
+        extract::memory_limit::set_soft_limit_bytes(mb * 1024 * 1024);
+    }
+
+    // Check the extractors' invariants on every class, see `soundness`.
+    if args.contains("--paranoid") {
+        extract::soundness::set_paranoid(true);
+    }
+
+    let canonicalize = args.contains("--canonicalize");
+
+    // Remove nodes and classes the optimal extraction doesn't need, see `preprocess`.
+    let preprocess = args.contains("--preprocess");
+
+    // Extract the regions only reachable through one class separately, see `decompose`.
+    let decompose = args.contains("--decompose");
+
+    // Repair invalid extractions so they can still be scored.
+    let repair = args.contains("--repair");
+
+    // Extract each root separately and merge the results.
+    let per_root = args.contains("--per-root");
+
+    // How many threads parallel extraction may use. One by default, so timings
+    // are comparable between machines.
+    let threads: usize = args.opt_value_from_str("--threads").unwrap().unwrap_or(1);
+
+    // Also report the dag cost with identical subterms in different classes counted once.
+    let cse_cost = args.contains("--cse-cost");
+
+    // Also report a lower bound on the dag cost, and how far the extraction is from it.
+    let lower_bound = args.contains("--lower-bound");
+    if lower_bound && !cfg!(feature = "ilp-cbc") {
+        panic!("--lower-bound needs the ilp-cbc feature");
+    }
+
+    // Extractors may make choices for classes that the roots don't need.
+    let keep_unreachable = args.contains("--keep-unreachable");
+
+    // How many of the most expensive classes to list in the output.
+    let top_classes: usize = args
+        .opt_value_from_str("--top-classes")
+        .unwrap()
+        .unwrap_or(10);
+
+    // Labels to copy into the result, each given as `--extra key=value`.
+    let extra: IndexMap<String, String> = args
+        .values_from_str::<_, String>("--extra")
+        .unwrap()
+        .into_iter()
+        .map(|pair| {
+            let (key, value) = pair
+                .split_once('=')
+                .unwrap_or_else(|| panic!("--extra {pair} isn't key=value"));
+            (key.to_string(), value.to_string())
+        })
+        .collect();
+
+    // Run a stochastic extractor with this many seeds and keep the best, see `restarts`.
+    let restarts: Option<usize> = args.opt_value_from_str("--restarts").unwrap();
+
+    // Give up on the extractor after this many seconds and fall back to bottom-up.
+    let timeout: Option<f64> = args.opt_value_from_str("--timeout").unwrap();
+
+    // Time this many runs after the first, cold, run, see `schema::Timing`.
+    let repeat: Option<usize> = args.opt_value_from_str("--repeat").unwrap();
+
+    // With --repeat, run this many more times after the cold run without timing them.
+    let warmup: usize = args.opt_value_from_str("--warmup").unwrap().unwrap_or(0);
+    assert!(warmup == 0 || repeat.is_some(), "--warmup needs --repeat");
+
+    // Skip the strict checks of the input format, for older files.
+    let lenient = args.contains("--lenient");
+
+    // Read the egraph straight into the compact form some extractors run on, for
+    // egraphs too big to load otherwise, see `FastEgraph::read_json`.
+    let low_memory = args.contains("--low-memory");
+
+    // The order faster-greedy-dag processes nodes in, first in first out by default.
+    let node_order: Option<extract::faster_greedy_dag::NodeOrder> =
+        args.opt_value_from_str("--node-order").unwrap();
+
+    // The discount per level of depth for discounted-bottom-up, 0.5 by default, see
+    // `ExtractionResult::discounted_tree_cost`.
+    let gamma: Option<f64> = args.opt_value_from_str("--gamma").unwrap();
+    if let Some(gamma) = gamma {
+        assert_eq!(
+            extractor_name, "discounted-bottom-up",
+            "--gamma is only supported by discounted-bottom-up"
+        );
+        assert!(
+            gamma > 0.0 && gamma <= 1.0,
+            "--gamma must be above 0 and at most 1"
+        );
+    }
+
+    // Stop global-greedy-dag after this many passes, once the roots cost at most
+    // this much, or after this many seconds, see `BudgetedGlobalGreedyDagExtractor`.
+    let max_iterations: Option<usize> = args.opt_value_from_str("--max-iterations").unwrap();
+    let target_cost: Option<f64> = args.opt_value_from_str("--target-cost").unwrap();
+    let time_budget: Option<f64> = args.opt_value_from_str("--time-budget").unwrap();
+    let budgeted = max_iterations.is_some() || target_cost.is_some() || time_budget.is_some();
+    if budgeted {
+        assert_eq!(
+            extractor_name, "global-greedy-dag",
+            "--max-iterations, --target-cost and --time-budget are only supported by global-greedy-dag"
+        );
+        assert!(
+            timeout.is_none(),
+            "--max-iterations, --target-cost and --time-budget can't be used with --timeout"
+        );
+        assert!(
+            max_iterations != Some(0),
+            "--max-iterations must be at least 1"
+        );
+        assert!(
+            time_budget.is_none_or(|t| t >= 0.0),
+            "--time-budget can't be negative"
+        );
+    }
+
+    // What the extractor minimises, the dag cost by default, the tree cost, or the
+    // depth, see `ExtractionResult::depth_cost`.
+    let objective: String = args
+        .opt_value_from_str("--objective")
+        .unwrap()
+        .unwrap_or_else(|| "cost".into());
+
+    // Extra validity rules of the benchmark suite, see `suite_rules`.
+    let rules_filename: Option<PathBuf> = args.opt_value_from_str("--rules").unwrap();
+
+    // Ops the extraction may use at most one of, see `op_policy`.
+    let policy_filename: Option<PathBuf> = args.opt_value_from_str("--policy").unwrap();
+
+    // Find the cheapest extraction that differs from these choices in at least
+    // `--distance` of them, with the ILP extractors, see `ilp_cbc`.
+    let differ_from_filename: Option<PathBuf> = args.opt_value_from_str("--differ-from").unwrap();
+    let distance: usize = args.opt_value_from_str("--distance").unwrap().unwrap_or(1);
+    #[cfg(not(feature = "ilp-cbc"))]
+    assert!(
+        differ_from_filename.is_none(),
+        "--differ-from needs the ilp-cbc feature"
+    );
+
+    // Where to write a flamegraph of the extraction, with the `profile` feature.
+    let profile_filename: Option<PathBuf> = args.opt_value_from_str("--profile").unwrap();
+    #[cfg(not(feature = "profile"))]
+    assert!(
+        profile_filename.is_none(),
+        "--profile needs the profile feature"
+    );
+
+    // Write the extracted term for each root as an S-expression.
+    let print_expr = args.contains("--print-expr");
+    let expr_filename: Option<PathBuf> = args.opt_value_from_str("--expr-out").unwrap();
+
+    let hint_filename: Option<PathBuf> = args.opt_value_from_str("--hint").unwrap();
+    let choices_filename: Option<PathBuf> = args.opt_value_from_str("--choices-out").unwrap();
+
+    // Extract every egraph in this directory rather than a single file.
+    let batch_dir: Option<PathBuf> = args.opt_value_from_str("--batch").unwrap();
+    if let Some(dir) = batch_dir {
+        // Only the egraphs in these suites, and with at most this many classes, see `corpus`.
+        let suites: Vec<String> = args.values_from_str("--suite").unwrap();
+        let max_classes: Option<usize> = args.opt_value_from_str("--max-classes").unwrap();
+        assert!(extra.is_empty(), "--extra isn't supported with --batch");
+        assert!(gamma.is_none(), "--gamma isn't supported with --batch");
+        assert!(
+            !budgeted,
+            "--max-iterations, --target-cost and --time-budget aren't supported with --batch"
+        );
+        let rest = args.finish();
+        if !rest.is_empty() {
+            panic!("Unknown arguments: {:?}", rest);
+        }
+        let ed = extractor_named(&extractor_name).unwrap();
+        let mut corpus = corpus::Corpus::discover([dir]).in_suites(&suites);
+        if let Some(max_classes) = max_classes {
+            corpus = corpus.with_max_classes(max_classes);
+        }
+        let report = batch::run_batch(&ed, &extractor_name, &corpus, lenient, threads);
+        let mut out_file = std::fs::File::create(out_filename).unwrap();
+        serde_json::to_writer_pretty(&mut out_file, &report).unwrap();
+        writeln!(out_file).unwrap();
+        return;
+    }
+
     let filename: String = args.free_from_str().unwrap();
 
     let rest = args.finish();
@@ -137,37 +312,680 @@ fn main() {
 
     let mut out_file = std::fs::File::create(out_filename).unwrap();
 
-    let egraph = EGraph::from_json_file(&filename)
+    if low_memory {
+        assert!(
+            cost_model_filename.is_none()
+                && also_eval_filenames.is_empty()
+                && only_ops.is_none()
+                && exclude_ops.is_none()
+                && !canonicalize
+                && !preprocess
+                && !decompose
+                && !repair
+                && !per_root
+                && !cse_cost
+                && !lower_bound
+                && restarts.is_none()
+                && timeout.is_none()
+                && repeat.is_none()
+                && node_order.is_none()
+                && gamma.is_none()
+                && !budgeted
+                && objective == "cost"
+                && rules_filename.is_none()
+                && policy_filename.is_none()
+                && differ_from_filename.is_none()
+                && hint_filename.is_none()
+                && choices_filename.is_none()
+                && expr_filename.is_none()
+                && !print_expr,
+            "--low-memory can't be combined with options that need the egraph"
+        );
+        let fast_extractor: &dyn extract::fast_egraph::FastExtractor = match extractor_name.as_str()
+        {
+            "bottom-up" => &extract::bottom_up::BottomUpExtractor,
+            _ => panic!("--low-memory is only supported by bottom-up"),
+        };
+
+        assert!(
+            !filename.ends_with(".bin"),
+            "--low-memory only reads JSON, use the egraph that {filename} was converted from"
+        );
+        let parse_start = std::time::Instant::now();
+        let fast = extract::fast_egraph::FastEgraph::read_json(&filename, lenient)
+            .with_context(|| format!("Failed to parse {filename}"))
+            .unwrap();
+        let parse_micros = parse_start.elapsed().as_micros() as u64;
+
+        extract::memory_limit::reset_peak();
+        let start_time = std::time::Instant::now();
+        let choices = fast_extractor.extract_fast(&fast);
+        let us = start_time.elapsed().as_micros();
+        let peak_bytes = extract::memory_limit::peak_resident_bytes();
+        let costs = fast.costs(&choices);
+
+        log::info!(
+            "{filename:40}\t{extractor_name:10}\t{:5}\t{:5}\t{us:5}",
+            costs.tree,
+            costs.dag
+        );
+        let record = schema::ResultRecord {
+            version: schema::SCHEMA_VERSION,
+            name: filename,
+            extractor: extractor_name,
+            status: schema::RunStatus::Ok,
+            tree: costs.tree.into_inner(),
+            dag: costs.dag.into_inner(),
+            depth: costs.depth.into_inner(),
+            cost_model: None,
+            also_eval: vec![],
+            lower_bound: None,
+            gap: None,
+            policy_violations: None,
+            cse_dag: None,
+            micros: us as u64,
+            peak_bytes,
+            parse_micros,
+            timing: None,
+            threads,
+            memory_limit_exceeded: false,
+            timed_out: false,
+            pruned_choices: 0,
+            repaired_classes: 0,
+            root_costs: vec![],
+            expensive_classes: vec![],
+            root_micros: vec![],
+            restarts: vec![],
+            solver_stats: None,
+            extra,
+        };
+        serde_json::to_writer_pretty(&mut out_file, &record).unwrap();
+        writeln!(out_file).unwrap();
+        return;
+    }
+
+    let parse_start = std::time::Instant::now();
+    let egraph = schema::read_egraph(&filename, lenient)
         .with_context(|| format!("Failed to parse {filename}"))
         .unwrap();
+    let parse_micros = parse_start.elapsed().as_micros() as u64;
 
-    let ed = extractors
-        .get(extractor_name.as_str())
-        .with_context(|| format!("Unknown extractor: {extractor_name}"))
-        .unwrap();
+    let cost_model = cost_model_filename.map(|path| {
+        CostModel::from_json_file(&path)
+            .with_context(|| format!("Failed to read cost model {}", path.display()))
+            .unwrap()
+    });
+    let also_eval: Vec<(PathBuf, CostModel)> = also_eval_filenames
+        .into_iter()
+        .map(|path| {
+            let model = CostModel::from_json_file(&path)
+                .with_context(|| format!("Failed to read cost model {}", path.display()))
+                .unwrap();
+            (path, model)
+        })
+        .collect();
+    // The serialized costs, which the --also-eval models apply to.
+    let serialized = (!also_eval.is_empty()).then(|| egraph.clone());
+    let egraph = match &cost_model {
+        None => egraph,
+        Some(model) => model.apply(&egraph),
+    };
+
+    let op_filter =
+        extract::op_filter::OpFilter::from_lists(only_ops.as_deref(), exclude_ops.as_deref());
+    let egraph = if op_filter.is_empty() {
+        egraph
+    } else {
+        op_filter
+            .apply(&egraph)
+            .with_context(|| format!("Failed to filter ops in {filename}"))
+            .unwrap()
+    };
+
+    let egraph = if canonicalize {
+        extract::canonicalize::canonicalize(&egraph).0
+    } else {
+        egraph
+    };
+
+    let egraph = if preprocess {
+        extract::preprocess::preprocess(&egraph)
+            .with_context(|| format!("Failed to preprocess {filename}"))
+            .unwrap()
+            .0
+    } else {
+        egraph
+    };
+
+    let ed = extractor_named(&extractor_name).unwrap();
+
+    let ordered;
+    let extractor: &dyn Extractor = match node_order {
+        None => &*ed.extractor,
+        Some(order) => {
+            assert_eq!(
+                extractor_name, "faster-greedy-dag",
+                "--node-order is only supported by faster-greedy-dag"
+            );
+            ordered = extract::faster_greedy_dag::OrderedGreedyDagExtractor { order };
+            &ordered
+        }
+    };
+
+    let discounted;
+    let extractor: &dyn Extractor = match gamma {
+        None => extractor,
+        Some(gamma) => {
+            discounted = extract::bottom_up::DiscountedBottomUpExtractor::new(gamma).unwrap();
+            &discounted
+        }
+    };
+
+    let budgeted_extractor;
+    let extractor: &dyn Extractor = if budgeted {
+        budgeted_extractor = extract::global_greedy_dag::BudgetedGlobalGreedyDagExtractor {
+            max_iterations,
+            target_cost: target_cost.map(|c| Cost::new(c).unwrap()),
+            time_budget: time_budget.map(std::time::Duration::from_secs_f64),
+        };
+        &budgeted_extractor
+    } else {
+        extractor
+    };
+
+    let rules = rules_filename.map(|path| {
+        extract::suite_rules::CoSelectRule::from_json_file(&path)
+            .with_context(|| format!("Failed to read rules {}", path.display()))
+            .unwrap()
+    });
+
+    // The ILP extractors can meet the rules rather than be vetoed by them.
+    #[cfg(feature = "ilp-cbc")]
+    let constrained;
+    #[cfg(feature = "ilp-cbc")]
+    let extractor: &dyn Extractor = match (&rules, extractor_name.as_str()) {
+        (Some(rule), "ilp-cbc" | "ilp-cbc-timeout") => {
+            constrained = extract::ilp_cbc::CbcExtractorWithRules {
+                rule,
+                timeout_seconds: if extractor_name == "ilp-cbc" {
+                    std::u32::MAX
+                } else {
+                    10
+                },
+            };
+            &constrained
+        }
+        _ => extractor,
+    };
+
+    let policy = policy_filename.map(|path| {
+        extract::op_policy::OpPolicy::from_json_file(&path)
+            .with_context(|| format!("Failed to read policy {}", path.display()))
+            .unwrap()
+    });
+    assert!(
+        rules.is_none() || policy.is_none(),
+        "--policy can't be combined with --rules"
+    );
+
+    // The ILP extractors can meet the policy exactly, the others only approximately.
+    let with_policy: Box<dyn Extractor + '_>;
+    let extractor: &dyn Extractor = match &policy {
+        None => extractor,
+        Some(policy) => {
+            with_policy = match extractor_name.as_str() {
+                #[cfg(feature = "ilp-cbc")]
+                "ilp-cbc" | "ilp-cbc-timeout" => {
+                    Box::new(extract::ilp_cbc::CbcExtractorWithPolicy {
+                        policy,
+                        timeout_seconds: if extractor_name == "ilp-cbc" {
+                            std::u32::MAX
+                        } else {
+                            10
+                        },
+                    })
+                }
+                _ => Box::new(extract::op_policy::PenalizedExtractor { extractor, policy }),
+            };
+            &*with_policy
+        }
+    };
+
+    let reference = differ_from_filename.map(|path| {
+        ExtractionResult::from_json_file(&path)
+            .with_context(|| format!("Failed to read choices {}", path.display()))
+            .unwrap()
+    });
+    #[cfg(feature = "ilp-cbc")]
+    let diverse;
+    #[cfg(feature = "ilp-cbc")]
+    let extractor: &dyn Extractor = match &reference {
+        Some(reference) => {
+            assert!(
+                rules.is_none()
+                    && policy.is_none()
+                    && matches!(extractor_name.as_str(), "ilp-cbc" | "ilp-cbc-timeout"),
+                "--differ-from needs the ilp-cbc or ilp-cbc-timeout extractor, without --rules or --policy"
+            );
+            diverse = extract::ilp_cbc::CbcExtractorDiverse {
+                reference,
+                distance,
+                timeout_seconds: if extractor_name == "ilp-cbc" {
+                    std::u32::MAX
+                } else {
+                    10
+                },
+            };
+            &diverse
+        }
+        None => extractor,
+    };
+
+    let by_objective;
+    let extractor: &dyn Extractor = match objective.as_str() {
+        "cost" => extractor,
+        "depth" => {
+            assert!(
+                rules.is_none() && policy.is_none() && reference.is_none(),
+                "--objective depth can't be combined with --rules, --policy or --differ-from"
+            );
+            by_objective = match extractor_name.as_str() {
+                "bottom-up" => extract::bottom_up::DepthBottomUpExtractor.boxed(),
+                #[cfg(feature = "ilp-cbc")]
+                "ilp-cbc" => extract::ilp_cbc::CbcExtractorMinDepth {
+                    timeout_seconds: std::u32::MAX,
+                }
+                .boxed(),
+                #[cfg(feature = "ilp-cbc")]
+                "ilp-cbc-timeout" => extract::ilp_cbc::CbcExtractorMinDepth {
+                    timeout_seconds: 10,
+                }
+                .boxed(),
+                _ => panic!(
+                    "--objective depth is only supported by bottom-up, ilp-cbc and ilp-cbc-timeout"
+                ),
+            };
+            &*by_objective
+        }
+        "tree" => {
+            assert!(
+                rules.is_none() && policy.is_none() && reference.is_none(),
+                "--objective tree can't be combined with --rules, --policy or --differ-from"
+            );
+            by_objective = match extractor_name.as_str() {
+                "bottom-up" => extract::bottom_up::BottomUpExtractor.boxed(),
+                #[cfg(feature = "ilp-cbc")]
+                "ilp-cbc" => extract::ilp_cbc::CbcExtractorMinTree {
+                    timeout_seconds: std::u32::MAX,
+                }
+                .boxed(),
+                #[cfg(feature = "ilp-cbc")]
+                "ilp-cbc-timeout" => extract::ilp_cbc::CbcExtractorMinTree {
+                    timeout_seconds: 10,
+                }
+                .boxed(),
+                _ => panic!(
+                    "--objective tree is only supported by bottom-up, ilp-cbc and ilp-cbc-timeout"
+                ),
+            };
+            &*by_objective
+        }
+        _ => panic!("Unknown objective: {objective}"),
+    };
+
+    let decomposed;
+    let extractor: &dyn Extractor = if decompose {
+        assert!(
+            rules.is_none() && policy.is_none() && reference.is_none() && objective == "cost",
+            "--decompose can't be combined with --rules, --policy, --differ-from or --objective"
+        );
+        decomposed = extract::decompose::DecomposedExtractor {
+            extractor,
+            min_region_classes: extract::decompose::MIN_REGION_CLASSES,
+        };
+        &decomposed
+    } else {
+        extractor
+    };
+
+    if !ed.supports_cycles && extract::canonicalize::has_cycles(&egraph) {
+        panic!("{extractor_name} doesn't support egraphs with cycles, like {filename}");
+    }
+
+    let hint = hint_filename.map(|path| {
+        ExtractionResult::from_json_file(&path)
+            .with_context(|| format!("Failed to read hint {}", path.display()))
+            .unwrap()
+    });
+
+    if timeout.is_some() {
+        assert!(
+            !per_root
+                && restarts.is_none()
+                && hint.is_none()
+                && rules.is_none()
+                && policy.is_none()
+                && reference.is_none()
+                && objective == "cost"
+                && !decompose
+                && repeat.is_none(),
+            "--timeout can't be combined with --per-root, --restarts, --hint, --rules, --policy, --differ-from, --objective, --decompose or --repeat"
+        );
+    }
+    assert!(
+        !(per_root && hint.is_some()),
+        "--hint can't be combined with --per-root"
+    );
+    assert!(
+        restarts.is_none() || (!per_root && hint.is_none()),
+        "--restarts can't be combined with --per-root or --hint"
+    );
+    // The extraction's thread may outlive the deadline, so it gets its own egraph,
+    // which is copied before any timing starts.
+    let shared_egraph = timeout.map(|_| std::sync::Arc::new(egraph.clone()));
+    let timed_out = std::cell::Cell::new(false);
+
+    // Returns the result, the time each root took if extracted separately, each restart's
+    // cost and time, and the total time.
+    let run = || {
+        // Its parts are computed lazily, inside the timed extraction, so the time includes
+        // the setup the extractor would do without a cache. Each run gets a new one, so
+        // repeats time the same work as the first run.
+        let cache = CostCache::new(&egraph);
+        let start_time = std::time::Instant::now();
+        let (result, root_micros, restart_runs) = match &hint {
+            _ if per_root => {
+                let per_root = extract::per_root::extract_per_root(
+                    extractor,
+                    &cache,
+                    &egraph.root_eclasses,
+                    threads,
+                );
+                (per_root.result, per_root.root_micros, vec![])
+            }
+            _ if restarts.is_some() => {
+                let restarts = extract::restarts::extract_with_restarts(
+                    extractor,
+                    &egraph,
+                    &egraph.root_eclasses,
+                    restarts.unwrap(),
+                    threads,
+                );
+                (restarts.result, vec![], restarts.runs)
+            }
+            Some(hint) => (
+                extractor.extract_with_hint(&egraph, &egraph.root_eclasses, hint),
+                vec![],
+                vec![],
+            ),
+            None if timeout.is_some() => {
+                let name = extractor_name.clone();
+                let make_extractor = move || match (node_order, gamma) {
+                    (Some(order), _) => {
+                        extract::faster_greedy_dag::OrderedGreedyDagExtractor { order }.boxed()
+                    }
+                    (None, Some(gamma)) => {
+                        extract::bottom_up::DiscountedBottomUpExtractor::new(gamma)
+                            .unwrap()
+                            .boxed()
+                    }
+                    (None, None) => extractor_named(&name).unwrap().extractor,
+                };
+                let result = extract::timeout::extract_with_timeout(
+                    make_extractor,
+                    shared_egraph.clone().unwrap(),
+                    egraph.root_eclasses.clone(),
+                    std::time::Duration::from_secs_f64(timeout.unwrap()),
+                );
+                let result = result.unwrap_or_else(|| {
+                    log::warn!("{extractor_name} timed out, falling back to bottom-up");
+                    timed_out.set(true);
+                    extract::bottom_up::BottomUpExtractor.extract(&egraph, &egraph.root_eclasses)
+                });
+                (result, vec![], vec![])
+            }
+            None => (
+                extractor.extract_with_cache(&egraph, &egraph.root_eclasses, &cache),
+                vec![],
+                vec![],
+            ),
+        };
+        (
+            result,
+            root_micros,
+            restart_runs,
+            start_time.elapsed().as_micros(),
+        )
+    };
 
-    let start_time = std::time::Instant::now();
-    let result = ed.extractor.extract(&egraph, &egraph.root_eclasses);
-    let us = start_time.elapsed().as_micros();
+    #[cfg(feature = "profile")]
+    let profiler = profile_filename.as_ref().map(|_| {
+        pprof::ProfilerGuardBuilder::default()
+            .frequency(1000)
+            .blocklist(&["libc", "libgcc", "pthread", "vdso"])
+            .build()
+            .unwrap()
+    });
+
+    let peak_reset = extract::memory_limit::reset_peak();
+    let (mut result, root_micros, restart_runs, mut us) = run();
+    let peak_bytes = extract::memory_limit::peak_resident_bytes();
+    if !peak_reset {
+        log::info!("Couldn't reset the peak memory use, so it includes parsing");
+    }
+
+    #[cfg(feature = "profile")]
+    if let (Some(profiler), Some(path)) = (profiler, &profile_filename) {
+        let report = profiler.report().build().unwrap();
+        let svg = std::fs::File::create(path)
+            .with_context(|| format!("Failed to create {}", path.display()))
+            .unwrap();
+        report.flamegraph(svg).unwrap();
+    }
+
+    // With --repeat, the first run is the cold one, then come the warmup runs, and `us`
+    // becomes the median of the repeats.
+    let mut timing = None;
+    if let Some(repeat) = repeat {
+        let mut runs = Vec::with_capacity(repeat);
+        for i in 0..warmup + repeat {
+            let (repeated, _, _, micros) = run();
+            if ed.deterministic {
+                assert!(
+                    repeated.choices == result.choices,
+                    "{extractor_name} gave different results on {filename} when repeated"
+                );
+            }
+            if i >= warmup {
+                runs.push(micros);
+            }
+        }
+        let stats = schema::Timing::from_micros(us, warmup, runs);
+        us = stats.median as u128;
+        timing = Some(stats);
+    }
+
+    let mut repaired_classes = 0;
+    if repair {
+        let repaired = extract::repair::repair(&result, &egraph, &egraph.root_eclasses)
+            .with_context(|| format!("Failed to repair the extraction of {filename}"))
+            .unwrap();
+        log::info!("Repaired {} classes", repaired.repaired_classes);
+        result = repaired.result;
+        repaired_classes = repaired.repaired_classes;
+    }
+
+    let pruned = if keep_unreachable {
+        0
+    } else {
+        result.retain_reachable(&egraph, &egraph.root_eclasses)
+    };
+    if pruned > 0 {
+        log::info!("Pruned {pruned} choices for classes unreachable from the roots");
+    }
+
+    if let Some(reference) = &reference {
+        let differing = result.differing_choices(reference);
+        let complete = egraph
+            .root_eclasses
+            .iter()
+            .all(|root| result.choices.contains_key(root));
+        assert!(
+            complete && differing >= distance,
+            "No extraction of {filename} differs from the reference in {distance} choices"
+        );
+        log::info!("Differs from the reference in {differing} choices");
+    }
 
     result.check(&egraph);
 
+    if let Some(rule) = &rules {
+        extract::suite_rules::SuiteRule::check(rule, &egraph, &result)
+            .with_context(|| format!("{extractor_name} broke the rules for {filename}"))
+            .unwrap();
+    }
+
+    // Only the ILP extractors are sure to meet the policy, so breaking it isn't an error.
+    let policy_violations = policy.as_ref().map(|policy| {
+        let violations = policy.violations(&egraph, &result, &egraph.root_eclasses);
+        for violation in &violations {
+            log::warn!(
+                "{extractor_name} used {:?} in {filename}, of which at most one may be used",
+                violation.ops
+            );
+        }
+        violations.len()
+    });
+
+    if print_expr || expr_filename.is_some() {
+        let mut exprs = String::new();
+        for root in &egraph.root_eclasses {
+            exprs += &format!("; root {root}\n{}\n", result.to_sexp(&egraph, root));
+        }
+        if print_expr {
+            print!("{exprs}");
+        }
+        if let Some(path) = &expr_filename {
+            std::fs::write(path, exprs)
+                .with_context(|| format!("Failed to write terms to {}", path.display()))
+                .unwrap();
+        }
+    }
+
+    if let Some(path) = choices_filename {
+        result
+            .to_json_file(&path)
+            .with_context(|| format!("Failed to write choices to {}", path.display()))
+            .unwrap();
+    }
+
     let tree = result.tree_cost(&egraph, &egraph.root_eclasses);
-    let dag = result.dag_cost(&egraph, &egraph.root_eclasses);
+    let mut breakdown = result.dag_cost_breakdown(&egraph, &egraph.root_eclasses);
+    // Sorted by class first so the sum, and the order of classes of equal cost, don't
+    // depend on the order the extractor made its choices in.
+    breakdown.sort_keys();
+    let dag: Cost = breakdown.values().sum();
+    breakdown.sort_by(|_, a, _, b| b.cmp(a));
+
+    #[cfg(feature = "ilp-cbc")]
+    let bound = lower_bound
+        .then(|| extract::ilp_cbc::lp_lower_bound(&egraph, &egraph.root_eclasses))
+        .flatten();
+    #[cfg(not(feature = "ilp-cbc"))]
+    let bound: Option<Cost> = None;
+    let gap = bound.map(|bound| {
+        if dag.into_inner() > 0.0 {
+            ((dag - bound) / dag).into_inner()
+        } else {
+            0.0
+        }
+    });
 
     log::info!("{filename:40}\t{extractor_name:10}\t{tree:5}\t{dag:5}\t{us:5}");
-    writeln!(
-        out_file,
-        r#"{{ 
-    "name": "{filename}",
-    "extractor": "{extractor_name}", 
-    "tree": {tree}, 
-    "dag": {dag}, 
-    "micros": {us}
-}}"#
-    )
-    .unwrap();
+    let record = schema::ResultRecord {
+        version: schema::SCHEMA_VERSION,
+        name: filename,
+        extractor: extractor_name,
+        status: schema::RunStatus::of(timed_out.get(), result.memory_limit_exceeded),
+        tree: tree.into_inner(),
+        dag: dag.into_inner(),
+        depth: result
+            .depth_cost(&egraph, &egraph.root_eclasses)
+            .into_inner(),
+        cost_model: cost_model.map(|model| schema::CostModelRecord {
+            version: model.version,
+            hash: model.hash,
+        }),
+        also_eval: also_eval
+            .into_iter()
+            .map(|(path, model)| {
+                let serialized = serialized.as_ref().unwrap();
+                let (tree, dag) = model.evaluate(serialized, &result, &egraph.root_eclasses);
+                log::info!("Under {}: tree {tree}, dag {dag}", path.display());
+                schema::ModelCostRecord {
+                    path: path.display().to_string(),
+                    model: schema::CostModelRecord {
+                        version: model.version,
+                        hash: model.hash,
+                    },
+                    tree: tree.into_inner(),
+                    dag: dag.into_inner(),
+                }
+            })
+            .collect(),
+        lower_bound: bound.map(|bound| bound.into_inner()),
+        gap,
+        policy_violations,
+        cse_dag: cse_cost.then(|| {
+            result
+                .cse_dag_cost(&egraph, &egraph.root_eclasses)
+                .into_inner()
+        }),
+        micros: us as u64,
+        peak_bytes,
+        parse_micros,
+        timing,
+        threads,
+        memory_limit_exceeded: result.memory_limit_exceeded,
+        timed_out: timed_out.get(),
+        solver_stats: result.solver_stats.clone(),
+        pruned_choices: pruned,
+        repaired_classes,
+        root_costs: egraph
+            .root_eclasses
+            .iter()
+            .zip(result.root_costs(&egraph, &egraph.root_eclasses))
+            .map(|(root, cost)| schema::RootCostRecord {
+                root: root.to_string(),
+                tree: cost.tree.into_inner(),
+                dag: cost.dag.into_inner(),
+            })
+            .collect(),
+        expensive_classes: breakdown
+            .iter()
+            .take(top_classes)
+            .map(|(cid, cost)| schema::ClassCost {
+                class: cid.to_string(),
+                cost: cost.into_inner(),
+            })
+            .collect(),
+        restarts: restart_runs
+            .into_iter()
+            .map(|run| schema::RestartRecord {
+                seed: run.seed,
+                dag: run.dag.map(|dag| dag.into_inner()),
+                micros: run.micros as u64,
+            })
+            .collect(),
+        root_micros: root_micros
+            .into_iter()
+            .map(|(cid, micros)| schema::RootMicros {
+                root: cid.to_string(),
+                micros: micros as u64,
+            })
+            .collect(),
+        extra,
+    };
+    serde_json::to_writer_pretty(&mut out_file, &record).unwrap();
+    writeln!(out_file).unwrap();
 }
-
-#[cfg(test)]
-pub mod test;
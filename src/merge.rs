@@ -0,0 +1,107 @@
+/*
+Merges egraphs over the same classes, such as two independent saturations of the same
+program, so extractors can be studied on the richer set of nodes.
+
+Classes are matched by id, and the merged egraph has the nodes of both. A node id
+that's in both must be the same node in both, and a class with a type in both must
+have the same type, otherwise the egraphs are inconsistent and the merge is an error.
+The roots are those of the first egraph, then those of the second that aren't roots
+of the first.
+
+Run with `extraction-gym merge-egraphs [--out merged.json] <a.json> <b.json>...`,
+which writes to stdout without `--out`.
+*/
+
+use crate::*;
+use anyhow::Context;
+use egraph_serialize::ClassData;
+
+// A new egraph, since an egraph's classes can't change once they've been computed.
+pub fn merge(first: &EGraph, other: &EGraph) -> anyhow::Result<EGraph> {
+    let mut egraph = EGraph::default();
+    for (nid, node) in &first.nodes {
+        egraph.add_node(nid.clone(), node.clone());
+    }
+    egraph.class_data = first.class_data.clone();
+    for (nid, node) in &other.nodes {
+        match egraph.nodes.get(nid) {
+            None => egraph.add_node(nid.clone(), node.clone()),
+            Some(existing) => {
+                anyhow::ensure!(
+                    existing.eclass == node.eclass,
+                    "Node {nid} is in class {} in one egraph and {} in the other",
+                    existing.eclass,
+                    node.eclass
+                );
+                anyhow::ensure!(
+                    existing.op == node.op
+                        && existing.children == node.children
+                        && existing.cost == node.cost,
+                    "Node {nid} differs between the egraphs"
+                );
+            }
+        }
+    }
+    for (cid, data) in &other.class_data {
+        let typ = egraph.class_data.get(cid).and_then(|d| d.typ.as_ref());
+        match (typ, &data.typ) {
+            (Some(typ), Some(other_typ)) => anyhow::ensure!(
+                typ == other_typ,
+                "Class {cid} has type {typ} in one egraph and {other_typ} in the other"
+            ),
+            (None, Some(_)) => {
+                egraph.class_data.insert(
+                    cid.clone(),
+                    ClassData {
+                        typ: data.typ.clone(),
+                    },
+                );
+            }
+            (_, None) => (),
+        }
+    }
+    egraph.root_eclasses = first.root_eclasses.clone();
+    egraph
+        .root_eclasses
+        .extend(other.root_eclasses.iter().cloned());
+    schema::normalize_roots(&mut egraph)?;
+    Ok(egraph)
+}
+
+pub fn merge_egraphs_command(mut args: pico_args::Arguments) {
+    let lenient = args.contains("--lenient");
+    let out_filename: Option<std::path::PathBuf> = args.opt_value_from_str("--out").unwrap();
+    let filenames: Vec<String> = args
+        .finish()
+        .into_iter()
+        .map(|arg| arg.into_string().unwrap())
+        .collect();
+    if filenames.len() < 2 {
+        panic!("Give at least two egraphs to merge");
+    }
+
+    let read = |filename: &str| {
+        schema::read_egraph(filename, lenient)
+            .with_context(|| format!("Failed to parse {filename}"))
+            .unwrap()
+    };
+    let mut merged = read(&filenames[0]);
+    for filename in &filenames[1..] {
+        let nodes = merged.nodes.len();
+        merged = merge(&merged, &read(filename))
+            .with_context(|| format!("Failed to merge {filename}"))
+            .unwrap();
+        log::info!(
+            "Merging {filename} added {} nodes",
+            merged.nodes.len() - nodes
+        );
+    }
+
+    let json = serde_json::to_string_pretty(&merged).unwrap();
+    match out_filename {
+        Some(path) => std::fs::write(&path, json + "\n")
+            .with_context(|| format!("Failed to write {}", path.display()))
+            .unwrap(),
+        None => println!("{json}"),
+    }
+}
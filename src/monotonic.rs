@@ -0,0 +1,132 @@
+/*
+Checks that extracting from a newer version of an egraph costs no more than
+extracting from an older one.
+
+Running more rewrites only adds options, so an egraph that's been saturated further
+and serialized again should never extract to something more expensive. If it does,
+either the extractor isn't optimal, or something was lost between the versions,
+e.g. by filtering nodes before serializing.
+
+Both versions are extracted with the same extractor, from the older version's roots,
+which must all still be in the newer one. If the newer dag cost is higher, the
+report lists the classes chosen in the older extraction that the newer egraph can no
+longer choose in the same way: either the class is gone, or the node chosen for it
+is gone or now in another class. Node and class ids are compared as they are, so
+this assumes the serializer keeps them stable between versions.
+
+Run with `extraction-gym check-monotonic [--extractor <name>] <old.json> <new.json>`.
+*/
+
+use crate::*;
+use anyhow::Context;
+
+pub struct LostChoice {
+    pub class: ClassId,
+    pub node: NodeId,
+    // Whether the whole class is gone, rather than just the node.
+    pub class_missing: bool,
+}
+
+pub struct MonotonicityReport {
+    pub old_dag: Cost,
+    pub new_dag: Cost,
+    // Only filled in if the newer egraph extracted to something more expensive.
+    pub lost: Vec<LostChoice>,
+}
+
+impl MonotonicityReport {
+    pub fn regressed(&self) -> bool {
+        self.new_dag.into_inner() > self.old_dag.into_inner() + EPSILON_ALLOWANCE
+    }
+}
+
+pub fn check_monotonic(
+    extractor: &dyn Extractor,
+    old: &EGraph,
+    new: &EGraph,
+) -> anyhow::Result<MonotonicityReport> {
+    let roots = &old.root_eclasses;
+    let new_classes = new.classes();
+    for root in roots {
+        anyhow::ensure!(
+            new_classes.contains_key(root),
+            "Root {root} is missing from the newer egraph"
+        );
+    }
+
+    let mut old_result = extractor.extract(old, roots);
+    let mut new_result = extractor.extract(new, roots);
+    for (result, egraph) in [(&mut old_result, old), (&mut new_result, new)] {
+        result.retain_reachable(egraph, roots);
+        result.check(egraph);
+    }
+
+    let mut report = MonotonicityReport {
+        old_dag: old_result.dag_cost(old, roots),
+        new_dag: new_result.dag_cost(new, roots),
+        lost: vec![],
+    };
+    if report.regressed() {
+        report.lost = old_result
+            .choices
+            .iter()
+            .filter_map(|(cid, nid)| {
+                let class_missing = !new_classes.contains_key(cid);
+                let node_kept = new.nodes.get(nid).is_some_and(|n| n.eclass == *cid);
+                (class_missing || !node_kept).then(|| LostChoice {
+                    class: cid.clone(),
+                    node: nid.clone(),
+                    class_missing,
+                })
+            })
+            .collect();
+    }
+    Ok(report)
+}
+
+pub fn check_monotonic_command(mut args: pico_args::Arguments) {
+    let extractor_name: String = args
+        .opt_value_from_str("--extractor")
+        .unwrap()
+        .unwrap_or_else(|| "bottom-up".into());
+    let lenient = args.contains("--lenient");
+    let old_filename: String = args.free_from_str().unwrap();
+    let new_filename: String = args.free_from_str().unwrap();
+
+    let rest = args.finish();
+    if !rest.is_empty() {
+        panic!("Unknown arguments: {:?}", rest);
+    }
+
+    let read = |filename: &str| {
+        schema::read_egraph(filename, lenient)
+            .with_context(|| format!("Failed to parse {filename}"))
+            .unwrap()
+    };
+    let old = read(&old_filename);
+    let new = read(&new_filename);
+
+    let extractors = extractors();
+    let ed = extractors
+        .get(extractor_name.as_str())
+        .with_context(|| format!("Unknown extractor: {extractor_name}"))
+        .unwrap();
+    let report = check_monotonic(ed.extractor.as_ref(), &old, &new)
+        .with_context(|| format!("Can't compare {old_filename} with {new_filename}"))
+        .unwrap();
+
+    println!("old dag\tnew dag");
+    println!("{}\t{}", report.old_dag, report.new_dag);
+    if !report.regressed() {
+        println!("{new_filename}: extracts no worse than {old_filename}");
+        return;
+    }
+
+    println!();
+    println!("class\tnode\tlost");
+    for lost in &report.lost {
+        let what = if lost.class_missing { "class" } else { "node" };
+        println!("{}\t{}\t{what}", lost.class, lost.node);
+    }
+    std::process::exit(1);
+}
@@ -0,0 +1,67 @@
+/*
+Writes the Pareto front of an egraph's extractions, trading the dag cost off against
+the depth, see `ilp_cbc::pareto_front`, as JSON for plotting:
+
+    {"name": ..., "points": [{"dag": ..., "depth": ..., "choices": {...}}, ...]}
+
+The points are cheapest first, so deepest first. Each point's choices are the
+extraction's, sorted by class id.
+
+Run with `extraction-gym pareto-front [--timeout <secs per point>] [--out <front.json>] <egraph.json>`.
+It needs the ilp-cbc feature.
+*/
+
+use crate::*;
+use anyhow::Context;
+use std::path::PathBuf;
+
+pub fn pareto_front_command(mut args: pico_args::Arguments) {
+    let lenient = args.contains("--lenient");
+    let timeout: Option<u32> = args.opt_value_from_str("--timeout").unwrap();
+    let out_filename: PathBuf = args
+        .opt_value_from_str("--out")
+        .unwrap()
+        .unwrap_or_else(|| "front.json".into());
+    let filename: String = args.free_from_str().unwrap();
+
+    let rest = args.finish();
+    if !rest.is_empty() {
+        panic!("Unknown arguments: {:?}", rest);
+    }
+
+    let egraph = schema::read_egraph(&filename, lenient)
+        .with_context(|| format!("Failed to parse {filename}"))
+        .unwrap();
+
+    let front = extract::ilp_cbc::pareto_front(
+        &egraph,
+        &egraph.root_eclasses,
+        timeout.unwrap_or(std::u32::MAX),
+    );
+
+    let report = schema::ParetoFront {
+        version: schema::SCHEMA_VERSION,
+        name: filename,
+        points: front
+            .into_iter()
+            .map(|mut point| {
+                point.result.sort_choices();
+                println!("{}\t{}", point.dag, point.depth);
+                schema::ParetoPointRecord {
+                    dag: point.dag.into_inner(),
+                    depth: point.depth.into_inner(),
+                    choices: point
+                        .result
+                        .choices
+                        .iter()
+                        .map(|(cid, nid)| (cid.to_string(), nid.to_string()))
+                        .collect(),
+                }
+            })
+            .collect(),
+    };
+    let file = std::fs::File::create(&out_filename)
+        .with_context(|| format!("Failed to create {}", out_filename.display()))
+        .unwrap();
+    serde_json::to_writer_pretty(std::io::BufWriter::new(file), &report).unwrap();
+}
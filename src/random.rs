@@ -0,0 +1,267 @@
+/*
+Random egraphs, for fuzzing extractors and reproducing their failures. The tests
+generate them with random seeds, and `extraction-gym gen-random` writes one as JSON:
+
+    extraction-gym gen-random --seed 7 --nodes 500 --cycle-probability 0.1 --out r.json
+
+The same seed and options give the same egraph, for a given version of `rand`.
+
+An egraph has two parts. The core has a loop-free extraction: each core node's
+children are earlier core nodes, each picked with `child_probability`, unless its
+class already has `max_parents_per_class` parents. Each core node starts a new class
+with `new_class_probability`. Then come the extra nodes, in random classes, which
+each point to every node with `cycle_probability`, so they can make cycles that
+extractors must avoid. The roots are core classes.
+
+Costs are drawn from `costs`, scaled by `cost_scale`. Ties and zero costs, which a
+continuous distribution almost never gives, are worth testing, so a cost is a copy of
+an earlier node's with `repeat_cost_probability`, and zero with
+`zero_cost_probability`.
+*/
+
+use crate::*;
+use anyhow::Context;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::collections::HashMap;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CostDistribution {
+    // Between zero and the scale.
+    Uniform,
+    // With the scale as the mean, so a few nodes cost far more than the rest.
+    Exponential,
+    // Whole numbers from zero to the scale, so ties are common.
+    Integer,
+}
+
+impl std::str::FromStr for CostDistribution {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "uniform" => Ok(CostDistribution::Uniform),
+            "exponential" => Ok(CostDistribution::Exponential),
+            "integer" => Ok(CostDistribution::Integer),
+            _ => Err(format!("Unknown cost distribution: {s}")),
+        }
+    }
+}
+
+/// Knobs for `generate`. The default reproduces the original generator: shallow
+/// graphs with fairly random sharing.
+#[derive(Clone, Debug)]
+pub struct RandomEgraphConfig {
+    // Number of nodes in the loop-free part of the egraph.
+    pub core_nodes: std::ops::Range<usize>,
+    // Number of extra nodes, which aren't required and may introduce cycles.
+    pub extra_nodes: std::ops::Range<usize>,
+    // Probability that each core node starts a new class, so lower values give wider classes.
+    pub new_class_probability: f64,
+    // Probability that each earlier core node is picked as a child of a core node.
+    pub child_probability: f64,
+    // Maximum number of core nodes that may have a given class as a child.
+    // One gives a tree-shaped core, larger values give more sharing for DAG extractors to exploit.
+    pub max_parents_per_class: usize,
+    // Probability that each extra node has each node as a child.
+    pub cycle_probability: f64,
+    pub costs: CostDistribution,
+    pub cost_scale: f64,
+    pub zero_cost_probability: f64,
+    pub repeat_cost_probability: f64,
+}
+
+impl Default for RandomEgraphConfig {
+    fn default() -> Self {
+        Self {
+            core_nodes: 1..100,
+            extra_nodes: 1..100,
+            new_class_probability: 0.2,
+            child_probability: 0.1,
+            max_parents_per_class: usize::MAX,
+            cycle_probability: 0.05,
+            costs: CostDistribution::Uniform,
+            cost_scale: 100.0,
+            zero_cost_probability: 0.05,
+            repeat_cost_probability: 0.1,
+        }
+    }
+}
+
+impl RandomEgraphConfig {
+    // No class is shared in the core, so the tree and dag costs of the core agree.
+    pub fn tree_like() -> Self {
+        Self {
+            max_parents_per_class: 1,
+            child_probability: 0.3,
+            ..Default::default()
+        }
+    }
+
+    // Narrow classes, each used by many parents, so sharing dominates the dag cost.
+    pub fn high_sharing() -> Self {
+        Self {
+            new_class_probability: 0.5,
+            child_probability: 0.2,
+            ..Default::default()
+        }
+    }
+
+    fn random_cost(&self, rng: &mut StdRng, nodes: &[Node]) -> Cost {
+        if !nodes.is_empty() && rng.gen_bool(self.repeat_cost_probability) {
+            return nodes[rng.gen_range(0..nodes.len())].cost;
+        }
+        if rng.gen_bool(self.zero_cost_probability) {
+            return Cost::default();
+        }
+        let cost = match self.costs {
+            CostDistribution::Uniform => rng.gen::<f64>() * self.cost_scale,
+            CostDistribution::Exponential => -(1.0 - rng.gen::<f64>()).ln() * self.cost_scale,
+            CostDistribution::Integer => rng.gen_range(0..=self.cost_scale as u64) as f64,
+        };
+        Cost::new(cost).unwrap()
+    }
+}
+
+// A random egraph that has a loop-free extraction.
+pub fn generate(config: &RandomEgraphConfig, seed: u64) -> EGraph {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let core_node_count = rng.gen_range(config.core_nodes.clone());
+    let extra_node_count = rng.gen_range(config.extra_nodes.clone());
+    let mut nodes: Vec<Node> = Vec::with_capacity(core_node_count + extra_node_count);
+    let mut eclass = 0;
+    // How many core nodes have each class as a child.
+    let mut parent_counts: HashMap<usize, usize> = HashMap::new();
+    let mut node_classes: Vec<usize> = Vec::with_capacity(core_node_count);
+
+    let id2nid = |id: usize| -> NodeId { format!("node_{}", id).into() };
+
+    for i in 0..core_node_count {
+        let mut children: Vec<NodeId> = vec![];
+        for j in 0..i {
+            let count = parent_counts.entry(node_classes[j]).or_default();
+            if *count < config.max_parents_per_class && rng.gen_bool(config.child_probability) {
+                *count += 1;
+                children.push(id2nid(j));
+            }
+        }
+
+        if rng.gen_bool(config.new_class_probability) {
+            eclass += 1;
+        }
+        node_classes.push(eclass);
+
+        let cost = config.random_cost(&mut rng, &nodes);
+        nodes.push(Node {
+            op: "operation".to_string(),
+            children,
+            eclass: eclass.to_string().into(),
+            cost,
+        });
+    }
+
+    // So far we have the nodes for a feasible egraph. Now we add some
+    // cycles to extra nodes - nodes that aren't required in the extraction.
+    for _ in 0..extra_node_count {
+        let cost = config.random_cost(&mut rng, &nodes);
+        nodes.push(Node {
+            op: "operation".to_string(),
+            children: vec![],
+            eclass: rng.gen_range(0..eclass * 2 + 1).to_string().into(),
+            cost,
+        });
+    }
+
+    let node_count = nodes.len();
+    for node in &mut nodes[core_node_count..] {
+        for j in 0..node_count {
+            if rng.gen_bool(config.cycle_probability) {
+                node.children.push(id2nid(j));
+            }
+        }
+    }
+
+    let mut egraph = EGraph::default();
+
+    for (i, node) in nodes.iter().enumerate() {
+        egraph.add_node(id2nid(i), node.clone());
+    }
+
+    // Set roots
+    for _ in 1..rng.gen_range(2..6) {
+        egraph
+            .root_eclasses
+            .push(nodes[rng.gen_range(0..core_node_count)].eclass.clone());
+    }
+
+    egraph
+}
+
+pub fn gen_random_command(mut args: pico_args::Arguments) {
+    let seed: u64 = args.opt_value_from_str("--seed").unwrap().unwrap_or(0);
+    let out_filename: Option<std::path::PathBuf> = args.opt_value_from_str("--out").unwrap();
+
+    // Exact counts, rather than the default ranges.
+    let mut config = RandomEgraphConfig::default();
+    if let Some(nodes) = args.opt_value_from_str::<_, usize>("--nodes").unwrap() {
+        assert!(nodes > 0, "--nodes must be at least one");
+        config.core_nodes = nodes..nodes + 1;
+    }
+    if let Some(extra) = args
+        .opt_value_from_str::<_, usize>("--extra-nodes")
+        .unwrap()
+    {
+        config.extra_nodes = extra..extra + 1;
+    }
+
+    let probability = |args: &mut pico_args::Arguments, flag: &'static str, default: f64| {
+        let p: f64 = args.opt_value_from_str(flag).unwrap().unwrap_or(default);
+        assert!((0.0..=1.0).contains(&p), "{flag} must be between 0 and 1");
+        p
+    };
+    config.new_class_probability = probability(
+        &mut args,
+        "--new-class-probability",
+        config.new_class_probability,
+    );
+    config.child_probability =
+        probability(&mut args, "--child-probability", config.child_probability);
+    config.cycle_probability =
+        probability(&mut args, "--cycle-probability", config.cycle_probability);
+    config.zero_cost_probability = probability(
+        &mut args,
+        "--zero-cost-probability",
+        config.zero_cost_probability,
+    );
+    config.repeat_cost_probability = probability(
+        &mut args,
+        "--repeat-cost-probability",
+        config.repeat_cost_probability,
+    );
+    if let Some(max) = args.opt_value_from_str("--max-parents").unwrap() {
+        config.max_parents_per_class = max;
+    }
+    if let Some(costs) = args.opt_value_from_str("--costs").unwrap() {
+        config.costs = costs;
+    }
+    if let Some(scale) = args.opt_value_from_str::<_, f64>("--cost-scale").unwrap() {
+        assert!(
+            scale >= 0.0 && scale.is_finite(),
+            "--cost-scale must be finite and not negative"
+        );
+        config.cost_scale = scale;
+    }
+
+    let rest = args.finish();
+    if !rest.is_empty() {
+        panic!("Unknown arguments: {:?}", rest);
+    }
+
+    let egraph = generate(&config, seed);
+    let json = serde_json::to_string_pretty(&egraph).unwrap();
+    match out_filename {
+        Some(path) => std::fs::write(&path, json + "\n")
+            .with_context(|| format!("Failed to write {}", path.display()))
+            .unwrap(),
+        None => println!("{json}"),
+    }
+}
@@ -0,0 +1,402 @@
+/*
+The JSON formats that other tools exchange with extraction-gym.
+
+Input egraphs are what egraph-serialize writes: `nodes` (op, children, eclass, cost),
+`root_eclasses`, and optionally `class_data` (the class's `type`), a `comment`, and the
+schema `version`. In strict mode, the default, any other field is an error, as is a
+version newer than this one, so typos and format drift are caught rather than ignored.
+`--lenient` skips these checks for older or hand-written files. In either mode,
+repeated roots are dropped, nodes that depend on a class without nodes are removed,
+and a root class without nodes is an error. A .bin file is the binary conversion of
+such an egraph, see `convert`.
+
+Each extraction writes one `ResultRecord`, and each `--batch` run one `BatchReport`.
+A result's `status` says whether its costs are the extractor's own, and its `extra`
+has the `--extra key=value` labels of the run, such as the machine or commit, which
+scripts can group by without a schema change.
+`--list-extractors-json` prints an `ExtractorListing` for each extractor.
+*/
+
+use crate::*;
+use serde::{Deserialize, Serialize};
+
+pub const SCHEMA_VERSION: u32 = 1;
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+#[allow(dead_code)] // only parsed to check the format
+struct StrictEGraph {
+    #[serde(default)]
+    version: Option<u32>,
+    #[serde(default)]
+    comment: Option<String>,
+    nodes: IndexMap<String, StrictNode>,
+    root_eclasses: Vec<String>,
+    #[serde(default)]
+    class_data: IndexMap<String, StrictClassData>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+#[allow(dead_code)]
+struct StrictNode {
+    op: String,
+    children: Vec<String>,
+    eclass: String,
+    cost: f64,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+#[allow(dead_code)]
+struct StrictClassData {
+    #[serde(rename = "type")]
+    typ: Option<String>,
+}
+
+pub fn parse_egraph(json: &str, lenient: bool) -> anyhow::Result<EGraph> {
+    if !lenient {
+        let strict: StrictEGraph = serde_json::from_str(json)
+            .context("Not a valid egraph in strict mode, --lenient skips this check")?;
+        if let Some(version) = strict.version {
+            anyhow::ensure!(
+                version <= SCHEMA_VERSION,
+                "Schema version {version} is newer than the supported version {SCHEMA_VERSION}"
+            );
+        }
+    }
+    let mut egraph: EGraph = serde_json::from_str(json)?;
+    remove_dead_nodes(&mut egraph);
+    normalize_roots(&mut egraph)?;
+    Ok(egraph)
+}
+
+// A child that isn't one of the egraph's nodes, as when all of a class's nodes were
+// filtered out before it was serialized, is taken to be in a class without nodes.
+// Such a class can't be extracted, and so neither can any node with it as a child,
+// nor any node whose child's class then has no nodes left, and so on. These dead
+// nodes are removed, see `canonicalize::rebuild_with`, so that extractors only see
+// egraphs where every child's class has a node. Returns how many were removed.
+pub fn remove_dead_nodes(egraph: &mut EGraph) -> usize {
+    let dangling = |node: &Node| node.children.iter().any(|c| !egraph.nodes.contains_key(c));
+    if !egraph.nodes.values().any(dangling) {
+        return 0;
+    }
+    let kept = egraph
+        .nodes
+        .iter()
+        .filter(|(_, node)| !dangling(node))
+        .map(|(nid, _)| nid.clone())
+        .collect();
+    let mut rebuilt = extract::canonicalize::rebuild_with(egraph, kept);
+    rebuilt.class_data = std::mem::take(&mut egraph.class_data);
+    let removed = egraph.nodes.len() - rebuilt.nodes.len();
+    log::warn!("Removed {removed} nodes that depend on classes without nodes");
+    *egraph = rebuilt;
+    removed
+}
+
+// Removes repeated roots, keeping the first of each, so that no extractor counts a
+// root's cost twice. A root class without nodes can't be extracted, so is an error.
+pub fn normalize_roots(egraph: &mut EGraph) -> anyhow::Result<()> {
+    let roots: indexmap::IndexSet<ClassId> = egraph.root_eclasses.drain(..).collect();
+    for root in &roots {
+        anyhow::ensure!(
+            egraph.classes().contains_key(root),
+            "Root class {root} has no nodes"
+        );
+    }
+    egraph.root_eclasses = roots.into_iter().collect();
+    Ok(())
+}
+
+// A .bin file is read as the egraph it was converted from, see `convert`.
+pub fn read_egraph(path: &str, lenient: bool) -> anyhow::Result<EGraph> {
+    if path.ends_with(".bin") {
+        return convert::read_binary(std::path::Path::new(path));
+    }
+    let json = std::fs::read_to_string(path)?;
+    parse_egraph(&json, lenient)
+}
+
+#[derive(Serialize)]
+pub struct ResultRecord {
+    pub version: u32,
+    pub name: String,
+    pub extractor: String,
+    pub status: RunStatus,
+    pub tree: f64,
+    pub dag: f64,
+    // The critical path, see `ExtractionResult::depth_cost`.
+    pub depth: f64,
+    // Only set with `--cost-model`.
+    pub cost_model: Option<CostModelRecord>,
+    // Only set with `--also-eval`, the costs of the same extraction under each of
+    // those models, in the order given.
+    pub also_eval: Vec<ModelCostRecord>,
+    // Only set with `--lower-bound`, see `ilp_cbc::lp_lower_bound`. The gap is how much
+    // more than the lower bound the dag cost is, as a fraction of the dag cost.
+    pub lower_bound: Option<f64>,
+    pub gap: Option<f64>,
+    // Only set with `--policy`, the exclusive groups the extraction used more than one
+    // op of, see `op_policy`.
+    pub policy_violations: Option<usize>,
+    // Only set with `--cse-cost`, see `ExtractionResult::cse_dag_cost`.
+    pub cse_dag: Option<f64>,
+    pub micros: u64,
+    // The peak resident set size of the first run, if the platform reports it, see
+    // `memory_limit`.
+    pub peak_bytes: Option<u64>,
+    // Reading and checking the input, which no extractor's time includes.
+    pub parse_micros: u64,
+    // Only set with `--repeat`, in which case `micros` is the median.
+    pub timing: Option<Timing>,
+    // The most threads the extraction could use.
+    pub threads: usize,
+    pub memory_limit_exceeded: bool,
+    // Set if `--timeout` passed and the result is bottom-up's.
+    pub timed_out: bool,
+    pub pruned_choices: usize,
+    pub repaired_classes: usize,
+    // The cost of each root, see `ExtractionResult::root_costs`. Empty, as are the
+    // expensive classes, with `--low-memory`.
+    pub root_costs: Vec<RootCostRecord>,
+    // The most expensive classes in the dag, most expensive first.
+    pub expensive_classes: Vec<ClassCost>,
+    // Only set when the roots were extracted separately.
+    pub root_micros: Vec<RootMicros>,
+    // Only set with `--restarts`, one per seed.
+    pub restarts: Vec<RestartRecord>,
+    // Only set by the ILP extractors.
+    pub solver_stats: Option<SolverStats>,
+    pub extra: IndexMap<String, String>,
+}
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum RunStatus {
+    Ok,
+    // The extractor ran out of `--timeout`, so the result is bottom-up's.
+    Timeout,
+    // The extractor went over `--soft-memory-limit-mb` and fell back to a cheaper
+    // approach, see `memory_limit`.
+    Fallback,
+}
+
+impl RunStatus {
+    pub fn of(timed_out: bool, memory_limit_exceeded: bool) -> Self {
+        if timed_out {
+            RunStatus::Timeout
+        } else if memory_limit_exceeded {
+            RunStatus::Fallback
+        } else {
+            RunStatus::Ok
+        }
+    }
+}
+
+// See `CostModel`.
+#[derive(Serialize)]
+pub struct CostModelRecord {
+    pub version: Option<String>,
+    pub hash: String,
+}
+
+#[derive(Serialize)]
+pub struct ModelCostRecord {
+    pub path: String,
+    pub model: CostModelRecord,
+    pub tree: f64,
+    pub dag: f64,
+}
+
+#[derive(Serialize)]
+pub struct RootCostRecord {
+    pub root: String,
+    pub tree: f64,
+    pub dag: f64,
+}
+
+#[derive(Serialize)]
+pub struct ClassCost {
+    pub class: String,
+    pub cost: f64,
+}
+
+#[derive(Serialize)]
+pub struct RestartRecord {
+    pub seed: u64,
+    // Not set if the result of this seed wasn't valid.
+    pub dag: Option<f64>,
+    pub micros: u64,
+}
+
+#[derive(Serialize)]
+pub struct RootMicros {
+    pub root: String,
+    pub micros: u64,
+}
+
+// The runtimes of repeated extractions, in microseconds. The first run is timed
+// separately as `cold`, since it pays for page faults and first allocations that
+// later runs don't. Then come `warmup_runs` untimed runs, and the `runs` timed ones
+// that the statistics are over.
+#[derive(Serialize)]
+pub struct Timing {
+    pub cold: u64,
+    pub warmup_runs: usize,
+    pub runs: usize,
+    pub min: u64,
+    pub median: u64,
+    pub stddev: f64,
+}
+
+impl Timing {
+    pub fn from_micros(cold: u128, warmup_runs: usize, mut runs: Vec<u128>) -> Self {
+        assert!(!runs.is_empty());
+        runs.sort();
+        let n = runs.len() as f64;
+        let mean = runs.iter().map(|&us| us as f64).sum::<f64>() / n;
+        let variance = runs
+            .iter()
+            .map(|&us| (us as f64 - mean).powi(2))
+            .sum::<f64>()
+            / n;
+        Self {
+            cold: cold as u64,
+            warmup_runs,
+            runs: runs.len(),
+            min: runs[0] as u64,
+            median: runs[runs.len() / 2] as u64,
+            stddev: variance.sqrt(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct BatchReport {
+    pub version: u32,
+    pub extractor: String,
+    pub threads: usize,
+    // How many of the results are copies of an earlier one, see `batch`.
+    pub duplicates: usize,
+    // One per egraph, in order of their paths.
+    pub results: Vec<BatchRecord>,
+}
+
+#[derive(Serialize, Clone)]
+pub struct BatchRecord {
+    pub name: String,
+    // See `corpus`.
+    pub suite: String,
+    // The costs and time are only set if the extraction succeeded, otherwise the error is.
+    pub tree: Option<f64>,
+    pub dag: Option<f64>,
+    pub micros: Option<u64>,
+    pub memory_limit_exceeded: bool,
+    pub error: Option<String>,
+    // See `corpus::egraph_hash`. Only unset if the egraph couldn't be read.
+    pub hash: Option<String>,
+    // The earlier egraph with the same hash, whose results these are a copy of.
+    pub duplicate_of: Option<String>,
+}
+
+// Written by `pareto-front`, see `pareto`.
+#[derive(Serialize)]
+pub struct ParetoFront {
+    pub version: u32,
+    pub name: String,
+    // Cheapest first.
+    pub points: Vec<ParetoPointRecord>,
+}
+
+#[derive(Serialize)]
+pub struct ParetoPointRecord {
+    pub dag: f64,
+    pub depth: f64,
+    pub choices: IndexMap<String, String>,
+}
+
+// Printed by `--list-extractors-json`, one per extractor in `extractors()`, each of
+// which `--extractor` takes, see `extractor_named`.
+#[derive(Serialize)]
+pub struct ExtractorListing {
+    pub name: String,
+    pub description: String,
+    // "tree", "dag" or "neither", see `Optimal`.
+    pub optimal: String,
+    pub use_for_bench: bool,
+    pub supports_cycles: bool,
+    pub deterministic: bool,
+    // The cargo feature the extractor was compiled in with, if any.
+    pub feature: Option<String>,
+    // The command line options only this extractor takes.
+    pub options: Vec<OptionListing>,
+}
+
+#[derive(Serialize)]
+pub struct OptionListing {
+    pub flag: String,
+    pub values: Vec<String>,
+    pub default: String,
+}
+
+impl ExtractorListing {
+    pub fn all() -> Vec<Self> {
+        extractors()
+            .into_iter()
+            .map(|(name, ed)| {
+                let options = match name {
+                    "faster-greedy-dag" => vec![OptionListing {
+                        flag: "--node-order".into(),
+                        values: extract::faster_greedy_dag::NodeOrder::ALL
+                            .iter()
+                            .map(|order| order.name().to_string())
+                            .collect(),
+                        default: extract::faster_greedy_dag::NodeOrder::Fifo
+                            .name()
+                            .to_string(),
+                    }],
+                    "discounted-bottom-up" => vec![OptionListing {
+                        flag: "--gamma".into(),
+                        values: vec!["above 0, at most 1".into()],
+                        default: "0.5".into(),
+                    }],
+                    "global-greedy-dag" => vec![
+                        OptionListing {
+                            flag: "--max-iterations".into(),
+                            values: vec!["at least 1".into()],
+                            default: "none".into(),
+                        },
+                        OptionListing {
+                            flag: "--target-cost".into(),
+                            values: vec!["a cost".into()],
+                            default: "none".into(),
+                        },
+                        OptionListing {
+                            flag: "--time-budget".into(),
+                            values: vec!["seconds".into()],
+                            default: "none".into(),
+                        },
+                    ],
+                    _ => vec![],
+                };
+                ExtractorListing {
+                    name: name.to_string(),
+                    description: ed.description.to_string(),
+                    optimal: match ed.optimal {
+                        Optimal::Tree => "tree",
+                        Optimal::DAG => "dag",
+                        Optimal::Neither => "neither",
+                    }
+                    .to_string(),
+                    use_for_bench: ed.use_for_bench,
+                    supports_cycles: ed.supports_cycles,
+                    deterministic: ed.deterministic,
+                    feature: ed.feature.map(str::to_string),
+                    options,
+                }
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,71 @@
+/*
+Runs the greedy dag extractor with each node order on a set of egraphs, to see how
+much the order nodes are processed in changes the result.
+
+Prints the dag cost and runtime of each order on each egraph, then, for each suite
+(the directory the egraph is in), the geometric mean of each order's dag cost
+relative to first in first out.
+
+Run with `extraction-gym sweep-node-orders <egraph.json>...`.
+*/
+
+use crate::extract::faster_greedy_dag::{NodeOrder, OrderedGreedyDagExtractor};
+use crate::*;
+use anyhow::Context;
+
+pub fn sweep_node_orders_command(mut args: pico_args::Arguments) {
+    let lenient = args.contains("--lenient");
+
+    let filenames: Vec<String> = args
+        .finish()
+        .into_iter()
+        .map(|arg| arg.into_string().unwrap())
+        .collect();
+    if filenames.is_empty() {
+        panic!("No egraphs given");
+    }
+
+    // suite -> order -> log of the dag cost relative to fifo, per egraph.
+    let mut suites: IndexMap<String, IndexMap<&str, Vec<f64>>> = IndexMap::new();
+
+    println!("suite\tname\torder\tdag\tmicros");
+    for filename in &filenames {
+        let egraph = schema::read_egraph(filename, lenient)
+            .with_context(|| format!("Failed to parse {filename}"))
+            .unwrap();
+        let suite = std::path::Path::new(filename)
+            .parent()
+            .and_then(|p| p.file_name())
+            .map_or_else(|| ".".into(), |s| s.to_string_lossy().into_owned());
+
+        let mut fifo_dag = None;
+        for order in NodeOrder::ALL {
+            let start_time = std::time::Instant::now();
+            let result =
+                OrderedGreedyDagExtractor { order }.extract(&egraph, &egraph.root_eclasses);
+            let us = start_time.elapsed().as_micros();
+            result.check(&egraph);
+            let dag = result.dag_cost(&egraph, &egraph.root_eclasses);
+            println!("{suite}\t{filename}\t{}\t{dag}\t{us}", order.name());
+
+            let fifo_dag = *fifo_dag.get_or_insert(dag);
+            if fifo_dag.into_inner() > 0.0 {
+                suites
+                    .entry(suite.clone())
+                    .or_default()
+                    .entry(order.name())
+                    .or_default()
+                    .push((dag.into_inner() / fifo_dag.into_inner()).ln());
+            }
+        }
+    }
+
+    println!();
+    println!("suite\torder\tdag relative to fifo (geometric mean)");
+    for (suite, orders) in &suites {
+        for (order, logs) in orders {
+            let mean = (logs.iter().sum::<f64>() / logs.len() as f64).exp();
+            println!("{suite}\t{order}\t{mean:.4}");
+        }
+    }
+}
@@ -1,6 +1,7 @@
-use crate::{extractors, Extractor, Optimal, EPSILON_ALLOWANCE};
+use crate::op_filter::OpFilter;
+use crate::{extractors, CostCache, ExtractionResult, Extractor, Optimal, EPSILON_ALLOWANCE};
 pub type Cost = NotNan<f64>;
-use egraph_serialize::{EGraph, Node, NodeId};
+use egraph_serialize::{ClassId, EGraph, Node, NodeId};
 use ordered_float::NotNan;
 use rand::Rng;
 
@@ -24,81 +25,128 @@ fn generate_random_not_nan() -> NotNan<f64> {
     NotNan::new(random_float).unwrap()
 }
 
+pub use crate::random::RandomEgraphConfig;
+
 //make a random egraph that has a loop-free extraction.
 pub fn generate_random_egraph() -> EGraph {
-    let mut rng = rand::thread_rng();
-    let core_node_count = rng.gen_range(1..100) as usize;
-    let extra_node_count = rng.gen_range(1..100);
-    let mut nodes: Vec<Node> = Vec::with_capacity(core_node_count + extra_node_count);
-    let mut eclass = 0;
+    generate_random_egraph_with(&RandomEgraphConfig::default())
+}
 
-    let id2nid = |id: usize| -> NodeId { format!("node_{}", id).into() };
+pub fn generate_random_egraph_with(config: &RandomEgraphConfig) -> EGraph {
+    crate::random::generate(config, rand::thread_rng().gen())
+}
 
-    // Unless we do it explicitly, the costs are almost never equal to others' costs or zero:
-    let get_semi_random_cost = |nodes: &Vec<Node>| -> Cost {
-        let mut rng = rand::thread_rng();
+// Adds a node to a hand-built egraph, with its id as its op.
+pub fn node(egraph: &mut EGraph, id: &str, class: &str, children: &[&str], cost: f64) {
+    node_with_op(egraph, id, id, class, children, cost);
+}
 
-        if nodes.len() > 0 && rng.gen_bool(0.1) {
-            return nodes[rng.gen_range(0..nodes.len())].cost;
-        } else if rng.gen_bool(0.05) {
-            return Cost::default();
-        } else {
-            return generate_random_not_nan() * 100.0;
-        }
-    };
+pub fn node_with_op(
+    egraph: &mut EGraph,
+    id: &str,
+    op: &str,
+    class: &str,
+    children: &[&str],
+    cost: f64,
+) {
+    egraph.add_node(
+        id.to_string().into(),
+        Node {
+            op: op.to_string(),
+            children: children.iter().map(|c| c.to_string().into()).collect(),
+            eclass: class.to_string().into(),
+            cost: Cost::new(cost).unwrap(),
+        },
+    );
+}
 
-    for i in 0..core_node_count {
-        let children: Vec<NodeId> = (0..i).filter(|_| rng.gen_bool(0.1)).map(id2nid).collect();
+/// An egraph along with the dag cost of its optimal extraction.
+pub struct PlantedEgraph {
+    pub egraph: EGraph,
+    pub optimal_dag_cost: Cost,
+}
 
-        if rng.gen_bool(0.2) {
-            eclass += 1;
-        }
+// Makes a random egraph with a known optimal extraction. The optimum is built first:
+// one node per class, with children only in earlier classes. Then distractor nodes and
+// classes are added, with each distractor node costing more than all the planted nodes
+// together, so any extraction that selects a distractor is strictly worse.
+pub fn generate_planted_egraph() -> PlantedEgraph {
+    let mut rng = rand::thread_rng();
+    let planted_class_count = rng.gen_range(1..50);
+    let distractor_class_count = rng.gen_range(0..20);
+    let distractor_node_count = rng.gen_range(1..100);
 
-        nodes.push(Node {
-            op: "operation".to_string(),
-            children: children,
-            eclass: eclass.to_string().clone().into(),
-            cost: get_semi_random_cost(&nodes),
-        });
-    }
+    let id2nid = |id: usize| -> NodeId { format!("node_{}", id).into() };
+    let planted_cid = |id: usize| -> ClassId { format!("p{}", id).into() };
+    let distractor_cid = |id: usize| -> ClassId { format!("d{}", id).into() };
+
+    let mut egraph = EGraph::default();
+    let mut planted = ExtractionResult::default();
+    let mut next_node = 0;
 
-    // So far we have the nodes for a feasible egraph. Now we add some
-    // cycles to extra nodes - nodes that aren't required in the extraction.
-    for _ in 0..extra_node_count {
-        nodes.push(Node {
-            op: "operation".to_string(),
-            children: vec![],
-            eclass: rng.gen_range(0..eclass * 2 + 1).to_string().clone().into(),
-            cost: get_semi_random_cost(&nodes),
-        });
+    for i in 0..planted_class_count {
+        let children: Vec<NodeId> = (0..i).filter(|_| rng.gen_bool(0.2)).map(id2nid).collect();
+        let node_id = id2nid(next_node);
+        next_node += 1;
+        egraph.add_node(
+            node_id.clone(),
+            Node {
+                op: "planted".to_string(),
+                children,
+                eclass: planted_cid(i),
+                cost: generate_random_not_nan() * 100.0,
+            },
+        );
+        planted.choose(planted_cid(i), node_id);
     }
 
-    for i in core_node_count..nodes.len() {
-        for j in 0..nodes.len() {
-            if rng.gen_bool(0.05) {
-                nodes.get_mut(i).unwrap().children.push(id2nid(j));
+    let planted_total: Cost = egraph.nodes.values().map(|n| n.cost).sum();
+
+    // Every distractor class gets at least one node.
+    let all_classes = planted_class_count + distractor_class_count;
+    for i in 0..distractor_class_count.max(distractor_node_count) {
+        let eclass = if i < distractor_class_count {
+            distractor_cid(i)
+        } else {
+            let c = rng.gen_range(0..all_classes);
+            if c < planted_class_count {
+                planted_cid(c)
+            } else {
+                distractor_cid(c - planted_class_count)
             }
-        }
-    }
+        };
 
-    let mut egraph = EGraph::default();
+        // Children may be any node added so far, so distractors can form cycles.
+        let children: Vec<NodeId> = (0..next_node)
+            .filter(|_| rng.gen_bool(0.05))
+            .map(id2nid)
+            .collect();
 
-    for i in 0..nodes.len() {
-        egraph.add_node(id2nid(i), nodes[i].clone());
+        egraph.add_node(
+            id2nid(next_node),
+            Node {
+                op: "distractor".to_string(),
+                children,
+                eclass,
+                cost: planted_total + 1.0 + generate_random_not_nan() * 100.0,
+            },
+        );
+        next_node += 1;
     }
 
-    // Set roots
-    for _ in 1..rng.gen_range(2..6) {
-        egraph.root_eclasses.push(
-            nodes
-                .get(rng.gen_range(0..core_node_count))
-                .unwrap()
-                .eclass
-                .clone(),
-        );
+    for _ in 0..rng.gen_range(1..5) {
+        egraph
+            .root_eclasses
+            .push(planted_cid(rng.gen_range(0..planted_class_count)));
     }
+    egraph.root_eclasses.sort();
+    egraph.root_eclasses.dedup();
 
-    egraph
+    let optimal_dag_cost = planted.dag_cost(&egraph, &egraph.root_eclasses);
+    PlantedEgraph {
+        egraph,
+        optimal_dag_cost,
+    }
 }
 
 /*
@@ -106,24 +154,48 @@ pub fn generate_random_egraph() -> EGraph {
  * Checks that the extractions are valid.
  */
 
-fn check_optimal_results<I: Iterator<Item = EGraph>>(egraphs: I) {
-    let mut optimal_dag: Vec<Box<dyn Extractor>> = Default::default();
-    let mut optimal_tree: Vec<Box<dyn Extractor>> = Default::default();
-    let mut others: Vec<Box<dyn Extractor>> = Default::default();
+fn check_optimal_results<I>(egraphs: I)
+where
+    I: IntoIterator,
+    I::Item: std::borrow::Borrow<EGraph>,
+{
+    let mut optimal_dag: Vec<(Box<dyn Extractor>, bool)> = Default::default();
+    let mut optimal_tree: Vec<(Box<dyn Extractor>, bool)> = Default::default();
+    let mut others: Vec<(Box<dyn Extractor>, bool)> = Default::default();
 
     for (_, ed) in extractors().into_iter() {
+        let e = (ed.extractor, ed.supports_cycles);
         match ed.optimal {
-            Optimal::DAG => optimal_dag.push(ed.extractor),
-            Optimal::Tree => optimal_tree.push(ed.extractor),
-            Optimal::Neither => others.push(ed.extractor),
+            Optimal::DAG => optimal_dag.push(e),
+            Optimal::Tree => optimal_tree.push(e),
+            Optimal::Neither => others.push(e),
         }
     }
 
     for egraph in egraphs {
+        let egraph: &EGraph = std::borrow::Borrow::borrow(&egraph);
+        // Extractors that don't support cycles are skipped on egraphs that have them.
+        let cyclic = crate::canonicalize::has_cycles(egraph);
+        fn runnable(
+            extractors: &[(Box<dyn Extractor>, bool)],
+            cyclic: bool,
+        ) -> Vec<&dyn Extractor> {
+            extractors
+                .iter()
+                .filter(|(_, supports_cycles)| *supports_cycles || !cyclic)
+                .map(|(e, _)| &**e)
+                .collect()
+        }
+        let optimal_dag = runnable(&optimal_dag, cyclic);
+        let optimal_tree = runnable(&optimal_tree, cyclic);
+        let others = runnable(&others, cyclic);
+
+        // Shared between all the extractors run on this egraph.
+        let cache = CostCache::new(&egraph);
         let mut optimal_dag_cost: Option<Cost> = None;
 
         for e in &optimal_dag {
-            let extract = e.extract(&egraph, &egraph.root_eclasses);
+            let extract = e.extract_with_cache(&egraph, &egraph.root_eclasses, &cache);
             extract.check(&egraph);
             let dag_cost = extract.dag_cost(&egraph, &egraph.root_eclasses);
             let tree_cost = extract.tree_cost(&egraph, &egraph.root_eclasses);
@@ -145,7 +217,7 @@ fn check_optimal_results<I: Iterator<Item = EGraph>>(egraphs: I) {
         let mut optimal_tree_cost: Option<Cost> = None;
 
         for e in &optimal_tree {
-            let extract = e.extract(&egraph, &egraph.root_eclasses);
+            let extract = e.extract_with_cache(&egraph, &egraph.root_eclasses, &cache);
             extract.check(&egraph);
             let tree_cost = extract.tree_cost(&egraph, &egraph.root_eclasses);
             if optimal_tree_cost.is_none() {
@@ -164,7 +236,7 @@ fn check_optimal_results<I: Iterator<Item = EGraph>>(egraphs: I) {
         }
 
         for e in &others {
-            let extract = e.extract(&egraph, &egraph.root_eclasses);
+            let extract = e.extract_with_cache(&egraph, &egraph.root_eclasses, &cache);
             extract.check(&egraph);
             let tree_cost = extract.tree_cost(&egraph, &egraph.root_eclasses);
             let dag_cost = extract.dag_cost(&egraph, &egraph.root_eclasses);
@@ -182,21 +254,16 @@ fn check_optimal_results<I: Iterator<Item = EGraph>>(egraphs: I) {
     }
 }
 
+// All the .json test files
+fn test_data_egraphs() -> impl Iterator<Item = EGraph> {
+    let corpus = crate::corpus::Corpus::discover(["./test_data/"]);
+    corpus.egraphs(true).collect::<Vec<_>>().into_iter()
+}
+
 // Run on all the .json test files
 #[test]
 fn run_on_test_egraphs() {
-    use walkdir::WalkDir;
-
-    let egraphs = WalkDir::new("./test_data/")
-        .into_iter()
-        .filter_map(Result::ok)
-        .filter(|e| {
-            e.file_type().is_file()
-                && e.path().extension().and_then(std::ffi::OsStr::to_str) == Some("json")
-        })
-        .map(|e| e.path().to_string_lossy().into_owned())
-        .map(|e| EGraph::from_json_file(e).unwrap());
-    check_optimal_results(egraphs);
+    check_optimal_results(test_data_egraphs());
 }
 
 #[test]
@@ -205,6 +272,104 @@ fn check_assert_enabled() {
     assert!(false);
 }
 
+thread_local! {
+    // Set while shrinking, when the panics are expected.
+    static QUIET_PANICS: std::cell::Cell<bool> = const { std::cell::Cell::new(false) };
+}
+
+// Whether `check_optimal_results` fails on `egraph`, without printing the panic.
+fn fails_optimal_check(egraph: &EGraph) -> bool {
+    // One hook for the whole process, so tests on other threads keep their messages.
+    static QUIET_HOOK: std::sync::Once = std::sync::Once::new();
+    QUIET_HOOK.call_once(|| {
+        let hook = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |info| {
+            if !QUIET_PANICS.with(|quiet| quiet.get()) {
+                hook(info);
+            }
+        }));
+    });
+    QUIET_PANICS.with(|quiet| quiet.set(true));
+    let failed = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        check_optimal_results([egraph])
+    }))
+    .is_err();
+    QUIET_PANICS.with(|quiet| quiet.set(false));
+    failed
+}
+
+// Removes roots and nodes from `egraph` while it still fails, one at a time, until
+// removing any one more would make it pass. Removing a node also removes the nodes
+// that depend on a class it leaves empty, and the egraph must stay extractable.
+pub fn shrink_egraph(egraph: EGraph, fails: impl Fn(&EGraph) -> bool) -> EGraph {
+    use crate::canonicalize::{extractable_classes, rebuild_with};
+
+    let mut current = egraph;
+    let mut shrunk = true;
+    while shrunk {
+        shrunk = false;
+
+        for i in 0..current.root_eclasses.len() {
+            if current.root_eclasses.len() > 1 {
+                let mut candidate = current.clone();
+                candidate.root_eclasses.remove(i);
+                if fails(&candidate) {
+                    current = candidate;
+                    shrunk = true;
+                    break;
+                }
+            }
+        }
+
+        let node_ids: Vec<NodeId> = current.nodes.keys().cloned().collect();
+        for node_id in node_ids {
+            let kept = current
+                .nodes
+                .keys()
+                .filter(|n| **n != node_id)
+                .cloned()
+                .collect();
+            let candidate = rebuild_with(&current, kept);
+            let extractable = extractable_classes(&candidate);
+            if candidate
+                .root_eclasses
+                .iter()
+                .all(|r| extractable.contains(r))
+                && fails(&candidate)
+            {
+                current = candidate;
+                shrunk = true;
+                break;
+            }
+        }
+    }
+    current
+}
+
+// Like `check_optimal_results`, but a failing egraph is shrunk and saved under
+// test_data/regressions/, where `run_on_test_egraphs` picks it up from then on.
+// Egraphs are only checked again, quietly, while shrinking.
+fn check_optimal_results_or_shrink<I: Iterator<Item = EGraph>>(name: &str, egraphs: I) {
+    for egraph in egraphs {
+        let checked = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            check_optimal_results([&egraph])
+        }));
+        if let Err(panic) = checked {
+            let shrunk = shrink_egraph(egraph, fails_optimal_check);
+            let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/test_data/regressions");
+            std::fs::create_dir_all(dir).unwrap();
+            let stamp = std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap()
+                .as_millis();
+            let path = format!("{dir}/{name}_{stamp}.json");
+            shrunk.to_json_file(&path).unwrap();
+            eprintln!("Shrunk egraph saved to {path}");
+            std::panic::resume_unwind(panic);
+        }
+    }
+}
+
 macro_rules! create_optimal_check_tests {
     ($($name:ident),*) => {
         $(
@@ -213,10 +378,1836 @@ macro_rules! create_optimal_check_tests {
                 let optimal_dag_found = extractors().into_iter().any(|(_, ed)| ed.optimal == Optimal::DAG);
                 let iterations = if optimal_dag_found { 100 } else { 10000 };
                 let egraphs = (0..iterations).map(|_| generate_random_egraph());
-                check_optimal_results(egraphs);
+                check_optimal_results_or_shrink(stringify!($name), egraphs);
             }
         )*
     }
 }
 
 create_optimal_check_tests!(check0, check1, check2, check3, check4, check5, check6, check7);
+
+// Fewer iterations than the default profile; these exist to exercise the extremes.
+#[test]
+fn check_tree_like() {
+    let config = RandomEgraphConfig::tree_like();
+    check_optimal_results((0..100).map(|_| generate_random_egraph_with(&config)));
+}
+
+#[test]
+fn check_high_sharing() {
+    let config = RandomEgraphConfig::high_sharing();
+    check_optimal_results((0..100).map(|_| generate_random_egraph_with(&config)));
+}
+
+// The DAG-optimal extractors must find the planted optimum exactly, and nothing
+// can beat it. Reports how far the other extractors are from it on average, as the
+// mean of (dag cost - optimum) / optimum, run with --nocapture to see it.
+#[test]
+fn check_planted_optimum() {
+    let planted: Vec<PlantedEgraph> = (0..100).map(|_| generate_planted_egraph()).collect();
+
+    for (name, ed) in extractors() {
+        let mut gaps = vec![];
+        for p in &planted {
+            let roots = &p.egraph.root_eclasses;
+            let extract = ed.extractor.extract(&p.egraph, roots);
+            extract.check(&p.egraph);
+            let dag_cost = extract.dag_cost(&p.egraph, roots);
+
+            assert!(
+                dag_cost + EPSILON_ALLOWANCE >= p.optimal_dag_cost,
+                "{name}: dag cost {dag_cost} is below the planted optimum {}",
+                p.optimal_dag_cost
+            );
+            if ed.optimal == Optimal::DAG {
+                assert!(
+                    (dag_cost - p.optimal_dag_cost).abs() < EPSILON_ALLOWANCE,
+                    "{name}: dag cost {dag_cost} isn't the planted optimum {}",
+                    p.optimal_dag_cost
+                );
+            } else if p.optimal_dag_cost.into_inner() > 0.0 {
+                gaps.push(((dag_cost - p.optimal_dag_cost) / p.optimal_dag_cost).into_inner());
+            }
+        }
+        if !gaps.is_empty() {
+            let mean = gaps.iter().sum::<f64>() / gaps.len() as f64;
+            eprintln!("{name}: mean optimality gap {:.2}%", 100.0 * mean);
+        }
+    }
+}
+
+// With the distractors filtered out, only the planted extraction is left.
+#[test]
+fn op_filter_keeps_planted_optimum() {
+    let filter = OpFilter::from_lists(None, Some("distractor"));
+    for _ in 0..20 {
+        let p = generate_planted_egraph();
+        let filtered = filter.apply(&p.egraph).unwrap();
+        assert!(filtered.nodes.values().all(|n| n.op == "planted"));
+
+        for (_, ed) in extractors() {
+            let extract = ed.extractor.extract(&filtered, &filtered.root_eclasses);
+            extract.check(&filtered);
+            let dag_cost = extract.dag_cost(&filtered, &filtered.root_eclasses);
+            assert!((dag_cost - p.optimal_dag_cost).abs() < EPSILON_ALLOWANCE);
+        }
+    }
+}
+
+#[test]
+fn op_filter_reports_unextractable_root() {
+    let mut egraph = EGraph::default();
+    node_with_op(&mut egraph, "leaf", "x", "leaf_class", &[], 0.0);
+    node_with_op(&mut egraph, "root", "f", "root_class", &["leaf"], 0.0);
+    egraph.root_eclasses.push("root_class".to_string().into());
+
+    let filter = |only, exclude| OpFilter::from_lists(only, exclude).apply(&egraph);
+    assert!(filter(None, Some("f")).is_err());
+    assert!(filter(Some("f"), None).is_err());
+    assert!(filter(Some("f,x"), None).is_ok());
+}
+
+#[test]
+fn lint_costs_finds_zero_cost_cycle_and_ties() {
+    use crate::lint::{lint_costs, CostLint};
+
+    let mut egraph = EGraph::default();
+    node(&mut egraph, "a", "A", &["b"], 0.0);
+    node(&mut egraph, "a_leaf", "A", &[], 1.0);
+    node(&mut egraph, "b", "B", &["a"], 0.0);
+    node(&mut egraph, "c1", "C", &[], 2.0);
+    node(&mut egraph, "c2", "C", &[], 2.0);
+    node(&mut egraph, "d1", "D", &[], 3.0);
+    node(&mut egraph, "d2", "D", &[], 3.0);
+    egraph.root_eclasses.push("A".to_string().into());
+
+    let lints = lint_costs(&egraph);
+    assert!(lints
+        .iter()
+        .any(|l| matches!(l, CostLint::ZeroCostCycle { classes } if classes.len() == 2)));
+    // The tied classes are summarised in one lint, which isn't an error.
+    assert!(lints.iter().any(|l| matches!(
+        l,
+        CostLint::AllTied {
+            classes: 2,
+            nodes: 2,
+            ..
+        }
+    ) && !l.is_error()));
+    assert_eq!(lints.len(), 2);
+}
+
+// Hints can come from a different extractor (or egraph), and mustn't stop the
+// optimal extractors from being optimal.
+#[test]
+fn hints_keep_results_valid() {
+    for _ in 0..50 {
+        let egraph = generate_random_egraph();
+        let roots = &egraph.root_eclasses;
+        let hint = crate::faster_greedy_dag::FasterGreedyDagExtractor.extract(&egraph, roots);
+
+        for (_, ed) in extractors() {
+            let plain = ed.extractor.extract(&egraph, roots);
+            let hinted = ed.extractor.extract_with_hint(&egraph, roots, &hint);
+            hinted.check(&egraph);
+
+            let (plain_cost, hinted_cost) = match ed.optimal {
+                Optimal::Tree => (
+                    plain.tree_cost(&egraph, roots),
+                    hinted.tree_cost(&egraph, roots),
+                ),
+                Optimal::DAG => (
+                    plain.dag_cost(&egraph, roots),
+                    hinted.dag_cost(&egraph, roots),
+                ),
+                Optimal::Neither => continue,
+            };
+            assert!((plain_cost - hinted_cost).abs() < EPSILON_ALLOWANCE);
+        }
+    }
+}
+
+#[test]
+fn extraction_result_json_round_trip() {
+    let egraph = generate_random_egraph();
+    let result = crate::bottom_up::BottomUpExtractor.extract(&egraph, &egraph.root_eclasses);
+
+    let path = std::env::temp_dir().join("extraction_gym_round_trip.json");
+    result.to_json_file(&path).unwrap();
+    let read = ExtractionResult::from_json_file(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    assert_eq!(result.choices, read.choices);
+}
+
+#[test]
+fn cost_models_override_costs() {
+    let mut egraph = EGraph::default();
+    node_with_op(&mut egraph, "a", "const_1", "A", &[], 2.0);
+    node_with_op(&mut egraph, "b", "mul", "B", &[], 2.0);
+    node_with_op(&mut egraph, "c", "mul", "C", &[], 2.0);
+    node_with_op(&mut egraph, "d", "add", "D", &[], 2.0);
+
+    let read = |json: &str| {
+        let path = std::env::temp_dir().join("extraction_gym_cost_model.json");
+        std::fs::write(&path, json).unwrap();
+        let model = crate::CostModel::from_json_file(&path);
+        std::fs::remove_file(&path).unwrap();
+        model
+    };
+    let model = read(
+        r#"{"rules": [
+            {"class": "C", "cost": 0},
+            {"op": "mul", "cost": 4},
+            {"op_regex": "^const_", "scale": 0.5}
+        ]}"#,
+    )
+    .unwrap();
+    let adjusted = model.apply(&egraph);
+    let cost = |nid: &str| adjusted[&NodeId::from(nid.to_string())].cost.into_inner();
+    assert_eq!(cost("a"), 1.0);
+    assert_eq!(cost("b"), 4.0);
+    // The class rule comes first.
+    assert_eq!(cost("c"), 0.0);
+    assert_eq!(cost("d"), 2.0);
+
+    // Results record which model they were extracted under.
+    let versioned = read(r#"{"version": "v2", "rules": [{"op": "mul", "cost": 4}]}"#).unwrap();
+    assert_eq!(versioned.version.as_deref(), Some("v2"));
+    assert_eq!(model.version, None);
+    assert_ne!(versioned.hash, model.hash);
+    let again = read(r#"{"version": "v2", "rules": [{"op": "mul", "cost": 4}]}"#).unwrap();
+    assert_eq!(versioned.hash, again.hash);
+
+    assert!(read(r#"{"rules": [{"op": "mul", "class": "C", "cost": 1}]}"#).is_err());
+    assert!(read(r#"{"rules": [{"op": "mul", "cost": -1}]}"#).is_err());
+    assert!(read(r#"{"rules": [{"op_regex": "(", "cost": 1}]}"#).is_err());
+}
+
+#[test]
+fn extractions_are_costed_under_other_models() {
+    let egraph = generate_random_egraph();
+    let roots = &egraph.root_eclasses;
+    let result = crate::bottom_up::BottomUpExtractor.extract(&egraph, roots);
+
+    let path = std::env::temp_dir().join("extraction_gym_also_eval.json");
+    std::fs::write(&path, r#"{"rules": [{"op_regex": ".*", "scale": 2}]}"#).unwrap();
+    let model = crate::CostModel::from_json_file(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    // The extraction isn't redone, so only its costs change.
+    let (tree, dag) = model.evaluate(&egraph, &result, roots);
+    let doubled = |cost: Cost| cost.into_inner() * 2.0;
+    assert!(
+        (tree.into_inner() - doubled(result.tree_cost(&egraph, roots))).abs() < EPSILON_ALLOWANCE
+    );
+    assert!(
+        (dag.into_inner() - doubled(result.dag_cost(&egraph, roots))).abs() < EPSILON_ALLOWANCE
+    );
+}
+
+#[test]
+fn identical_extractions_write_identical_files() {
+    let egraph = generate_random_egraph();
+    let result = crate::bottom_up::BottomUpExtractor.extract(&egraph, &egraph.root_eclasses);
+    let mut reversed = ExtractionResult::default();
+    for (cid, nid) in result.choices.iter().rev() {
+        reversed.choose(cid.clone(), nid.clone());
+    }
+
+    let write = |result: &ExtractionResult, name: &str| {
+        let path = std::env::temp_dir().join(name);
+        result.to_json_file(&path).unwrap();
+        let bytes = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        bytes
+    };
+    assert_eq!(
+        write(&result, "extraction_gym_forwards.json"),
+        write(&reversed, "extraction_gym_reversed.json")
+    );
+}
+
+#[test]
+fn discounted_bottom_up() {
+    use crate::bottom_up::{BottomUpExtractor, DiscountedBottomUpExtractor};
+
+    for _ in 0..100 {
+        let egraph = generate_random_egraph();
+        let roots = &egraph.root_eclasses;
+        let bottom_up = BottomUpExtractor.extract(&egraph, roots);
+
+        // Without discounting, it's the usual tree cost.
+        let undiscounted = DiscountedBottomUpExtractor::new(1.0)
+            .unwrap()
+            .extract(&egraph, roots);
+        undiscounted.check(&egraph);
+        let tree_cost = bottom_up.tree_cost(&egraph, roots);
+        assert!((undiscounted.tree_cost(&egraph, roots) - tree_cost).abs() < EPSILON_ALLOWANCE);
+        assert!(
+            (bottom_up.discounted_tree_cost(&egraph, roots, 1.0) - tree_cost).abs()
+                < EPSILON_ALLOWANCE
+        );
+
+        let discounted = DiscountedBottomUpExtractor::new(0.5)
+            .unwrap()
+            .extract(&egraph, roots);
+        discounted.check(&egraph);
+        assert!(
+            discounted.discounted_tree_cost(&egraph, roots, 0.5)
+                <= discounted.tree_cost(&egraph, roots) + EPSILON_ALLOWANCE
+        );
+    }
+}
+
+// Out of attempts, top-down gives the faster-greedy-dag extraction instead.
+#[test]
+fn top_down_falls_back_when_out_of_attempts() {
+    use crate::top_down::TopDownGreedyExtractor;
+    for _ in 0..20 {
+        let egraph = generate_random_egraph();
+        let roots = &egraph.root_eclasses;
+        let greedy = crate::faster_greedy_dag::FasterGreedyDagExtractor.extract(&egraph, roots);
+        let result = TopDownGreedyExtractor { max_attempts: 0 }.extract(&egraph, roots);
+        result.check(&egraph);
+        assert_eq!(result.choices, greedy.choices);
+
+        let result = TopDownGreedyExtractor {
+            max_attempts: usize::MAX,
+        }
+        .extract(&egraph, roots);
+        result.check(&egraph);
+    }
+}
+
+// Each limit stops the search after the first pass, before the cheaper node for F
+// can be made from X's.
+#[test]
+fn global_greedy_dag_budgets_stop_the_search() {
+    use crate::global_greedy_dag::{BudgetedGlobalGreedyDagExtractor, GlobalGreedyDagExtractor};
+    use std::time::Duration;
+
+    let mut egraph = EGraph::default();
+    node(&mut egraph, "f2", "F", &["x"], 1.0);
+    node(&mut egraph, "f1", "F", &[], 10.0);
+    node(&mut egraph, "x", "X", &[], 1.0);
+    egraph.root_eclasses = vec!["F".to_string().into()];
+    let f = ClassId::from("F".to_string());
+
+    let unlimited = GlobalGreedyDagExtractor.extract(&egraph, &egraph.root_eclasses);
+    unlimited.check(&egraph);
+    assert_eq!(unlimited.choices[&f], NodeId::from("f2".to_string()));
+
+    let budgets = [
+        BudgetedGlobalGreedyDagExtractor {
+            max_iterations: Some(1),
+            ..Default::default()
+        },
+        BudgetedGlobalGreedyDagExtractor {
+            target_cost: Some(Cost::new(10.0).unwrap()),
+            ..Default::default()
+        },
+        BudgetedGlobalGreedyDagExtractor {
+            time_budget: Some(Duration::ZERO),
+            ..Default::default()
+        },
+    ];
+    for budgeted in budgets {
+        let result = budgeted.extract(&egraph, &egraph.root_eclasses);
+        result.check(&egraph);
+        assert_eq!(result.choices[&f], NodeId::from("f1".to_string()));
+    }
+}
+
+// Nodes with the same op and children in different classes are different terms.
+#[test]
+fn global_greedy_dag_keeps_equal_terms_in_their_classes() {
+    let mut egraph = EGraph::default();
+    node_with_op(&mut egraph, "g1", "g", "G", &[], 1.0);
+    node_with_op(&mut egraph, "g2", "g", "H", &[], 1.0);
+    egraph.root_eclasses = vec!["G".to_string().into(), "H".to_string().into()];
+
+    let result =
+        crate::global_greedy_dag::GlobalGreedyDagExtractor.extract(&egraph, &egraph.root_eclasses);
+    result.check(&egraph);
+    assert_eq!(result.dag_cost(&egraph, &egraph.root_eclasses), 2.0);
+}
+
+// Canonicalization mustn't change the optimal costs.
+#[test]
+fn canonicalize_keeps_optimal_costs() {
+    use crate::canonicalize::canonicalize;
+
+    let egraphs = test_data_egraphs()
+        .chain((0..50).map(|_| generate_random_egraph()))
+        .chain((0..50).map(|_| generate_planted_egraph().egraph));
+
+    for egraph in egraphs {
+        let (canonical, _) = canonicalize(&egraph);
+        let roots = &egraph.root_eclasses;
+        for (_, ed) in extractors() {
+            let cost = |e: &EGraph| {
+                let extract = ed.extractor.extract(e, roots);
+                extract.check(e);
+                // An extraction of the canonical egraph is also one of the original.
+                extract.check(&egraph);
+                match ed.optimal {
+                    Optimal::Tree => Some(extract.tree_cost(e, roots)),
+                    Optimal::DAG => Some(extract.dag_cost(e, roots)),
+                    Optimal::Neither => None,
+                }
+            };
+            if let (Some(before), Some(after)) = (cost(&egraph), cost(&canonical)) {
+                assert!((before - after).abs() < EPSILON_ALLOWANCE);
+            }
+        }
+    }
+}
+
+#[test]
+fn repair_invalid_extractions() {
+    use crate::repair::repair;
+    let mut rng = rand::thread_rng();
+
+    for _ in 0..100 {
+        let egraph = generate_random_egraph();
+        let roots = &egraph.root_eclasses;
+
+        // A valid extraction is left alone.
+        let valid = crate::faster_greedy_dag::FasterGreedyDagExtractor.extract(&egraph, roots);
+        let repaired = repair(&valid, &egraph, roots).unwrap();
+        assert_eq!(repaired.repaired_classes, 0);
+        assert_eq!(
+            repaired.result.dag_cost(&egraph, roots),
+            valid.dag_cost(&egraph, roots)
+        );
+
+        // Random choices usually have cycles and missing classes.
+        let mut random = ExtractionResult::default();
+        for class in egraph.classes().values() {
+            if rng.gen_bool(0.8) {
+                let node = class.nodes[rng.gen_range(0..class.nodes.len())].clone();
+                random.choose(class.id.clone(), node);
+            }
+        }
+        let repaired = repair(&random, &egraph, roots).unwrap();
+        repaired.result.check(&egraph);
+    }
+}
+
+#[test]
+fn dag_cost_breakdown_sums_to_dag_cost() {
+    for _ in 0..100 {
+        let egraph = generate_random_egraph();
+        let roots = &egraph.root_eclasses;
+        let result = crate::faster_greedy_dag::FasterGreedyDagExtractor.extract(&egraph, roots);
+        let breakdown = result.dag_cost_breakdown(&egraph, roots);
+        for (cid, cost) in &breakdown {
+            assert_eq!(*cost, egraph[&result.choices[cid]].cost);
+        }
+        assert_eq!(
+            breakdown.values().sum::<crate::Cost>(),
+            result.dag_cost(&egraph, roots)
+        );
+    }
+}
+
+#[test]
+fn per_root_extraction_is_valid() {
+    use crate::per_root::extract_per_root;
+    for _ in 0..50 {
+        let egraph = generate_random_egraph();
+        let roots = &egraph.root_eclasses;
+        let cache = CostCache::new(&egraph);
+        for (_, ed) in extractors() {
+            let threads = rand::thread_rng().gen_range(1..4);
+            let per_root = extract_per_root(&*ed.extractor, &cache, roots, threads);
+            per_root.result.check(&egraph);
+            assert_eq!(per_root.root_micros.len(), roots.len());
+        }
+    }
+}
+
+#[test]
+fn strict_and_lenient_parsing() {
+    use crate::schema::parse_egraph;
+    let egraph = r#"{
+        "nodes": { "a": { "op": "x", "children": [], "eclass": "c", "cost": 1 } },
+        "root_eclasses": ["c"]
+    }"#;
+    assert!(parse_egraph(egraph, false).is_ok());
+
+    let unknown_field = egraph.replace(r#""cost": 1"#, r#""cost": 1, "weight": 2"#);
+    assert!(parse_egraph(&unknown_field, false).is_err());
+    assert!(parse_egraph(&unknown_field, true).is_ok());
+
+    let newer = egraph.replacen('{', r#"{ "version": 1000,"#, 1);
+    assert!(parse_egraph(&newer, false).is_err());
+    assert!(parse_egraph(&newer, true).is_ok());
+
+    let corpus = crate::corpus::Corpus::discover(["./test_data/"]);
+    assert_eq!(corpus.egraphs(false).count(), corpus.entries.len());
+}
+
+#[test]
+fn node_orders_give_valid_results() {
+    use crate::faster_greedy_dag::{NodeOrder, OrderedGreedyDagExtractor};
+    for _ in 0..100 {
+        let egraph = generate_random_egraph();
+        let roots = &egraph.root_eclasses;
+        let fifo = crate::faster_greedy_dag::FasterGreedyDagExtractor.extract(&egraph, roots);
+        for order in NodeOrder::ALL {
+            let result = OrderedGreedyDagExtractor { order }.extract(&egraph, roots);
+            result.check(&egraph);
+            if order == NodeOrder::Fifo {
+                assert_eq!(result.choices, fifo.choices);
+            }
+        }
+    }
+    assert_eq!("fan-out".parse(), Ok(NodeOrder::FanOut));
+}
+
+// On small egraphs A* finishes, so nothing else should find a cheaper dag.
+#[test]
+fn a_star_is_dag_optimal_on_small_egraphs() {
+    use crate::a_star::AStarExtractor;
+    let config = RandomEgraphConfig {
+        core_nodes: 1..15,
+        extra_nodes: 1..10,
+        ..Default::default()
+    };
+    let a_star = AStarExtractor {
+        max_expansions: usize::MAX,
+    };
+    for _ in 0..100 {
+        let egraph = generate_random_egraph_with(&config);
+        let roots = &egraph.root_eclasses;
+        let result = a_star.extract(&egraph, roots);
+        result.check(&egraph);
+        let dag_cost = result.dag_cost(&egraph, roots);
+        for (_, ed) in extractors() {
+            let other = ed.extractor.extract(&egraph, roots);
+            assert!(dag_cost <= other.dag_cost(&egraph, roots) + EPSILON_ALLOWANCE);
+        }
+    }
+}
+
+#[test]
+fn decomposition_keeps_the_optimal_dag_cost() {
+    use crate::a_star::AStarExtractor;
+    use crate::decompose::DecomposedExtractor;
+    let config = RandomEgraphConfig {
+        core_nodes: 1..15,
+        extra_nodes: 1..10,
+        ..Default::default()
+    };
+    let a_star = AStarExtractor {
+        max_expansions: usize::MAX,
+    };
+    let decomposed = DecomposedExtractor {
+        extractor: &a_star,
+        min_region_classes: 1,
+    };
+    for _ in 0..100 {
+        let egraph = generate_random_egraph_with(&config);
+        let roots = &egraph.root_eclasses;
+        let whole = a_star.extract(&egraph, roots);
+        let split = decomposed.extract(&egraph, roots);
+        split.check(&egraph);
+        let (whole, split) = (
+            whole.dag_cost(&egraph, roots),
+            split.dag_cost(&egraph, roots),
+        );
+        assert!((whole - split).abs() < EPSILON_ALLOWANCE);
+    }
+
+    let corpus = crate::corpus::Corpus::discover(["./test_data/"]);
+    let greedy = crate::faster_greedy_dag::FasterGreedyDagExtractor;
+    for entry in &corpus.entries {
+        let egraph = crate::schema::read_egraph(&entry.path.to_string_lossy(), true).unwrap();
+        let decomposed = DecomposedExtractor {
+            extractor: &greedy,
+            min_region_classes: 1,
+        };
+        decomposed
+            .extract(&egraph, &egraph.root_eclasses)
+            .check(&egraph);
+    }
+}
+
+#[test]
+fn random_egraphs_are_reproducible() {
+    use crate::random::{generate, CostDistribution};
+    let json = |config: &RandomEgraphConfig, seed: u64| {
+        serde_json::to_string(&generate(config, seed)).unwrap()
+    };
+    let config = RandomEgraphConfig::default();
+    assert_eq!(json(&config, 7), json(&config, 7));
+    assert_ne!(json(&config, 7), json(&config, 8));
+
+    // Without the extra nodes' edges, only the loop-free core has children.
+    let acyclic = RandomEgraphConfig {
+        cycle_probability: 0.0,
+        costs: CostDistribution::Integer,
+        cost_scale: 10.0,
+        ..Default::default()
+    };
+    for seed in 0..20 {
+        let egraph = generate(&acyclic, seed);
+        assert!(!crate::canonicalize::has_cycles(&egraph));
+        for node in egraph.nodes.values() {
+            assert!(node.cost.fract() == 0.0 && *node.cost <= 10.0);
+        }
+    }
+}
+
+#[test]
+fn minimize_keeps_costs() {
+    for _ in 0..100 {
+        let egraph = generate_random_egraph();
+        let roots = &egraph.root_eclasses;
+        // Bottom-up chooses a node for every class, needed or not.
+        let result = crate::bottom_up::BottomUpExtractor.extract(&egraph, roots);
+        let minimized = result.minimize(&egraph, roots);
+        minimized.check(&egraph);
+        assert!(minimized.choices.len() <= result.choices.len());
+        assert!(minimized
+            .choices
+            .iter()
+            .all(|(cid, nid)| result.choices[cid] == *nid));
+        assert_eq!(
+            minimized.tree_cost(&egraph, roots),
+            result.tree_cost(&egraph, roots)
+        );
+        assert_eq!(
+            minimized.dag_cost(&egraph, roots),
+            result.dag_cost(&egraph, roots)
+        );
+        assert_eq!(
+            minimized.minimize(&egraph, roots).choices,
+            minimized.choices
+        );
+    }
+}
+
+#[test]
+fn root_costs_sum_to_totals() {
+    for _ in 0..100 {
+        let egraph = generate_random_egraph();
+        let roots = &egraph.root_eclasses;
+        let result = crate::faster_greedy_dag::FasterGreedyDagExtractor.extract(&egraph, roots);
+        let root_costs = result.root_costs(&egraph, roots);
+        assert_eq!(root_costs.len(), roots.len());
+
+        let tree: f64 = root_costs.iter().map(|c| c.tree.into_inner()).sum();
+        let dag: f64 = root_costs.iter().map(|c| c.dag.into_inner()).sum();
+        assert!((tree - result.tree_cost(&egraph, roots).into_inner()).abs() < EPSILON_ALLOWANCE);
+        assert!((dag - result.dag_cost(&egraph, roots).into_inner()).abs() < EPSILON_ALLOWANCE);
+
+        // Each root's dag cost is at most its dag cost on its own.
+        for (root, cost) in roots.iter().zip(&root_costs) {
+            let alone = result.dag_cost(&egraph, std::slice::from_ref(root));
+            assert!(cost.dag <= alone + EPSILON_ALLOWANCE);
+            assert!(alone <= cost.tree + EPSILON_ALLOWANCE);
+        }
+    }
+}
+
+#[test]
+fn cse_dag_cost_counts_identical_subterms_once() {
+    let mut egraph = EGraph::default();
+    // (g (f x) (f x)) where the two (f x) are in different classes.
+    node_with_op(&mut egraph, "x", "x", "X", &[], 1.0);
+    node_with_op(&mut egraph, "f1", "f", "F1", &["x"], 10.0);
+    node_with_op(&mut egraph, "f2", "f", "F2", &["x"], 10.0);
+    node_with_op(&mut egraph, "g", "g", "G", &["f1", "f2"], 1.0);
+    egraph.root_eclasses.push("G".to_string().into());
+
+    let roots = &egraph.root_eclasses;
+    let result = crate::cse_greedy_dag::CseGreedyDagExtractor.extract(&egraph, roots);
+    result.check(&egraph);
+    assert_eq!(result.dag_cost(&egraph, roots), 22.0);
+    assert_eq!(result.cse_dag_cost(&egraph, roots), 12.0);
+
+    // A cyclic selection has no finite term.
+    let mut cyclic = egraph.clone();
+    node_with_op(&mut cyclic, "x_loop", "h", "X", &["g"], 1.0);
+    let mut looping = result.clone();
+    looping.choose("X".to_string().into(), "x_loop".to_string().into());
+    assert_eq!(looping.cse_dag_cost(&cyclic, roots), crate::INFINITY);
+
+    for _ in 0..100 {
+        let egraph = generate_random_egraph();
+        let roots = &egraph.root_eclasses;
+        for (_, ed) in extractors() {
+            let result = ed.extractor.extract(&egraph, roots);
+            assert!(result.cse_dag_cost(&egraph, roots) <= result.dag_cost(&egraph, roots));
+        }
+    }
+}
+
+// `--extractor` takes extractors that aren't benchmarked too.
+#[test]
+fn unbenchmarked_extractors_run_by_name() {
+    let egraph = generate_random_egraph();
+    let roots = &egraph.root_eclasses;
+    for name in [
+        "faster-bottom-up-capped",
+        "discounted-bottom-up",
+        "cse-greedy-dag",
+    ] {
+        let ed = crate::extractor_named(name).unwrap();
+        assert!(!ed.use_for_bench, "{name}");
+        ed.extractor.extract(&egraph, roots).check(&egraph);
+    }
+    assert!(crate::extractor_named("no-such-extractor").is_err());
+}
+
+// Even with the smallest caps, the final passes reach the same fixed point.
+#[test]
+fn capped_bottom_up_keeps_tree_cost() {
+    use crate::faster_bottom_up::CappedBottomUpExtractor;
+    for _ in 0..100 {
+        let egraph = generate_random_egraph();
+        let roots = &egraph.root_eclasses;
+        let expected = crate::bottom_up::BottomUpExtractor
+            .extract(&egraph, roots)
+            .tree_cost(&egraph, roots);
+        for revisit_cap in [0, 1, 2] {
+            let result = CappedBottomUpExtractor { revisit_cap }.extract(&egraph, roots);
+            result.check(&egraph);
+            let tree_cost = result.tree_cost(&egraph, roots);
+            assert!((tree_cost - expected).abs() < EPSILON_ALLOWANCE);
+        }
+    }
+}
+
+// The epsilon comparisons on float costs should agree with exact arithmetic about
+// which extractor wins, and the float costs should be within epsilon of exact.
+#[cfg(feature = "exact-costs")]
+#[test]
+fn float_costs_agree_with_exact() {
+    use crate::exact_cost::exact;
+    use num_traits::ToPrimitive;
+
+    for _ in 0..100 {
+        let egraph = generate_random_egraph();
+        let roots = &egraph.root_eclasses;
+        let costs: Vec<_> = extractors()
+            .values()
+            .map(|ed| {
+                let result = ed.extractor.extract(&egraph, roots);
+                (
+                    result.dag_cost(&egraph, roots),
+                    result.exact_dag_cost(&egraph, roots),
+                    result.tree_cost(&egraph, roots),
+                    result.exact_tree_cost(&egraph, roots),
+                )
+            })
+            .collect();
+
+        for (dag, exact_dag, tree, exact_tree) in &costs {
+            let error = |float: &Cost, exact_cost: &num_rational::BigRational| {
+                (exact(*float) - exact_cost).to_f64().unwrap().abs()
+            };
+            assert!(error(dag, exact_dag) < EPSILON_ALLOWANCE);
+            assert!(error(tree, exact_tree) < EPSILON_ALLOWANCE);
+        }
+        for (a, exact_a, ..) in &costs {
+            for (b, exact_b, ..) in &costs {
+                if *a + EPSILON_ALLOWANCE < *b {
+                    assert!(exact_a < exact_b);
+                }
+            }
+        }
+    }
+}
+
+// A chain deeper than the thread's stack would allow by recursion.
+#[test]
+fn topo_order_handles_deep_egraphs() {
+    let depth = 200_000;
+    let mut egraph = EGraph::default();
+    node(&mut egraph, "n0", "c0", &[], 1.0);
+    for i in 1..depth {
+        let child = format!("n{}", i - 1);
+        node(
+            &mut egraph,
+            &format!("n{i}"),
+            &format!("c{i}"),
+            &[child.as_str()],
+            1.0,
+        );
+    }
+    egraph.root_eclasses.push(format!("c{}", depth - 1).into());
+
+    let cache = CostCache::new(&egraph);
+    let order = cache.topo_order();
+    assert_eq!(order.len(), depth);
+    assert!(order
+        .iter()
+        .enumerate()
+        .all(|(i, cid)| *cid == ClassId::from(format!("c{i}"))));
+}
+
+#[test]
+fn borrowed_results_match_owned() {
+    use crate::faster_bottom_up::FasterBottomUpExtractor;
+    for _ in 0..100 {
+        let egraph = generate_random_egraph();
+        let cache = CostCache::new(&egraph);
+        let borrowed = FasterBottomUpExtractor.extract_ref(&egraph, &cache);
+        let owned = FasterBottomUpExtractor.extract(&egraph, &egraph.root_eclasses);
+        assert_eq!(borrowed.into_owned().choices, owned.choices);
+    }
+}
+
+#[test]
+fn shrink_egraph_keeps_failure() {
+    for _ in 0..20 {
+        let egraph = generate_random_egraph();
+        // A made-up failure: having at least two nodes.
+        let fails = |g: &EGraph| g.nodes.len() >= 2;
+        if !fails(&egraph) {
+            continue;
+        }
+        let shrunk = shrink_egraph(egraph, fails);
+        assert!(fails(&shrunk));
+        assert!(shrunk.root_eclasses.len() == 1);
+        crate::bottom_up::BottomUpExtractor
+            .extract(&shrunk, &shrunk.root_eclasses)
+            .check(&shrunk);
+    }
+}
+
+#[test]
+fn has_cycles_finds_cycles() {
+    let mut egraph = EGraph::default();
+    node(&mut egraph, "x", "X", &[], 1.0);
+    node(&mut egraph, "f", "F", &["x"], 1.0);
+    egraph.root_eclasses.push("F".to_string().into());
+    assert!(!crate::canonicalize::has_cycles(&egraph));
+
+    let mut cyclic = egraph.clone();
+    node(&mut cyclic, "g", "X", &["f"], 1.0);
+    assert!(crate::canonicalize::has_cycles(&cyclic));
+}
+
+#[test]
+fn suite_rules_veto_and_constrain() {
+    use crate::suite_rules::{CoSelectRule, SuiteRule};
+    let mut egraph = EGraph::default();
+    node(&mut egraph, "x", "X", &[], 0.0);
+    node(&mut egraph, "y", "X", &[], 5.0);
+    node(&mut egraph, "a", "R", &["x"], 1.0);
+    node(&mut egraph, "b", "R", &["x"], 3.0);
+    egraph.root_eclasses.push("R".to_string().into());
+    let roots = &egraph.root_eclasses;
+
+    let rule: CoSelectRule =
+        serde_json::from_str(r#"{"co_select": [{"op": "a", "requires_one_of": ["y"]}]}"#).unwrap();
+
+    // The cheapest extraction, a(x), chooses a without y.
+    let greedy = crate::faster_greedy_dag::FasterGreedyDagExtractor.extract(&egraph, roots);
+    assert_eq!(greedy.dag_cost(&egraph, roots), 1.0);
+    assert!(rule.check(&egraph, &greedy).is_err());
+
+    // Choices the roots don't reach aren't part of the term, so don't break the rule.
+    let mut unreachable = egraph.clone();
+    node_with_op(&mut unreachable, "u", "a", "U", &["x"], 1.0);
+    let mut result = ExtractionResult::default();
+    for (class, nid) in [("R", "b"), ("X", "x"), ("U", "u")] {
+        result.choose(class.to_string().into(), nid.to_string().into());
+    }
+    rule.check(&unreachable, &result).unwrap();
+
+    #[cfg(feature = "ilp-cbc")]
+    {
+        let constrained = crate::ilp_cbc::CbcExtractorWithRules {
+            rule: &rule,
+            timeout_seconds: std::u32::MAX,
+        }
+        .extract(&egraph, roots);
+        constrained.check(&egraph);
+        rule.check(&egraph, &constrained).unwrap();
+        // b(x) is cheaper than a(y).
+        assert_eq!(constrained.dag_cost(&egraph, roots), 3.0);
+    }
+}
+
+#[test]
+fn diverse_extractions_differ_from_the_reference() {
+    let mut egraph = EGraph::default();
+    node(&mut egraph, "x", "X", &[], 0.0);
+    node(&mut egraph, "y", "X", &[], 5.0);
+    node(&mut egraph, "a", "R", &["x"], 1.0);
+    node(&mut egraph, "b", "R", &["x"], 3.0);
+    egraph.root_eclasses.push("R".to_string().into());
+    let roots = &egraph.root_eclasses;
+
+    // a(x), the cheapest.
+    let reference = crate::bottom_up::BottomUpExtractor.extract(&egraph, roots);
+    assert_eq!(reference.differing_choices(&reference), 0);
+    assert_eq!(ExtractionResult::default().differing_choices(&reference), 2);
+
+    #[cfg(feature = "ilp-cbc")]
+    {
+        let diverse = |distance| {
+            crate::ilp_cbc::CbcExtractorDiverse {
+                reference: &reference,
+                distance,
+                timeout_seconds: std::u32::MAX,
+            }
+            .extract(&egraph, roots)
+        };
+        // b(x) is cheaper than a(y).
+        let one = diverse(1);
+        one.check(&egraph);
+        assert_eq!(one.dag_cost(&egraph, roots), 3.0);
+        let two = diverse(2);
+        two.check(&egraph);
+        assert_eq!(two.dag_cost(&egraph, roots), 8.0);
+        // There are only two choices to differ in.
+        assert!(diverse(3).choices.is_empty());
+    }
+}
+
+#[cfg(feature = "egg-interop")]
+#[test]
+fn rec_expr_shares_subterms() {
+    let mut egraph = EGraph::default();
+    node_with_op(&mut egraph, "x", "x", "X", &[], 1.0);
+    node_with_op(&mut egraph, "g", "g", "G", &["x"], 1.0);
+    node_with_op(&mut egraph, "f", "f", "F", &["g", "g"], 1.0);
+    egraph.root_eclasses.push("F".to_string().into());
+    let root = &egraph.root_eclasses[0];
+
+    let result =
+        crate::faster_bottom_up::FasterBottomUpExtractor.extract(&egraph, &egraph.root_eclasses);
+    let expr: egg::RecExpr<egg::SymbolLang> = result.to_rec_expr(&egraph, root).unwrap();
+    assert_eq!(expr.to_string(), "(f (g x) (g x))");
+    assert_eq!(expr.as_ref().len(), 3);
+
+    let mut missing = result.clone();
+    missing
+        .choices
+        .shift_remove(&ClassId::from("G".to_string()));
+    assert!(missing
+        .to_rec_expr::<egg::SymbolLang>(&egraph, root)
+        .is_err());
+}
+
+#[test]
+fn repeated_and_missing_roots() {
+    use crate::schema::parse_egraph;
+    let egraph = r#"{
+        "nodes": {
+            "a": { "op": "x", "children": [], "eclass": "c", "cost": 1 },
+            "b": { "op": "f", "children": ["a"], "eclass": "d", "cost": 2 }
+        },
+        "root_eclasses": ["d", "d", "c", "d"]
+    }"#;
+    let egraph = parse_egraph(egraph, false).unwrap();
+    assert_eq!(
+        egraph.root_eclasses,
+        vec![
+            ClassId::from("d".to_string()),
+            ClassId::from("c".to_string())
+        ]
+    );
+    for (name, ed) in extractors() {
+        let result = ed.extractor.extract(&egraph, &egraph.root_eclasses);
+        result.check(&egraph);
+        assert_eq!(
+            result.tree_cost(&egraph, &egraph.root_eclasses),
+            4.0,
+            "{name}"
+        );
+        assert_eq!(
+            result.dag_cost(&egraph, &egraph.root_eclasses),
+            3.0,
+            "{name}"
+        );
+    }
+
+    let missing = r#"{
+        "nodes": { "a": { "op": "x", "children": [], "eclass": "c", "cost": 1 } },
+        "root_eclasses": ["c", "e"]
+    }"#;
+    assert!(parse_egraph(missing, false).is_err());
+    assert!(parse_egraph(missing, true).is_err());
+}
+
+#[test]
+fn nodes_depending_on_empty_classes_are_removed() {
+    use crate::schema::parse_egraph;
+    // "gone" was filtered out, so g is dead, and so is h, whose only child class is g's.
+    // The root can still be extracted through f.
+    let egraph = r#"{
+        "nodes": {
+            "a": { "op": "x", "children": [], "eclass": "c", "cost": 1 },
+            "f": { "op": "f", "children": ["a"], "eclass": "r", "cost": 5 },
+            "g": { "op": "g", "children": ["gone"], "eclass": "e", "cost": 1 },
+            "h": { "op": "h", "children": ["g"], "eclass": "r", "cost": 1 }
+        },
+        "root_eclasses": ["r"]
+    }"#;
+    let egraph = parse_egraph(egraph, false).unwrap();
+    assert_eq!(egraph.nodes.len(), 2);
+    assert!(!egraph
+        .classes()
+        .contains_key(&ClassId::from("e".to_string())));
+    for (name, ed) in extractors() {
+        let result = ed.extractor.extract(&egraph, &egraph.root_eclasses);
+        result.check(&egraph);
+        assert_eq!(
+            result.dag_cost(&egraph, &egraph.root_eclasses),
+            6.0,
+            "{name}"
+        );
+    }
+
+    let dead_root = r#"{
+        "nodes": { "g": { "op": "g", "children": ["gone"], "eclass": "r", "cost": 1 } },
+        "root_eclasses": ["r"]
+    }"#;
+    assert!(parse_egraph(dead_root, true).is_err());
+}
+
+#[test]
+fn cost_model_deltas_find_flipped_choices() {
+    use crate::cost_diff::{check_same_structure, choice_deltas};
+    let mut a = EGraph::default();
+    node(&mut a, "x", "X", &[], 1.0);
+    node(&mut a, "y", "X", &[], 2.0);
+    node(&mut a, "f", "F", &["x"], 1.0);
+    a.root_eclasses.push("F".to_string().into());
+
+    // Under b, y is the cheaper leaf.
+    let mut b = a.clone();
+    b.nodes[&NodeId::from("y".to_string())].cost = Cost::new(0.5).unwrap();
+    check_same_structure(&a, &b).unwrap();
+
+    let extractor = crate::faster_bottom_up::FasterBottomUpExtractor;
+    let result_a = extractor.extract(&a, &a.root_eclasses);
+    let result_b = extractor.extract(&b, &b.root_eclasses);
+    let deltas = choice_deltas(&a, &b, &result_a, &result_b);
+    assert_eq!(deltas.len(), 1);
+    assert_eq!(deltas[0].class, ClassId::from("X".to_string()));
+    assert_eq!(deltas[0].delta_under_a, 1.0);
+    assert_eq!(deltas[0].delta_under_b, -0.5);
+
+    let mut different = b.clone();
+    different.nodes[&NodeId::from("f".to_string())].children = vec![];
+    assert!(check_same_structure(&a, &different).is_err());
+}
+
+#[test]
+fn merged_egraphs_have_the_nodes_of_both() {
+    use crate::merge::merge;
+    let mut a = EGraph::default();
+    node(&mut a, "x", "X", &[], 2.0);
+    node(&mut a, "f", "F", &["x"], 1.0);
+    a.root_eclasses.push("F".to_string().into());
+
+    // b found a cheaper node for X, and has a root of its own.
+    let mut b = EGraph::default();
+    node(&mut b, "x", "X", &[], 2.0);
+    node(&mut b, "y", "X", &[], 1.0);
+    node(&mut b, "g", "G", &["y"], 1.0);
+    b.root_eclasses.push("G".to_string().into());
+    b.root_eclasses.push("F".to_string().into());
+
+    let merged = merge(&a, &b).unwrap();
+    assert_eq!(merged.nodes.len(), 4);
+    assert_eq!(merged.root_eclasses.len(), 2);
+    let roots = &merged.root_eclasses;
+    let result = crate::bottom_up::BottomUpExtractor.extract(&merged, roots);
+    result.check(&merged);
+    assert_eq!(result.dag_cost(&merged, roots), 3.0);
+
+    let mut moved = b.clone();
+    moved.nodes[&NodeId::from("x".to_string())].eclass = "Y".to_string().into();
+    assert!(merge(&a, &moved).is_err());
+    let mut repriced = b.clone();
+    repriced.nodes[&NodeId::from("x".to_string())].cost = Cost::new(5.0).unwrap();
+    assert!(merge(&a, &repriced).is_err());
+}
+
+#[test]
+fn monotonicity_pinpoints_lost_choices() {
+    use crate::monotonic::check_monotonic;
+    let mut old = EGraph::default();
+    node(&mut old, "x", "X", &[], 1.0);
+    node(&mut old, "y", "X", &[], 2.0);
+    node(&mut old, "f", "F", &["x"], 1.0);
+    old.root_eclasses.push("F".to_string().into());
+
+    // A newer version with an extra option extracts no worse.
+    let mut new = old.clone();
+    node(&mut new, "z", "X", &[], 0.5);
+    let extractor = crate::faster_bottom_up::FasterBottomUpExtractor;
+    let report = check_monotonic(&extractor, &old, &new).unwrap();
+    assert!(!report.regressed());
+    assert_eq!(report.new_dag, 1.5);
+
+    // One that lost the cheap leaf regresses, and the leaf is blamed.
+    let mut filtered = EGraph::default();
+    node(&mut filtered, "y", "X", &[], 2.0);
+    node(&mut filtered, "f", "F", &["y"], 1.0);
+    filtered.root_eclasses.push("F".to_string().into());
+    let report = check_monotonic(&extractor, &old, &filtered).unwrap();
+    assert!(report.regressed());
+    assert_eq!(report.lost.len(), 1);
+    assert_eq!(report.lost[0].node, NodeId::from("x".to_string()));
+    assert!(!report.lost[0].class_missing);
+
+    let mut rootless = EGraph::default();
+    node(&mut rootless, "x", "X", &[], 1.0);
+    rootless.root_eclasses.push("X".to_string().into());
+    assert!(check_monotonic(&extractor, &old, &rootless).is_err());
+}
+
+#[test]
+fn sexp_output() {
+    let mut egraph = EGraph::default();
+    node_with_op(&mut egraph, "x", "x", "X", &[], 1.0);
+    node_with_op(&mut egraph, "n", "Num(2)", "N", &[], 1.0);
+    node_with_op(&mut egraph, "g", "g", "G", &["x", "n"], 1.0);
+    node_with_op(&mut egraph, "f", "f", "F", &["g", "g"], 1.0);
+    egraph.root_eclasses.push("F".to_string().into());
+
+    let result =
+        crate::faster_bottom_up::FasterBottomUpExtractor.extract(&egraph, &egraph.root_eclasses);
+    assert_eq!(
+        result.to_sexp(&egraph, &egraph.root_eclasses[0]),
+        r#"(f (g x "Num(2)") (g x "Num(2)"))"#
+    );
+}
+
+#[test]
+#[should_panic(expected = "less than")]
+fn soundness_checks_catch_non_optimal_choices() {
+    use crate::soundness::check_tree_optimal_sampled;
+    let mut egraph = EGraph::default();
+    node(&mut egraph, "x", "X", &[], 1.0);
+    node(&mut egraph, "y", "X", &[], 2.0);
+    node(&mut egraph, "f", "F", &["x"], 1.0);
+    egraph.root_eclasses.push("F".to_string().into());
+
+    let mut result = crate::bottom_up::BottomUpExtractor.extract(&egraph, &egraph.root_eclasses);
+    check_tree_optimal_sampled(&egraph, &result, 1.0);
+
+    result.choose("X".to_string().into(), "y".to_string().into());
+    check_tree_optimal_sampled(&egraph, &result, 1.0);
+}
+
+#[test]
+fn batch_extracts_every_file() {
+    let extractors = extractors();
+    let ed = &extractors["faster-greedy-dag"];
+    let dir = std::path::Path::new("./test_data/fuzz");
+    let files = std::fs::read_dir(dir)
+        .unwrap()
+        .filter(|e| e.as_ref().unwrap().path().extension() == Some("json".as_ref()))
+        .count();
+
+    let corpus = crate::corpus::Corpus::discover([dir]);
+    let report = crate::batch::run_batch(ed, "faster-greedy-dag", &corpus, false, 3);
+    assert_eq!(report.results.len(), files);
+    let single = crate::batch::run_batch(ed, "faster-greedy-dag", &corpus, false, 1);
+    for (record, expected) in report.results.iter().zip(&single.results) {
+        assert_eq!(record.name, expected.name);
+        assert!(
+            record.error.is_none(),
+            "{}: {:?}",
+            record.name,
+            record.error
+        );
+        assert_eq!(record.dag, expected.dag);
+    }
+}
+
+#[test]
+fn corpus_tags_suites_and_filters() {
+    use crate::corpus::Corpus;
+    let corpus = Corpus::discover(["./test_data/"]);
+    assert!(corpus
+        .entries
+        .iter()
+        .all(|e| e.path.extension() == Some("json".as_ref())));
+    for suite in ["crafted", "fuzz"] {
+        assert!(corpus.entries.iter().any(|e| e.suite == suite));
+    }
+
+    let fuzz = Corpus::discover(["./test_data/"]).in_suites(&["fuzz".to_string()]);
+    assert!(!fuzz.entries.is_empty());
+    assert!(fuzz.entries.iter().all(|e| e.suite == "fuzz"));
+
+    // Files directly in the walked directory have no suite of their own.
+    let flat = Corpus::discover(["./test_data/fuzz"]);
+    assert!(flat.entries.iter().all(|e| e.suite == "."));
+
+    let small = Corpus::discover(["./test_data/"]).with_max_classes(3);
+    assert!(small
+        .egraphs(true)
+        .all(|egraph| egraph.classes().len() <= 3));
+    assert!(small.egraphs(true).count() < corpus.entries.len());
+}
+
+#[test]
+fn restarts_keep_the_cheapest_seed() {
+    use crate::faster_greedy_dag::{NodeOrder, OrderedGreedyDagExtractor};
+    use crate::restarts::extract_with_restarts;
+    let extractor = OrderedGreedyDagExtractor {
+        order: NodeOrder::Random(0),
+    };
+    for _ in 0..20 {
+        let egraph = generate_random_egraph();
+        let roots = &egraph.root_eclasses;
+
+        // The same seed gives the same result.
+        assert_eq!(
+            extractor.extract_with_seed(&egraph, roots, 7).choices,
+            extractor.extract_with_seed(&egraph, roots, 7).choices
+        );
+
+        let restarts = extract_with_restarts(&extractor, &egraph, roots, 5, 2);
+        restarts.result.check(&egraph);
+        assert_eq!(restarts.runs.len(), 5);
+        let best = restarts
+            .runs
+            .iter()
+            .filter_map(|run| run.dag)
+            .min()
+            .unwrap();
+        assert_eq!(restarts.result.dag_cost(&egraph, roots), best);
+    }
+}
+
+#[test]
+fn fast_egraphs_index_like_their_egraphs() {
+    for _ in 0..50 {
+        let egraph = generate_random_egraph();
+        let fast = crate::fast_egraph::FastEgraph::new(&egraph);
+        let cache = CostCache::new(&egraph);
+        assert_eq!(fast.num_nodes(), egraph.nodes.len());
+        for (class, cid) in fast.class_indices().zip(&fast.class_ids) {
+            let nodes: Vec<&NodeId> = fast
+                .nodes(class)
+                .map(|n| &fast.node_ids[n.index()])
+                .collect();
+            assert_eq!(nodes, egraph[cid].nodes.iter().collect::<Vec<_>>());
+            for node in fast.nodes(class) {
+                let children: Vec<&ClassId> = fast
+                    .children(node)
+                    .iter()
+                    .map(|&c| &fast.class_ids[c.index()])
+                    .collect();
+                let expected: Vec<&ClassId> = egraph[&fast.node_ids[node.index()]]
+                    .children
+                    .iter()
+                    .map(|c| egraph.nid_to_cid(c))
+                    .collect();
+                assert_eq!(children, expected);
+            }
+            let parents: std::collections::HashSet<&NodeId> = fast
+                .parents(class)
+                .iter()
+                .map(|&n| &fast.node_ids[n.index()])
+                .collect();
+            assert_eq!(parents.len(), fast.parents(class).len());
+            assert_eq!(parents, cache.parents()[cid].iter().collect());
+        }
+    }
+}
+
+#[test]
+fn exhaustive_search_finds_planted_optima() {
+    let mut searched = 0;
+    for _ in 0..50 {
+        let planted = generate_planted_egraph();
+        let egraph = &planted.egraph;
+        if let Some(cost) =
+            crate::verify::exhaustive_dag_cost(egraph, &egraph.root_eclasses, 100_000)
+        {
+            assert!(
+                (cost.into_inner() - planted.optimal_dag_cost.into_inner()).abs()
+                    < EPSILON_ALLOWANCE
+            );
+            searched += 1;
+        }
+    }
+    assert!(searched > 0);
+}
+
+#[test]
+fn preprocessing_keeps_the_optimum() {
+    let mut egraph = EGraph::default();
+    node(&mut egraph, "a", "A", &[], 1.0);
+    node(&mut egraph, "b", "B", &[], 1.0);
+    node(&mut egraph, "u", "U", &[], 1.0);
+    node(&mut egraph, "r1", "R", &["a"], 2.0);
+    // Subsumed by r1, which leaves B unreachable.
+    node(&mut egraph, "r2", "R", &["a", "b"], 3.0);
+    node(&mut egraph, "r3", "R", &["r1"], 0.0);
+    egraph.root_eclasses.push("R".to_string().into());
+
+    let (simplified, stats) = crate::preprocess::preprocess(&egraph).unwrap();
+    let mut kept: Vec<String> = simplified.nodes.keys().map(|nid| nid.to_string()).collect();
+    kept.sort();
+    assert_eq!(kept, vec!["a", "r1"]);
+    assert_eq!((stats.loops, stats.subsumed, stats.unreachable), (1, 1, 2));
+
+    // With only nodes that have the root as a child, the root is left without any.
+    let mut looping = EGraph::default();
+    node(&mut looping, "x", "X", &[], 1.0);
+    node(&mut looping, "r", "R", &["r"], 1.0);
+    looping.root_eclasses.push("R".to_string().into());
+    assert!(crate::preprocess::preprocess(&looping).is_err());
+
+    for _ in 0..100 {
+        let egraph = generate_random_egraph();
+        let (simplified, _) = crate::preprocess::preprocess(&egraph).unwrap();
+        let roots = &egraph.root_eclasses;
+        let original = crate::bottom_up::BottomUpExtractor.extract(&egraph, roots);
+        let result = crate::bottom_up::BottomUpExtractor.extract(&simplified, roots);
+        result.check(&egraph);
+        assert_eq!(
+            result.tree_cost(&egraph, roots),
+            original.tree_cost(&egraph, roots)
+        );
+    }
+}
+
+#[cfg(feature = "ilp-cbc")]
+#[test]
+fn lp_lower_bounds_are_below_the_optimum() {
+    for _ in 0..20 {
+        let planted = generate_planted_egraph();
+        let egraph = &planted.egraph;
+        let bound = crate::ilp_cbc::lp_lower_bound(egraph, &egraph.root_eclasses).unwrap();
+        assert!(bound.into_inner() <= planted.optimal_dag_cost.into_inner() + EPSILON_ALLOWANCE);
+    }
+}
+
+// The tree ILP certifies the tree costs of the extractors that claim to be tree optimal.
+#[cfg(feature = "ilp-cbc")]
+#[test]
+fn tree_optimal_extractors_match_the_tree_ilp() {
+    // Small, so the tree costs stay small enough for the solver to be exact.
+    let config = RandomEgraphConfig {
+        core_nodes: 1..30,
+        extra_nodes: 1..20,
+        ..Default::default()
+    };
+    let tree_optimal: Vec<(&str, crate::ExtractorDetail)> = extractors()
+        .into_iter()
+        .filter(|(_, ed)| matches!(ed.optimal, Optimal::Tree))
+        .collect();
+    for _ in 0..20 {
+        let egraph = generate_random_egraph_with(&config);
+        let roots = &egraph.root_eclasses;
+        let certified = crate::ilp_cbc::CbcExtractorMinTree {
+            timeout_seconds: std::u32::MAX,
+        }
+        .extract(&egraph, roots);
+        certified.check(&egraph);
+        let optimum = certified.tree_cost(&egraph, roots).into_inner();
+
+        let cyclic = crate::canonicalize::has_cycles(&egraph);
+        for (name, ed) in &tree_optimal {
+            if cyclic && !ed.supports_cycles {
+                continue;
+            }
+            let tree = ed
+                .extractor
+                .extract(&egraph, roots)
+                .tree_cost(&egraph, roots);
+            assert!(
+                (tree.into_inner() - optimum).abs() <= EPSILON_ALLOWANCE * optimum.max(1.0),
+                "{name} has tree cost {tree}, but the optimum is {optimum}"
+            );
+        }
+    }
+}
+
+#[test]
+fn improvements_reuse_chosen_classes() {
+    let mut egraph = EGraph::default();
+    node(&mut egraph, "s", "S", &[], 2.0);
+    node(&mut egraph, "a1", "A", &["s"], 1.0);
+    node(&mut egraph, "a2", "A", &[], 2.0);
+    node(&mut egraph, "b1", "B", &["s"], 1.0);
+    node(&mut egraph, "b2", "B", &[], 4.0);
+    node(&mut egraph, "pair", "R", &["a2", "b1"], 0.0);
+    egraph.root_eclasses.push("R".to_string().into());
+
+    let mut result = ExtractionResult::default();
+    for (cid, nid) in [("R", "pair"), ("A", "a2"), ("B", "b1"), ("S", "s")] {
+        result.choose(cid.to_string().into(), nid.to_string().into());
+    }
+    let improvements = crate::improve::find_improvements(&egraph, &result, &egraph.root_eclasses);
+    assert_eq!(improvements.len(), 1);
+    let improvement = &improvements[0];
+    assert_eq!(improvement.to, NodeId::from("a1".to_string()));
+    assert_eq!(improvement.saving, 1.0);
+    assert_eq!(improvement.reused, vec![ClassId::from("S".to_string())]);
+}
+
+#[test]
+fn extractor_listing_covers_every_extractor() {
+    let listing = crate::schema::ExtractorListing::all();
+    let names: Vec<&str> = listing.iter().map(|l| l.name.as_str()).collect();
+    assert_eq!(names, extractors().keys().copied().collect::<Vec<_>>());
+    assert!(listing.iter().all(|l| !l.description.is_empty()));
+    // Every listed extractor can be run by name, benchmarked or not.
+    for l in &listing {
+        assert!(crate::extractor_named(&l.name).is_ok(), "{}", l.name);
+    }
+    let json = serde_json::to_value(&listing).unwrap();
+    assert_eq!(json[0]["name"], names[0]);
+}
+
+#[cfg(feature = "smt")]
+#[test]
+fn z3_finds_planted_optima() {
+    for _ in 0..20 {
+        let planted = generate_planted_egraph();
+        let egraph = &planted.egraph;
+        let roots = &egraph.root_eclasses;
+        let result = crate::smt_z3::Z3Extractor { timeout_ms: None }.extract(egraph, roots);
+        result.check(egraph);
+        let dag = result.dag_cost(egraph, roots).into_inner();
+        assert!((dag - planted.optimal_dag_cost.into_inner()).abs() < EPSILON_ALLOWANCE);
+    }
+}
+
+#[test]
+fn views_extract_like_their_copies() {
+    use crate::view::EGraphView;
+    let mut rng = rand::thread_rng();
+    for _ in 0..20 {
+        let egraph = generate_random_egraph();
+        let excluded: Vec<NodeId> = egraph
+            .nodes
+            .keys()
+            .filter(|_| rng.gen_bool(0.1))
+            .cloned()
+            .collect();
+        let view = EGraphView::new(&egraph).without_nodes(excluded.iter().cloned());
+
+        let skipping = crate::faster_bottom_up::FasterBottomUpExtractor
+            .extract_view(&view, &egraph.root_eclasses);
+        let copying =
+            crate::bottom_up::BottomUpExtractor.extract_view(&view, &egraph.root_eclasses);
+        assert!(excluded
+            .iter()
+            .all(|nid| !skipping.choices.values().any(|n| n == nid)));
+
+        let skipping_costs = skipping.choice_tree_costs(&egraph);
+        let copying_costs = copying.choice_tree_costs(&egraph);
+        assert_eq!(skipping_costs.len(), copying_costs.len());
+        for (cid, cost) in &skipping_costs {
+            assert!(
+                (cost.into_inner() - copying_costs[cid].into_inner()).abs() < EPSILON_ALLOWANCE
+            );
+        }
+    }
+}
+
+#[test]
+fn views_without_a_root_extract_the_other_roots() {
+    use crate::view::EGraphView;
+    let mut egraph = EGraph::default();
+    node(&mut egraph, "x", "X", &[], 1.0);
+    node(&mut egraph, "f", "F", &["x"], 1.0);
+    node(&mut egraph, "g", "G", &[], 1.0);
+    egraph.root_eclasses.push("F".to_string().into());
+    egraph.root_eclasses.push("G".to_string().into());
+
+    // G has no nodes in the view.
+    let view = EGraphView::new(&egraph).without_nodes(["g".to_string().into()]);
+    let result = crate::bottom_up::BottomUpExtractor.extract_view(&view, &egraph.root_eclasses);
+    assert!(result.choices.contains_key(&ClassId::from("F".to_string())));
+    assert!(!result.choices.contains_key(&ClassId::from("G".to_string())));
+}
+
+// Times bottom-up on a FastEgraph against the same loop over a hash map of class ids,
+// as it was before FastEgraph. Run with `cargo test --release -- --ignored`.
+#[test]
+#[ignore]
+fn fast_egraphs_speed_up_bottom_up() {
+    use crate::fast_egraph::{FastEgraph, FastExtractor};
+    use rustc_hash::FxHashMap;
+
+    fn hashed_bottom_up(egraph: &EGraph) -> ExtractionResult {
+        let mut result = ExtractionResult::default();
+        let mut costs = FxHashMap::<ClassId, Cost>::default();
+        let mut did_something = true;
+        while did_something {
+            did_something = false;
+            for class in egraph.classes().values() {
+                for node in &class.nodes {
+                    let cost = result.node_sum_cost(egraph, &egraph[node], &costs);
+                    if &cost < costs.get(&class.id).unwrap_or(&crate::INFINITY) {
+                        result.choose(class.id.clone(), node.clone());
+                        costs.insert(class.id.clone(), cost);
+                        did_something = true;
+                    }
+                }
+            }
+        }
+        result
+    }
+
+    let config = RandomEgraphConfig {
+        core_nodes: 5000..5001,
+        extra_nodes: 1000..1001,
+        child_probability: 0.001,
+        cycle_probability: 0.0005,
+        ..Default::default()
+    };
+    let egraph = crate::random::generate(&config, 0);
+    let roots = &egraph.root_eclasses;
+    // The classes are computed once and cached, so not by whichever runs first.
+    let _ = egraph.classes();
+
+    let start = std::time::Instant::now();
+    let hashed = hashed_bottom_up(&egraph);
+    let hashed_time = start.elapsed();
+
+    let start = std::time::Instant::now();
+    let fast = FastEgraph::new(&egraph);
+    let build_time = start.elapsed();
+    let indexed = fast.to_result(&crate::bottom_up::BottomUpExtractor.extract_fast(&fast));
+    let indexed_time = start.elapsed();
+
+    assert_eq!(
+        hashed.tree_cost(&egraph, roots),
+        indexed.tree_cost(&egraph, roots)
+    );
+    eprintln!(
+        "hashed {hashed_time:?}, indexed {indexed_time:?} of which building {build_time:?}, {:.1}x",
+        hashed_time.as_secs_f64() / indexed_time.as_secs_f64()
+    );
+    assert!(indexed_time < hashed_time);
+}
+
+#[test]
+fn depth_extraction_is_shallowest() {
+    use crate::bottom_up::{BottomUpExtractor, DepthBottomUpExtractor};
+    for _ in 0..50 {
+        let egraph = generate_random_egraph();
+        let roots = &egraph.root_eclasses;
+        let shallowest = DepthBottomUpExtractor.extract(&egraph, roots);
+        shallowest.check(&egraph);
+        let depth = shallowest.depth_cost(&egraph, roots);
+
+        for other in [
+            BottomUpExtractor.extract(&egraph, roots),
+            crate::faster_greedy_dag::FasterGreedyDagExtractor.extract(&egraph, roots),
+        ] {
+            assert!(depth <= other.depth_cost(&egraph, roots));
+            // A path's cost is part of the tree cost.
+            assert!(other.depth_cost(&egraph, roots) <= other.tree_cost(&egraph, roots));
+        }
+
+        #[cfg(feature = "ilp-cbc")]
+        {
+            let cheapest = crate::ilp_cbc::CbcExtractorMinDepth {
+                timeout_seconds: std::u32::MAX,
+            }
+            .extract(&egraph, roots);
+            cheapest.check(&egraph);
+            let cheapest_depth = cheapest.depth_cost(&egraph, roots).into_inner();
+            assert!((cheapest_depth - depth.into_inner()).abs() < EPSILON_ALLOWANCE);
+            assert!(
+                cheapest.dag_cost(&egraph, roots).into_inner()
+                    <= shallowest.dag_cost(&egraph, roots).into_inner() + EPSILON_ALLOWANCE
+            );
+        }
+    }
+}
+
+#[cfg(feature = "ilp-cbc")]
+#[test]
+fn pareto_front_trades_cost_for_depth() {
+    let mut egraph = EGraph::default();
+    // Sharing s is cheapest, but not sharing it is shallower.
+    node(&mut egraph, "s", "S", &[], 3.0);
+    node(&mut egraph, "a1", "A", &["s"], 1.0);
+    node(&mut egraph, "a2", "A", &[], 3.0);
+    node(&mut egraph, "b1", "B", &["s"], 1.0);
+    node(&mut egraph, "b2", "B", &[], 3.0);
+    node(&mut egraph, "pair", "R", &["a1", "b1"], 0.0);
+    egraph.root_eclasses.push("R".to_string().into());
+
+    let front = crate::ilp_cbc::pareto_front(&egraph, &egraph.root_eclasses, std::u32::MAX);
+    let points: Vec<(f64, f64)> = front
+        .iter()
+        .map(|p| (p.dag.into_inner(), p.depth.into_inner()))
+        .collect();
+    assert_eq!(points, vec![(5.0, 4.0), (6.0, 3.0)]);
+    for point in &front {
+        point.result.check(&egraph);
+    }
+}
+
+#[test]
+fn timeouts_return_finished_extractions() {
+    use crate::timeout::extract_with_timeout;
+    use std::time::Duration;
+
+    struct Slow;
+    impl Extractor for Slow {
+        fn extract(&self, egraph: &EGraph, roots: &[ClassId]) -> ExtractionResult {
+            std::thread::sleep(Duration::from_secs(10));
+            crate::bottom_up::BottomUpExtractor.extract(egraph, roots)
+        }
+    }
+
+    let egraph = std::sync::Arc::new(generate_random_egraph());
+    let roots = egraph.root_eclasses.clone();
+    let result = extract_with_timeout(
+        || crate::faster_greedy_dag::FasterGreedyDagExtractor.boxed(),
+        egraph.clone(),
+        roots.clone(),
+        Duration::from_secs(60),
+    );
+    result.unwrap().check(&egraph);
+
+    let result = extract_with_timeout(|| Slow.boxed(), egraph, roots, Duration::from_millis(10));
+    assert!(result.is_none());
+}
+
+#[test]
+fn cycle_strategies_agree() {
+    use crate::cycles::{
+        feedback_classes, find_cycles, simple_cycles, strongly_connected_components,
+    };
+    let cid = |s: &str| -> ClassId { s.to_string().into() };
+    // a -> b -> c -> a, a -> c, c -> d -> d, e -> a
+    let ids: Vec<ClassId> = ["a", "b", "c", "d", "e"].iter().map(|&s| cid(s)).collect();
+    let mut edges: IndexMap<ClassId, Vec<ClassId>> = IndexMap::new();
+    for (from, to) in [
+        ("a", "b"),
+        ("b", "c"),
+        ("c", "a"),
+        ("a", "c"),
+        ("c", "d"),
+        ("d", "d"),
+        ("e", "a"),
+    ] {
+        edges.entry(cid(from)).or_default().push(cid(to));
+    }
+    let successors = |c: &ClassId| edges.get(c).into_iter().flatten();
+    let classes: Vec<&ClassId> = ids.iter().collect();
+
+    let mut cycles = find_cycles(&ids, successors, usize::MAX);
+    cycles.sort();
+    assert_eq!(
+        cycles,
+        vec![vec![cid("a"), cid("b"), cid("c")], vec![cid("d")]]
+    );
+    assert_eq!(find_cycles(&ids, successors, 1).len(), 1);
+
+    // The depth first search misses a -> c -> a, as c is done by then.
+    let mut simple = simple_cycles(&classes, successors, usize::MAX);
+    simple.sort();
+    assert_eq!(
+        simple,
+        vec![
+            vec![cid("a"), cid("b"), cid("c")],
+            vec![cid("a"), cid("c")],
+            vec![cid("d")]
+        ]
+    );
+    assert_eq!(simple_cycles(&classes, successors, 2).len(), 2);
+
+    let mut components = strongly_connected_components(&classes, successors);
+    for component in &mut components {
+        component.sort();
+    }
+    // Children first.
+    assert_eq!(
+        components,
+        vec![
+            vec![cid("d")],
+            vec![cid("a"), cid("b"), cid("c")],
+            vec![cid("e")]
+        ]
+    );
+
+    let feedback = feedback_classes(&classes, successors);
+    assert_eq!(feedback.len(), 2);
+    assert!(feedback.contains(&cid("d")));
+    let rest: Vec<&ClassId> = ids.iter().filter(|c| !feedback.contains(c)).collect();
+    let removed = &feedback;
+    let rest_successors = |c: &ClassId| successors(c).filter(move |c| !removed.contains(c));
+    assert!(find_cycles(rest.iter().copied(), rest_successors, usize::MAX).is_empty());
+}
+
+#[test]
+fn op_policies_are_met() {
+    use crate::op_policy::{OpPolicy, PenalizedExtractor};
+    let mut egraph = EGraph::default();
+    node_with_op(&mut egraph, "a1", "MulDsp", "A", &[], 1.0);
+    node_with_op(&mut egraph, "a2", "MulLut", "A", &[], 3.0);
+    node_with_op(&mut egraph, "b1", "MulLut", "B", &[], 1.0);
+    node_with_op(&mut egraph, "b2", "MulDsp", "B", &[], 5.0);
+    node_with_op(&mut egraph, "r", "Pair", "R", &["a1", "b1"], 0.0);
+    egraph.root_eclasses.push("R".to_string().into());
+    let roots = &egraph.root_eclasses;
+
+    let policy: OpPolicy =
+        serde_json::from_str(r#"{"exclusive": [["MulDsp", "MulLut"]]}"#).unwrap();
+
+    // The cheapest extraction, a1 and b1, uses both.
+    let greedy = crate::faster_greedy_dag::FasterGreedyDagExtractor.extract(&egraph, roots);
+    assert_eq!(greedy.dag_cost(&egraph, roots), 2.0);
+    assert_eq!(policy.violations(&egraph, &greedy, roots).len(), 1);
+    assert!(policy.check(&egraph, &greedy, roots).is_err());
+
+    // Each op is used once, so the first, MulDsp, is kept.
+    let penalized = PenalizedExtractor {
+        extractor: &crate::faster_greedy_dag::FasterGreedyDagExtractor,
+        policy: &policy,
+    }
+    .extract(&egraph, roots);
+    penalized.check(&egraph);
+    policy.check(&egraph, &penalized, roots).unwrap();
+    assert_eq!(penalized.dag_cost(&egraph, roots), 6.0);
+
+    #[cfg(feature = "ilp-cbc")]
+    {
+        let constrained = crate::ilp_cbc::CbcExtractorWithPolicy {
+            policy: &policy,
+            timeout_seconds: std::u32::MAX,
+        }
+        .extract(&egraph, roots);
+        constrained.check(&egraph);
+        policy.check(&egraph, &constrained, roots).unwrap();
+        // a2 and b1, both MulLut, are cheaper.
+        assert_eq!(constrained.dag_cost(&egraph, roots), 4.0);
+    }
+}
+
+#[test]
+fn batches_skip_duplicate_egraphs() {
+    let dir = std::env::temp_dir().join("extraction_gym_duplicates");
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let json = std::fs::read_to_string("./test_data/crafted/paths.json").unwrap();
+    std::fs::write(dir.join("a.json"), &json).unwrap();
+    // The same egraph, formatted differently.
+    let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+    std::fs::write(dir.join("b.json"), serde_json::to_string(&value).unwrap()).unwrap();
+    std::fs::copy("./test_data/crafted/tree.json", dir.join("c.json")).unwrap();
+
+    let extractors = extractors();
+    let corpus = crate::corpus::Corpus::discover([&dir]);
+    let report = crate::batch::run_batch(&extractors["bottom-up"], "bottom-up", &corpus, false, 2);
+    assert_eq!(report.results.len(), 3);
+    assert_eq!(report.duplicates, 1);
+    let [a, b, c] = &report.results[..] else {
+        panic!()
+    };
+    assert_eq!(a.hash, b.hash);
+    assert_ne!(a.hash, c.hash);
+    assert_eq!(b.duplicate_of.as_ref(), Some(&a.name));
+    assert!(a.duplicate_of.is_none() && c.duplicate_of.is_none());
+    assert_eq!(a.dag, b.dag);
+}
+
+#[test]
+fn low_memory_loading_matches_the_egraph() {
+    use crate::fast_egraph::{FastEgraph, FastExtractor};
+    let corpus = crate::corpus::Corpus::discover(["./test_data/"]);
+    for entry in &corpus.entries {
+        let path = entry.path.to_string_lossy();
+        let egraph = crate::schema::read_egraph(&path, true).unwrap();
+        let expected = FastEgraph::new(&egraph);
+        let fast = FastEgraph::read_json(&path, true).unwrap();
+
+        assert_eq!(fast.class_ids, expected.class_ids, "{path}");
+        assert_eq!(fast.node_ids, expected.node_ids, "{path}");
+        assert_eq!(fast.node_cost, expected.node_cost, "{path}");
+        assert_eq!(fast.class_min_cost, expected.class_min_cost, "{path}");
+        assert_eq!(fast.roots, expected.roots, "{path}");
+        for node in fast.node_indices() {
+            assert_eq!(fast.children(node), expected.children(node), "{path}");
+        }
+        for class in fast.class_indices() {
+            assert!(fast.nodes(class).eq(expected.nodes(class)), "{path}");
+            assert_eq!(fast.parents(class), expected.parents(class), "{path}");
+        }
+
+        let choices = crate::bottom_up::BottomUpExtractor.extract_fast(&fast);
+        let costs = fast.costs(&choices);
+        let result = fast.to_result(&choices);
+        let roots = &egraph.root_eclasses;
+        // The sums are in different orders, so may round differently.
+        let close = |a: Cost, b: Cost| (a - b).abs() <= 1e-9 * a.abs().max(1.0);
+        assert!(
+            close(costs.tree, result.tree_cost(&egraph, roots)),
+            "{path}"
+        );
+        assert!(close(costs.dag, result.dag_cost(&egraph, roots)), "{path}");
+        assert_eq!(costs.depth, result.depth_cost(&egraph, roots), "{path}");
+    }
+}
+
+#[test]
+fn binary_egraphs_round_trip() {
+    for _ in 0..10 {
+        let egraph = generate_random_egraph();
+        let path = std::env::temp_dir().join("extraction_gym_round_trip.bin");
+        crate::convert::write_binary(&egraph, &path).unwrap();
+        let read = crate::schema::read_egraph(&path.to_string_lossy(), false).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(read.root_eclasses, egraph.root_eclasses);
+        assert_eq!(read.nodes.len(), egraph.nodes.len());
+        for ((nid, node), (read_nid, read_node)) in egraph.nodes.iter().zip(&read.nodes) {
+            assert_eq!(nid, read_nid);
+            assert_eq!(node.op, read_node.op);
+            assert_eq!(node.children, read_node.children);
+            assert_eq!(node.eclass, read_node.eclass);
+            assert_eq!(node.cost, read_node.cost);
+        }
+    }
+}
+
+#[test]
+fn aggregates_compare_extractors_head_to_head() {
+    use crate::aggregate::{head_to_head, load_results};
+    let dir = std::env::temp_dir().join("extraction_gym_aggregate");
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(dir.join("suite")).unwrap();
+    let write = |file: &str, json: &str| std::fs::write(dir.join(file), json).unwrap();
+    let result = |name: &str, extractor: &str, dag: f64, micros: u64| {
+        format!(
+            r#"{{"name": "{name}", "extractor": "{extractor}", "tree": {dag}, "dag": {dag}, "micros": {micros}, "status": "ok"}}"#
+        )
+    };
+    write("suite/a-x.json", &result("a", "x", 2.0, 10));
+    write("suite/a-y.json", &result("a", "y", 4.0, 40));
+    write("suite/b-x.json", &result("b", "x", 3.0, 20));
+    write("suite/b-y.json", &result("b", "y", 3.0, 5));
+    write("suite/c-x.json", &result("c", "x", 1.0, 1));
+    // Neither a result nor a pair.
+    write("report.json", r#"{"extractor": "x", "results": []}"#);
+
+    let results = load_results(&[&dir]);
+    std::fs::remove_dir_all(&dir).unwrap();
+    assert_eq!(results.len(), 5);
+    let outcome = head_to_head(&results, "x", "y").unwrap();
+    assert_eq!((outcome.wins, outcome.ties, outcome.losses), (1, 1, 0));
+    let dag = outcome.dag.unwrap();
+    assert_eq!(dag.count, 2);
+    assert!((dag.geomean - 0.5f64.sqrt()).abs() < EPSILON_ALLOWANCE);
+    assert_eq!(dag.quantiles[0], 0.5);
+    assert_eq!(dag.quantiles[4], 1.0);
+    assert!(head_to_head(&results, "x", "z").is_none());
+}
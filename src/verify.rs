@@ -0,0 +1,232 @@
+/*
+Cross-checks the extractors that claim to be optimal for the dag cost, i.e. the ILP
+encodings, against an exhaustive search, on egraphs small enough to search.
+
+The search, see `exhaustive_dag_cost`, tries every choice of node for every class the
+roots need, pruning partial extractions that already cost as much as the best found,
+starting from the faster-greedy-dag extraction. It shares no code with the encodings,
+so when an encoding and the search disagree, the encoding is almost always the one
+that's wrong.
+
+Each egraph in the corpus with at most `--max-classes` classes, 20 by default, is
+extracted with every compiled-in extractor that's optimal for the dag cost. An
+extraction that's invalid, panics, or costs more or less than the search's optimum
+is reported, and the egraph is copied to `--out-dir`, prefixed with its suite, to
+debug it. Egraphs the search gives up on are skipped.
+
+Run with `extraction-gym verify-encodings [--max-classes <n>] [--max-steps <n>]
+[--out-dir <dir>] <dir>...`, which exits with 1 if anything disagreed. The encodings
+are only compiled in with the ilp-cbc and smt features.
+*/
+
+use crate::corpus::Corpus;
+use crate::*;
+use anyhow::Context;
+use rustc_hash::FxHashSet;
+use std::path::PathBuf;
+
+pub struct Disagreement {
+    pub extractor: &'static str,
+    pub optimal: Cost,
+    // None if the extraction was invalid or panicked.
+    pub dag: Option<Cost>,
+}
+
+// The least dag cost of any extraction of the roots, or None if the search took
+// more than `max_steps` steps.
+pub fn exhaustive_dag_cost(egraph: &EGraph, roots: &[ClassId], max_steps: usize) -> Option<Cost> {
+    let mut greedy = extract::faster_greedy_dag::FasterGreedyDagExtractor.extract(egraph, roots);
+    greedy.retain_reachable(egraph, roots);
+    let mut best = if greedy.find_cycles(egraph, roots).is_empty() {
+        greedy.dag_cost(egraph, roots)
+    } else {
+        INFINITY
+    };
+    let mut steps = 0;
+    let mut choices = IndexMap::new();
+    search(
+        egraph,
+        roots,
+        &mut choices,
+        Cost::default(),
+        &mut best,
+        &mut steps,
+        max_steps,
+    )
+    .then_some(best)
+}
+
+// False if it gave up.
+fn search(
+    egraph: &EGraph,
+    roots: &[ClassId],
+    choices: &mut IndexMap<ClassId, NodeId>,
+    cost: Cost,
+    best: &mut Cost,
+    steps: &mut usize,
+    max_steps: usize,
+) -> bool {
+    *steps += 1;
+    if *steps > max_steps {
+        return false;
+    }
+    if cost >= *best {
+        return true;
+    }
+
+    // The first class the roots need, given the choices so far, that has no choice yet.
+    let mut todo: Vec<&ClassId> = roots.iter().collect();
+    let mut visited = FxHashSet::<&ClassId>::default();
+    let mut pending = None;
+    while let Some(cid) = todo.pop() {
+        if !visited.insert(cid) {
+            continue;
+        }
+        match choices.get(cid) {
+            Some(nid) => todo.extend(egraph[nid].children.iter().map(|c| egraph.nid_to_cid(c))),
+            None => {
+                pending = Some(cid.clone());
+                break;
+            }
+        }
+    }
+
+    let Some(cid) = pending else {
+        let mut result = ExtractionResult::default();
+        for (cid, nid) in choices.iter() {
+            result.choose(cid.clone(), nid.clone());
+        }
+        if result.find_cycles(egraph, roots).is_empty() {
+            *best = cost;
+        }
+        return true;
+    };
+
+    for nid in &egraph[&cid].nodes {
+        choices.insert(cid.clone(), nid.clone());
+        let finished = search(
+            egraph,
+            roots,
+            choices,
+            cost + egraph[nid].cost,
+            best,
+            steps,
+            max_steps,
+        );
+        choices.pop();
+        if !finished {
+            return false;
+        }
+    }
+    true
+}
+
+// The extractions of `extractors` that don't cost `optimal`.
+pub fn verify_encodings(
+    extractors: &IndexMap<&'static str, ExtractorDetail>,
+    egraph: &EGraph,
+    optimal: Cost,
+) -> Vec<Disagreement> {
+    let roots = &egraph.root_eclasses;
+    extractors
+        .iter()
+        .filter_map(|(name, ed)| {
+            let dag = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+                let mut result = ed.extractor.extract(egraph, roots);
+                result.retain_reachable(egraph, roots);
+                result.check(egraph);
+                result.dag_cost(egraph, roots)
+            }))
+            .ok();
+            let agrees = dag.is_some_and(|dag| {
+                (dag.into_inner() - optimal.into_inner()).abs() <= EPSILON_ALLOWANCE
+            });
+            (!agrees).then_some(Disagreement {
+                extractor: *name,
+                optimal,
+                dag,
+            })
+        })
+        .collect()
+}
+
+pub fn verify_encodings_command(mut args: pico_args::Arguments) {
+    let lenient = args.contains("--lenient");
+    let max_classes: usize = args
+        .opt_value_from_str("--max-classes")
+        .unwrap()
+        .unwrap_or(20);
+    let max_steps: usize = args
+        .opt_value_from_str("--max-steps")
+        .unwrap()
+        .unwrap_or(1_000_000);
+    let out_dir: PathBuf = args
+        .opt_value_from_str("--out-dir")
+        .unwrap()
+        .unwrap_or_else(|| "disagreements".into());
+    let dirs: Vec<PathBuf> = args.finish().into_iter().map(PathBuf::from).collect();
+    if dirs.is_empty() {
+        panic!("No directories given");
+    }
+
+    let mut encodings = extractors();
+    encodings.retain(|_, ed| ed.optimal == Optimal::DAG);
+    if encodings.is_empty() {
+        panic!("verify-encodings needs an extractor that's optimal for the dag cost, e.g. with the ilp-cbc or smt feature");
+    }
+
+    let corpus = Corpus::discover(&dirs).with_max_classes(max_classes);
+    let (mut checked, mut skipped, mut disagreed) = (0, 0, 0);
+    println!("name\textractor\toptimal\tdag");
+    for entry in &corpus.entries {
+        let egraph = match corpus
+            .read(entry, lenient)
+            .with_context(|| format!("Failed to parse {}", entry.path.display()))
+            .unwrap()
+        {
+            Some(egraph) => egraph,
+            None => continue,
+        };
+        let Some(optimal) = exhaustive_dag_cost(&egraph, &egraph.root_eclasses, max_steps) else {
+            skipped += 1;
+            continue;
+        };
+        checked += 1;
+
+        let disagreements = verify_encodings(&encodings, &egraph, optimal);
+        if disagreements.is_empty() {
+            continue;
+        }
+        disagreed += 1;
+        for d in &disagreements {
+            let dag = d
+                .dag
+                .map_or_else(|| "invalid".to_string(), |dag| dag.to_string());
+            println!(
+                "{}\t{}\t{}\t{dag}",
+                entry.path.display(),
+                d.extractor,
+                d.optimal
+            );
+        }
+        std::fs::create_dir_all(&out_dir)
+            .with_context(|| format!("Failed to create {}", out_dir.display()))
+            .unwrap();
+        let saved = out_dir.join(format!(
+            "{}-{}",
+            entry.suite,
+            entry.path.file_name().unwrap().to_string_lossy()
+        ));
+        std::fs::copy(&entry.path, &saved)
+            .with_context(|| format!("Failed to save {}", saved.display()))
+            .unwrap();
+    }
+
+    println!();
+    println!(
+        "{checked} egraphs checked, {skipped} too big to search, {disagreed} with disagreements"
+    );
+    if disagreed > 0 {
+        std::process::exit(1);
+    }
+}